@@ -0,0 +1,53 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::patch::{Edits, PostProcessor};
+use super::time::Timestamp;
+use std::fmt::Write;
+
+/// Inserts a comment before the root element describing the changes.
+pub struct Annotate;
+
+impl PostProcessor for Annotate {
+    fn process(&mut self, edits: &mut Edits<'_, '_>) {
+        let changed = edits.summary.changes.len();
+        if changed == 0 {
+            return;
+        }
+        let noun = if changed == 1 {
+            "index"
+        } else {
+            "indices"
+        };
+        let mut comment = format!(
+            "<!-- Modified by {} {} on {}: {changed} parameter {noun} \
+             changed",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            Timestamp::now(),
+        );
+        let skipped = edits.summary.skipped.len();
+        if skipped > 0 {
+            write!(comment, ", {skipped} skipped").unwrap();
+        }
+        comment.push_str(" -->\n");
+        let start = edits.document.root_element().range().start;
+        edits.replace(start..start, comment);
+    }
+}
@@ -17,27 +17,260 @@
  * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
  */
 
+use super::Dirs;
+use super::backup::{self, Strategy as BackupStrategy};
+use super::color;
+use super::completions::Shell;
+use super::config::{self, Config};
+use super::diag::{self, Verbosity};
+use super::report::Format as ReportFormat;
 use std::borrow::Cow;
 use std::ffi::{OsStr, OsString};
 use std::fmt::{self, Display};
+use std::io::{self, Read};
 use std::ops::ControlFlow::{self, Break};
-use std::path::PathBuf;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The default value of `--settle-time`.
+const DEFAULT_SETTLE_TIME: Duration = Duration::from_secs(3);
+
+/// The environment variable that holds default options.
+const OPTIONS_VAR: &str = "FIX_ARDOUR_LV2_INDEX_OPTS";
 
 pub const USAGE: &str = "\
-[options] <session-file>
+[options] <session>...
+
+Fixes parameter indices in the .ardour file <session> and saves a backup
+of the original session in <session>.orig. If <session> is a session
+directory, every snapshot in the directory is fixed. If it is a zip
+archive of a session, the snapshots in the archive are fixed and the
+archive is repacked. Several .ardour files may be given to fix each of
+them.
+
+Arguments of the form @<file> are replaced with the arguments in <file>,
+which are separated by whitespace and may be enclosed in single or double
+quotes. A backslash escapes the next character, except in single quotes.
+Arguments of the form @@<file> are replaced with the lines of <file>, each
+taken as one argument as written (empty lines are ignored).
+
+The value of a long option may also be given as --option=value. An
+option without a value can be turned off with --no-<option>, and one
+named --no-<option> with --<option>, to override a default.
 
-Fixes parameter indices in the .ardour file <session-file> and saves
-a backup of the original session in <session-file>.orig.
+Default options can be set in the environment variable
+FIX_ARDOUR_LV2_INDEX_OPTS, which is split into arguments like an @<file>.
+Options given on the command line take precedence. Defaults can also be
+set in the configuration file (see --config), which has the lowest
+precedence. An option that can't be combined with another, like
+--port-map and --index-map, replaces the defaults for both. Defaults
+can't give sessions, choose where they're read from or written to,
+include --ignore-lock, or select another mode, like --help.
 
 Options:
-  -o <file>   Write to <file> instead of modifying the session in-place
-  -h, --help  Show this help message
+  -o, --output <file>
+                     Write to <file> instead of modifying the session
+                     in-place
+  --in-place         Modify the session in-place (the default unless it
+                     is read from standard input)
+  --stdin            Read the session from standard input and write it
+                     to standard output (the same as giving - as the
+                     session)
+  -O, --output-dir <dir>
+                     Write each session (or each snapshot of a session
+                     directory) to a file of the same name in <dir>
+                     instead of modifying it in-place
+  --suffix <suffix>  Write each session to <session><suffix> (such as
+                     Session.ardour.fixed) next to it instead of
+                     modifying it in-place
+  --files-from <file>
+                     Also fix the sessions listed in <file>, one per
+                     line, or in standard input if <file> is -
+  -0, --null         Separate the sessions listed with --files-from by
+                     NUL bytes instead of newlines, as printed by
+                     find -print0
+  --compress         Write the session gzip-compressed (sessions that
+                     were read compressed are always written compressed)
+  --snapshot <name>  Fix only the snapshot <name> of a session directory
+                     (may be given multiple times)
+  --include-backups  Also fix backups made by this program (<session>.orig
+                     and <session>.orig<n>, or named with --backup-suffix),
+                     which are otherwise refused and ignored in session
+                     directories
+  --settle-time <seconds>
+                     When fixing a session directory, defer snapshots
+                     modified within the last <seconds> seconds (default
+                     3), since Ardour may still be saving them; 0
+                     disables this
+  --dry-run          Show the changes that would be made without writing
+                     the session or creating a backup (prints a text
+                     report unless another report is written to
+                     standard output)
+  --check            Like --dry-run, but fail unless every parameter
+                     index already matches the installed plugins
+  --strict           Treat warnings (such as missing plugins or ports)
+                     as errors, writing nothing for affected sessions
+  --conservative     Only change indices of ports that were found, and
+                     skip parameters whose symbol is ambiguous within
+                     their processor, rather than assigning new indices
+                     to missing ports (other installed versions of a
+                     plugin are not consulted)
+  --max-change-ratio <ratio>
+                     Refuse to save a session if more than <ratio> (a
+                     number from 0 to 1) of its parameters would be
+                     changed, which may indicate the wrong plugin
+                     versions are installed
+  --verify           Before saving a session, parse the patched output
+                     again and check that it is well-formed and that
+                     every parameter has the expected index
+  --force            Save sessions even if --max-change-ratio is
+                     exceeded
+  --ignore-lock      Save sessions even if they appear to be open in
+                     Ardour (their .lock file exists); can't be a
+                     default
+  --skip-bypassed    Leave the parameters of bypassed (inactive) plugins
+                     unchanged
+  --plugin-name <pattern>
+                     Only fix plugins whose name contains <pattern>
+                     (case-insensitive; may contain * and ? wildcards,
+                     in which case the whole name must match)
+  --no-annotation    Don't add a comment summarizing the changes to the
+                     session
+  --index-map <file>
+                     Apply the index mapping in <file> instead of
+                     querying lilv
+  --export-index-map <file>
+                     Write the index mapping that was applied to <file>
+  --port-map <file>  Look up ports in <file>, an inventory saved with
+                     plugins-diff --save, instead of querying lilv
+  --backend <url>    Retrieve port metadata from the service at <url>
+                     instead of querying lilv
+  --simulate-missing <uri>
+                     Treat the plugin <uri> as not installed, to preview
+                     how the session would be fixed on a machine without
+                     it (may be given multiple times)
+  --refresh-plugin <uri>
+                     Discard cached information about the plugin <uri>,
+                     such as after updating it (may be given multiple
+                     times)
+  --no-cache         Don't read or write cached plugin information
+  --refresh-cache    Discard all cached plugin information, looking up
+                     each plugin again
+  --lv2-path <paths> Load plugins from the directories in <paths>
+                     (separated like LV2_PATH) instead of from LV2_PATH;
+                     if a plugin is in more than one, the first is used
+  --plugins-as-of <dir>
+                     Load plugins only from <dir>, such as an archived
+                     copy of the plugins installed when the session was
+                     created, instead of from LV2_PATH
+  -B, --bundle <dir> Also load the plugin bundle <dir>, such as a
+                     development build that isn't installed; its
+                     plugins take priority over installed ones (may be
+                     given multiple times)
+  --report <format>[=<file>]
+                     Write a report of the changes in <format> (text,
+                     json, csv, or html) to <file>, or to standard
+                     output if <file> is omitted or is - (may be given
+                     multiple times)
+  --no-pager         Don't show text reports written to a terminal in a
+                     pager
+  --backup-strategy <strategy>
+                     How to back up the session: reflink (default)
+                     copies it using a reflink where supported, copy
+                     always makes a regular copy, and rename moves it
+                     and writes the patched session to a new file
+  --backup-suffix <suffix>
+                     Name backups <session><suffix> instead of
+                     <session>.orig
+  --backup-dir <dir> Place backups in <dir> instead of next to the
+                     session
+  --backup <naming>  How to name backups: numbered (default) appends
+                     a number to later backups, and timestamp appends
+                     the time the backup was made
+  --keep-backups <n> Remove the oldest backups of each session beyond
+                     the newest <n>
+  --preserve-mtime   Keep the modification time of sessions fixed
+                     in-place
+  --config <file>    Read the configuration from <file> instead of
+                     config.toml in the XDG config directory
+  --state-dir <dir>  Store caches and other auxiliary files in <dir>
+                     instead of the XDG base directories
+  --warn-immediately Print warnings as they occur instead of grouping
+                     them by plugin at the end
+  -v, --verbose      Also print every resolved parameter, including those
+                     whose index is already correct
+  -q, --quiet        Print only errors, not warnings or other messages
+  --deny <code>      Treat warnings with the code <code> (such as
+                     missing-plugin; see README.md) as errors, writing
+                     nothing for affected sessions (may be given
+                     multiple times)
+  --allow <code>     Ignore warnings with the code <code> (may be given
+                     multiple times)
+  --max-warnings <n> Print at most <n> warnings of each kind, then how
+                     many more there were
+  --diagnostics <format>
+                     Print warnings and errors as text (default) or as
+                     json, one object per line with the severity, code,
+                     message, plugin uri, port symbol, and byte offset
+  --color <when>     Color output: auto (default) colors output to a
+                     terminal unless NO_COLOR is set, always, or never
+  --capabilities     Print a JSON description of the features supported
+                     by this build
+  -V, --version      Show the version of this program and of the lilv
+                     library it uses
+  -h, --help         Show this help message
+
+Subcommands:
+  merge-reports <report>...
+                     Combine JSON reports from several machines, listing
+                     for each plugin the machines it is available on and
+                     the sessions that use it. Each machine is named after
+                     its report file.
+  check-consistency <session>
+                     Check that every snapshot in the session directory
+                     <session> uses the same index for each port of each
+                     processor, listing any that differ
+  preflight [<option>...] <session>
+                     For scripts that launch Ardour: unless <session> was
+                     modified within the --settle-time, fix it
+                     conservatively with a backup, check the result, and
+                     print the outcome as one line of JSON (accepts the
+                     options above, and their defaults, except those
+                     that select the output)
+  plugins-diff [--since <old>] [--save <new>]
+                     Compare the ports of the installed plugins with the
+                     inventory <old> saved earlier, listing the plugins
+                     whose port indices changed, and/or save the current
+                     inventory to <new> (which may be the same file)
+  export-portmap [-o <file>] [--session <session>]... [<uri>...]
+                     Write the ports of the plugins <uri> and of those
+                     used by each <session> (a snapshot or session
+                     directory) to <file> or stdout, for use with
+                     --port-map on another machine
+  restore [--keep-patched <file>] <session>
+                     Move the newest backup of <session> back into
+                     place, first moving the current session to <file>
+                     if given (also accepts --backup-suffix and
+                     --backup-dir)
+  list-ports <uri>   Print the index, symbol, name, and type of each port
+                     of the installed plugin <uri>
+  doctor [<uri>...]  Show the lv2 search path, how many plugins lilv
+                     finds in it and how long that takes, and whether
+                     each plugin <uri> is installed or differs only
+                     slightly (in case, a trailing slash, or http vs
+                     https) from one that is
+  completions <shell>
+                     Print a completion script for <shell> (bash, zsh,
+                     or fish)
 ";
 
 #[derive(Debug)]
 pub enum Input {
     Stdin,
     Path(PathBuf),
+    /// Several session files.
+    Paths(Vec<PathBuf>),
 }
 
 #[derive(Debug)]
@@ -45,18 +278,97 @@ pub enum Output {
     InPlace,
     Stdout,
     Path(PathBuf),
+    /// Write each session to a file of the same name in a directory.
+    Dir(PathBuf),
+    /// Write each session to a file named with a suffix appended.
+    Suffix(OsString),
+}
+
+#[derive(Debug)]
+pub struct ReportArg {
+    pub format: ReportFormat,
+    /// The file to write the report to, or [`None`] for standard output.
+    pub path: Option<PathBuf>,
 }
 
 #[derive(Debug)]
 pub struct RunArgs {
     pub input: Input,
     pub output: Output,
+    pub snapshots: Vec<OsString>,
+    pub settle_time: Duration,
+    pub include_backups: bool,
+    pub index_map: Option<PathBuf>,
+    pub export_index_map: Option<PathBuf>,
+    pub port_map: Option<PathBuf>,
+    pub backend: Option<OsString>,
+    pub lv2_path: Option<OsString>,
+    pub plugins_as_of: Option<PathBuf>,
+    pub simulate_missing: Vec<String>,
+    pub refresh_plugins: Vec<String>,
+    pub state_dir: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub reports: Vec<ReportArg>,
+    pub backup: backup::Options,
+    pub max_change_ratio: Option<f64>,
+    pub force: bool,
+    pub ignore_lock: bool,
+    pub dry_run: bool,
+    pub check: bool,
+    pub strict: bool,
+    pub verify: bool,
+    pub conservative: bool,
+    pub skip_bypassed: bool,
+    pub plugin_name: Option<String>,
+    pub no_annotation: bool,
+    pub warn_immediately: bool,
+    pub verbosity: Verbosity,
+    pub diagnostics: diag::Format,
+    /// How warnings are treated, by `--deny` and `--allow`, in order.
+    pub lints: Vec<(diag::Kind, diag::Lint)>,
+    pub max_warnings: Option<usize>,
+    pub color: color::Choice,
+    pub no_pager: bool,
+    pub no_cache: bool,
+    pub refresh_cache: bool,
+    pub bundles: Vec<PathBuf>,
+    pub compress: bool,
+    pub preserve_mtime: bool,
 }
 
 #[derive(Debug)]
 pub enum Args {
     Help,
-    Run(RunArgs),
+    Version,
+    Capabilities,
+    /// Print the completion script for a shell.
+    Completions(Shell),
+    /// Print the ports of the plugin with this URI.
+    ListPorts(String),
+    /// Diagnose why plugins can't be found, optionally these URIs.
+    Doctor(Vec<String>),
+    MergeReports(Vec<PathBuf>),
+    CheckConsistency(PathBuf),
+    /// Fix the session in `input` for a script that launches Ardour.
+    Preflight(Box<RunArgs>),
+    PluginsDiff {
+        since: Option<PathBuf>,
+        save: Option<PathBuf>,
+    },
+    ExportPortMap {
+        uris: Vec<String>,
+        sessions: Vec<PathBuf>,
+        output: Option<PathBuf>,
+    },
+    Restore {
+        session: PathBuf,
+        keep_patched: Option<PathBuf>,
+        backup: backup::Options,
+    },
+    /// Patch the session XML given as an argument (undocumented; for test
+    /// harnesses and scripts).
+    PatchString(OsString),
+    Run(Box<RunArgs>),
 }
 
 #[derive(Debug)]
@@ -67,6 +379,22 @@ pub enum ArgsError {
     BadShortOption(char),
     IncompleteOption(&'static str),
     DuplicateOption(&'static str),
+    /// A value was given with `--option=value` to an option without one.
+    UnexpectedValue(OsString),
+    /// Two options (or an option and an argument) that can't be combined.
+    Conflict(&'static str, &'static str),
+    InvalidValue(&'static str, OsString),
+    ArgFile(PathBuf, io::Error),
+    ArgFileSyntax(PathBuf),
+    /// The default options in the environment couldn't be split.
+    OptionsVarSyntax,
+    Config(PathBuf, config::Error),
+    /// An invalid default option in the configuration file.
+    ConfigDefault(PathBuf, Box<ArgsError>),
+    /// An invalid default option in the environment.
+    OptionsVar(Box<ArgsError>),
+    /// An option that selects sessions or another mode, given as a default.
+    NotDefault(&'static str),
 }
 
 impl Display for ArgsError {
@@ -84,6 +412,37 @@ impl Display for ArgsError {
                 write!(f, "missing argument for option {s}")
             }
             Self::DuplicateOption(s) => write!(f, "duplicate option: {s}"),
+            Self::UnexpectedValue(s) => write!(
+                f,
+                "option doesn't take a value: {}",
+                s.to_string_lossy(),
+            ),
+            Self::Conflict(a, b) => write!(f, "{a} cannot be used with {b}"),
+            Self::InvalidValue(s, v) => {
+                write!(f, "invalid value for {s}: {}", v.to_string_lossy())
+            }
+            Self::ArgFile(p, e) => {
+                write!(f, "could not read {}: {e}", p.display())
+            }
+            Self::ArgFileSyntax(p) => write!(
+                f,
+                "unterminated quote or trailing backslash in {}",
+                p.display(),
+            ),
+            Self::OptionsVarSyntax => write!(
+                f,
+                "unterminated quote or trailing backslash in ${OPTIONS_VAR}",
+            ),
+            Self::Config(p, e) => {
+                write!(f, "could not load {}: {e}", p.display())
+            }
+            Self::ConfigDefault(p, e) => {
+                write!(f, "{e} (in defaults in {})", p.display())
+            }
+            Self::OptionsVar(e) => write!(f, "{e} (in ${OPTIONS_VAR})"),
+            Self::NotDefault(s) => {
+                write!(f, "{s} cannot be used as a default option")
+            }
         }
     }
 }
@@ -91,11 +450,124 @@ impl Display for ArgsError {
 struct Parser<A> {
     args: A,
     options_done: bool,
+    /// The value of the current option, if it was given as `--option=value`.
+    inline_value: Option<OsString>,
+    /// Whether the options are defaults, from the environment or the
+    /// configuration file, which can't select sessions or another mode.
+    defaults: bool,
     input: Option<Input>,
-    output: Output,
+    output: Option<Output>,
+    files_from: Option<PathBuf>,
+    null: bool,
+    snapshots: Vec<OsString>,
+    settle_time: Option<Duration>,
+    include_backups: Option<bool>,
+    index_map: Option<PathBuf>,
+    export_index_map: Option<PathBuf>,
+    port_map: Option<PathBuf>,
+    backend: Option<OsString>,
+    lv2_path: Option<OsString>,
+    plugins_as_of: Option<PathBuf>,
+    simulate_missing: Vec<String>,
+    refresh_plugins: Vec<String>,
+    state_dir: Option<PathBuf>,
+    config: Option<PathBuf>,
+    reports: Vec<ReportArg>,
+    backup_strategy: Option<BackupStrategy>,
+    backup_suffix: Option<String>,
+    backup_dir: Option<PathBuf>,
+    backup_naming: Option<backup::Naming>,
+    keep_backups: Option<usize>,
+    max_change_ratio: Option<f64>,
+    force: Option<bool>,
+    ignore_lock: bool,
+    dry_run: Option<bool>,
+    check: Option<bool>,
+    strict: Option<bool>,
+    verify: Option<bool>,
+    conservative: Option<bool>,
+    skip_bypassed: Option<bool>,
+    plugin_name: Option<String>,
+    no_annotation: Option<bool>,
+    warn_immediately: Option<bool>,
+    verbosity: Verbosity,
+    diagnostics: Option<diag::Format>,
+    lints: Vec<(diag::Kind, diag::Lint)>,
+    max_warnings: Option<usize>,
+    color: Option<color::Choice>,
+    no_pager: Option<bool>,
+    no_cache: Option<bool>,
+    refresh_cache: Option<bool>,
+    bundles: Vec<PathBuf>,
+    compress: Option<bool>,
+    preserve_mtime: Option<bool>,
 }
 
 impl<A: Iterator<Item = OsString>> Parser<A> {
+    fn new(args: A) -> Self {
+        Self {
+            args,
+            options_done: false,
+            inline_value: None,
+            defaults: false,
+            input: None,
+            output: None,
+            files_from: None,
+            null: false,
+            snapshots: Vec::new(),
+            settle_time: None,
+            include_backups: None,
+            index_map: None,
+            export_index_map: None,
+            port_map: None,
+            backend: None,
+            lv2_path: None,
+            plugins_as_of: None,
+            simulate_missing: Vec::new(),
+            refresh_plugins: Vec::new(),
+            state_dir: None,
+            config: None,
+            reports: Vec::new(),
+            backup_strategy: None,
+            backup_suffix: None,
+            backup_dir: None,
+            backup_naming: None,
+            keep_backups: None,
+            max_change_ratio: None,
+            force: None,
+            ignore_lock: false,
+            dry_run: None,
+            check: None,
+            strict: None,
+            verify: None,
+            conservative: None,
+            skip_bypassed: None,
+            plugin_name: None,
+            no_annotation: None,
+            warn_immediately: None,
+            verbosity: Verbosity::Normal,
+            diagnostics: None,
+            lints: Vec::new(),
+            max_warnings: None,
+            color: None,
+            no_pager: None,
+            no_cache: None,
+            refresh_cache: None,
+            bundles: Vec::new(),
+            compress: None,
+            preserve_mtime: None,
+        }
+    }
+
+    /// Creates a parser of default options, from the environment or the
+    /// configuration file.
+    fn defaults(args: A) -> Self {
+        Self {
+            defaults: true,
+            ..Self::new(args)
+        }
+    }
+
     fn rest_or_next<'a>(&mut self, rest: &'a OsStr) -> Option<Cow<'a, OsStr>> {
         if rest.is_empty() {
             self.args.next().map(Into::into)
@@ -110,33 +582,446 @@ impl<A: Iterator<Item = OsString>> Parser<A> {
         rest: &OsStr,
     ) -> Result<ControlFlow<Option<Args>>, ArgsError> {
         match opt {
-            'h' => Ok(Break(Some(Args::Help))),
+            'h' => {
+                self.not_default("-h")?;
+                Ok(Break(Some(Args::Help)))
+            }
+            'V' => {
+                self.not_default("-V")?;
+                Ok(Break(Some(Args::Version)))
+            }
             'o' => {
-                if !matches!(self.output, Output::InPlace) {
-                    return Err(ArgsError::DuplicateOption("-o"));
-                }
                 let Some(next) = self.rest_or_next(rest) else {
                     return Err(ArgsError::IncompleteOption("-o"));
                 };
-                self.output = match next.as_encoded_bytes() {
+                let output = match next.as_encoded_bytes() {
                     b"-" => Output::Stdout,
                     _ => Output::Path(next.into_owned().into()),
                 };
+                self.output("-o", output)?;
+                Ok(Break(None))
+            }
+            'O' => {
+                let Some(next) = self.rest_or_next(rest) else {
+                    return Err(ArgsError::IncompleteOption("-O"));
+                };
+                self.output("-O", Output::Dir(next.into_owned().into()))?;
+                Ok(Break(None))
+            }
+            'v' => {
+                self.verbosity = Verbosity::Verbose;
+                Ok(ControlFlow::Continue(()))
+            }
+            'q' => {
+                self.verbosity = Verbosity::Quiet;
+                Ok(ControlFlow::Continue(()))
+            }
+            '0' => {
+                self.not_default("-0")?;
+                self.null = true;
+                Ok(ControlFlow::Continue(()))
+            }
+            'B' => {
+                let Some(next) = self.rest_or_next(rest) else {
+                    return Err(ArgsError::IncompleteOption("-B"));
+                };
+                self.bundles.push(next.into_owned().into());
                 Ok(Break(None))
             }
             _ => Err(ArgsError::BadShortOption(opt)),
         }
     }
 
+    fn value(&mut self, opt: &'static str) -> Result<OsString, ArgsError> {
+        self.inline_value
+            .take()
+            .or_else(|| self.args.next())
+            .ok_or(ArgsError::IncompleteOption(opt))
+    }
+
+    fn path(
+        &mut self,
+        opt: &'static str,
+        get: fn(&mut Self) -> &mut Option<PathBuf>,
+    ) -> Result<(), ArgsError> {
+        if get(self).is_some() {
+            return Err(ArgsError::DuplicateOption(opt));
+        }
+        let value = self.value(opt)?;
+        *get(self) = Some(value.into());
+        Ok(())
+    }
+
+    fn lint(
+        &mut self,
+        opt: &'static str,
+        lint: diag::Lint,
+    ) -> Result<(), ArgsError> {
+        let value = self.value(opt)?;
+        let Some(kind) = diag::Kind::from_name(value.as_encoded_bytes())
+        else {
+            return Err(ArgsError::InvalidValue(opt, value));
+        };
+        self.lints.push((kind, lint));
+        Ok(())
+    }
+
+    /// Sets the output to `output`, given with the option `opt`.
+    fn output(
+        &mut self,
+        opt: &'static str,
+        output: Output,
+    ) -> Result<(), ArgsError> {
+        self.not_default(opt)?;
+        if self.output.is_some() {
+            return Err(ArgsError::DuplicateOption(opt));
+        }
+        self.output = Some(output);
+        Ok(())
+    }
+
+    fn long(&mut self, arg: OsString) -> Result<Option<Args>, ArgsError> {
+        match arg.as_encoded_bytes() {
+            b"--help" => {
+                self.not_default("--help")?;
+                return Ok(Some(Args::Help));
+            }
+            b"--version" => {
+                self.not_default("--version")?;
+                return Ok(Some(Args::Version));
+            }
+            b"--capabilities" => {
+                self.not_default("--capabilities")?;
+                return Ok(Some(Args::Capabilities));
+            }
+            b"--snapshot" => {
+                let name = self.value("--snapshot")?;
+                self.snapshots.push(name);
+            }
+            b"--output" => {
+                let value = self.value("--output")?;
+                let output = match value.as_encoded_bytes() {
+                    b"-" => Output::Stdout,
+                    _ => Output::Path(value.into()),
+                };
+                self.output("--output", output)?;
+            }
+            b"--in-place" => self.output("--in-place", Output::InPlace)?,
+            b"--stdin" => {
+                self.not_default("--stdin")?;
+                self.input("-".into(), true)?;
+            }
+            b"--output-dir" => {
+                let dir = self.value("--output-dir")?;
+                self.output("--output-dir", Output::Dir(dir.into()))?;
+            }
+            b"--suffix" => {
+                let suffix = self.value("--suffix")?;
+                let bytes = suffix.as_encoded_bytes();
+                if bytes.is_empty() || bytes.contains(&b'/') {
+                    return Err(ArgsError::InvalidValue("--suffix", suffix));
+                }
+                self.output("--suffix", Output::Suffix(suffix))?;
+            }
+            b"--files-from" => {
+                self.not_default("--files-from")?;
+                self.path("--files-from", |s| &mut s.files_from)?;
+            }
+            b"--null" => {
+                self.not_default("--null")?;
+                self.null = true;
+            }
+            b"--ignore-lock" => {
+                self.not_default("--ignore-lock")?;
+                self.ignore_lock = true;
+            }
+            b"--settle-time" => {
+                if self.settle_time.is_some() {
+                    return Err(ArgsError::DuplicateOption("--settle-time"));
+                }
+                let value = self.value("--settle-time")?;
+                let Some(secs) = value.to_str().and_then(|s| s.parse().ok())
+                else {
+                    return Err(ArgsError::InvalidValue(
+                        "--settle-time",
+                        value,
+                    ));
+                };
+                self.settle_time = Some(Duration::from_secs(secs));
+            }
+            b"--max-change-ratio" => {
+                if self.max_change_ratio.is_some() {
+                    return Err(ArgsError::DuplicateOption(
+                        "--max-change-ratio",
+                    ));
+                }
+                let value = self.value("--max-change-ratio")?;
+                let Some(ratio) = value
+                    .to_str()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|r| (0.0..=1.0).contains(r))
+                else {
+                    return Err(ArgsError::InvalidValue(
+                        "--max-change-ratio",
+                        value,
+                    ));
+                };
+                self.max_change_ratio = Some(ratio);
+            }
+            b"--plugin-name" => {
+                if self.plugin_name.is_some() {
+                    return Err(ArgsError::DuplicateOption("--plugin-name"));
+                }
+                let value = self.value("--plugin-name")?;
+                match value.into_string() {
+                    Ok(s) => self.plugin_name = Some(s),
+                    Err(value) => {
+                        return Err(ArgsError::InvalidValue(
+                            "--plugin-name",
+                            value,
+                        ));
+                    }
+                }
+            }
+            b"--verbose" => self.verbosity = Verbosity::Verbose,
+            b"--quiet" => self.verbosity = Verbosity::Quiet,
+            b"--deny" => self.lint("--deny", diag::Lint::Deny)?,
+            b"--allow" => self.lint("--allow", diag::Lint::Allow)?,
+            b"--max-warnings" => {
+                if self.max_warnings.is_some() {
+                    return Err(ArgsError::DuplicateOption("--max-warnings"));
+                }
+                let value = self.value("--max-warnings")?;
+                let Some(n) = value.to_str().and_then(|s| s.parse().ok())
+                else {
+                    return Err(ArgsError::InvalidValue(
+                        "--max-warnings",
+                        value,
+                    ));
+                };
+                self.max_warnings = Some(n);
+            }
+            b"--diagnostics" => {
+                if self.diagnostics.is_some() {
+                    return Err(ArgsError::DuplicateOption("--diagnostics"));
+                }
+                let value = self.value("--diagnostics")?;
+                let Some(format) =
+                    diag::Format::from_name(value.as_encoded_bytes())
+                else {
+                    return Err(ArgsError::InvalidValue(
+                        "--diagnostics",
+                        value,
+                    ));
+                };
+                self.diagnostics = Some(format);
+            }
+            b"--color" => {
+                if self.color.is_some() {
+                    return Err(ArgsError::DuplicateOption("--color"));
+                }
+                let value = self.value("--color")?;
+                let Some(choice) =
+                    color::Choice::from_name(value.as_encoded_bytes())
+                else {
+                    return Err(ArgsError::InvalidValue("--color", value));
+                };
+                self.color = Some(choice);
+            }
+            b"--bundle" => {
+                let bundle = self.value("--bundle")?;
+                self.bundles.push(bundle.into());
+            }
+            b"--index-map" => {
+                self.path("--index-map", |s| &mut s.index_map)?;
+            }
+            b"--export-index-map" => {
+                self.path("--export-index-map", |s| &mut s.export_index_map)?;
+            }
+            b"--port-map" => {
+                self.path("--port-map", |s| &mut s.port_map)?;
+            }
+            b"--backend" => {
+                if self.backend.is_some() {
+                    return Err(ArgsError::DuplicateOption("--backend"));
+                }
+                self.backend = Some(self.value("--backend")?);
+            }
+            b"--report" => {
+                let value = self.value("--report")?;
+                let bytes = value.as_encoded_bytes();
+                let (name, path) = match bytes.iter().position(|&b| b == b'=')
+                {
+                    // SAFETY: `bytes[i + 1..]` starts immediately after an
+                    // ASCII byte and ends at the end of `value`, a valid
+                    // `OsStr`.
+                    Some(i) => (
+                        &bytes[..i],
+                        Some(unsafe {
+                            OsStr::from_encoded_bytes_unchecked(
+                                &bytes[i + 1..],
+                            )
+                        }),
+                    ),
+                    None => (bytes, None),
+                };
+                let Some(format) = ReportFormat::from_name(name) else {
+                    return Err(ArgsError::InvalidValue("--report", value));
+                };
+                let path = path
+                    .filter(|p| p.as_encoded_bytes() != b"-")
+                    .map(PathBuf::from);
+                self.reports.push(ReportArg {
+                    format,
+                    path,
+                });
+            }
+            b"--backup-strategy" => {
+                if self.backup_strategy.is_some() {
+                    return Err(ArgsError::DuplicateOption(
+                        "--backup-strategy",
+                    ));
+                }
+                let value = self.value("--backup-strategy")?;
+                let Some(strategy) =
+                    BackupStrategy::from_name(value.as_encoded_bytes())
+                else {
+                    return Err(ArgsError::InvalidValue(
+                        "--backup-strategy",
+                        value,
+                    ));
+                };
+                self.backup_strategy = Some(strategy);
+            }
+            b"--backup-suffix" => {
+                if self.backup_suffix.is_some() {
+                    return Err(ArgsError::DuplicateOption("--backup-suffix"));
+                }
+                let value = self.value("--backup-suffix")?;
+                let Some(suffix) = value.to_str().filter(|s| valid_suffix(s))
+                else {
+                    return Err(ArgsError::InvalidValue(
+                        "--backup-suffix",
+                        value,
+                    ));
+                };
+                self.backup_suffix = Some(suffix.to_owned());
+            }
+            b"--backup-dir" => {
+                self.path("--backup-dir", |s| &mut s.backup_dir)?;
+            }
+            b"--backup" => {
+                if self.backup_naming.is_some() {
+                    return Err(ArgsError::DuplicateOption("--backup"));
+                }
+                let value = self.value("--backup")?;
+                let Some(naming) =
+                    backup::Naming::from_name(value.as_encoded_bytes())
+                else {
+                    return Err(ArgsError::InvalidValue("--backup", value));
+                };
+                self.backup_naming = Some(naming);
+            }
+            b"--keep-backups" => {
+                if self.keep_backups.is_some() {
+                    return Err(ArgsError::DuplicateOption("--keep-backups"));
+                }
+                let value = self.value("--keep-backups")?;
+                let Some(n) = value
+                    .to_str()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n| n > 0)
+                else {
+                    return Err(ArgsError::InvalidValue(
+                        "--keep-backups",
+                        value,
+                    ));
+                };
+                self.keep_backups = Some(n);
+            }
+            b"--simulate-missing" => {
+                let uri = self.value("--simulate-missing")?;
+                let uri = uri.into_string().map_err(|uri| {
+                    ArgsError::InvalidValue("--simulate-missing", uri)
+                })?;
+                self.simulate_missing.push(uri);
+            }
+            b"--refresh-plugin" => {
+                let uri = self.value("--refresh-plugin")?;
+                let uri = uri.into_string().map_err(|uri| {
+                    ArgsError::InvalidValue("--refresh-plugin", uri)
+                })?;
+                self.refresh_plugins.push(uri);
+            }
+            b"--lv2-path" => {
+                if self.lv2_path.is_some() {
+                    return Err(ArgsError::DuplicateOption("--lv2-path"));
+                }
+                self.lv2_path = Some(self.value("--lv2-path")?);
+            }
+            b"--plugins-as-of" => {
+                self.path("--plugins-as-of", |s| &mut s.plugins_as_of)?;
+            }
+            b"--state-dir" => {
+                self.path("--state-dir", |s| &mut s.state_dir)?;
+            }
+            b"--config" => {
+                self.path("--config", |s| &mut s.config)?;
+            }
+            _ => {
+                let Some((flag, value)) = self.flag(arg.as_encoded_bytes())
+                else {
+                    return Err(ArgsError::BadOption(arg));
+                };
+                *flag = Some(value);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the field of the boolean option `name` and the value `name`
+    /// gives it. Each can be given as `--<option>` or `--no-<option>`, so
+    /// that a default can be overridden; the fields of options named
+    /// `--no-<option>` are true when they're given that way.
+    fn flag(&mut self, name: &[u8]) -> Option<(&mut Option<bool>, bool)> {
+        let (name, on) = match name.strip_prefix(b"--no-") {
+            Some(name) => (name, false),
+            None => (name.strip_prefix(b"--")?, true),
+        };
+        let (field, negated) = match name {
+            b"include-backups" => (&mut self.include_backups, false),
+            b"force" => (&mut self.force, false),
+            b"dry-run" => (&mut self.dry_run, false),
+            b"check" => (&mut self.check, false),
+            b"strict" => (&mut self.strict, false),
+            b"verify" => (&mut self.verify, false),
+            b"conservative" => (&mut self.conservative, false),
+            b"skip-bypassed" => (&mut self.skip_bypassed, false),
+            b"annotation" => (&mut self.no_annotation, true),
+            b"warn-immediately" => (&mut self.warn_immediately, false),
+            b"pager" => (&mut self.no_pager, true),
+            b"cache" => (&mut self.no_cache, true),
+            b"refresh-cache" => (&mut self.refresh_cache, false),
+            b"compress" => (&mut self.compress, false),
+            b"preserve-mtime" => (&mut self.preserve_mtime, false),
+            _ => return None,
+        };
+        Some((field, on != negated))
+    }
+
     fn arg(&mut self, arg: OsString) -> Result<Option<Args>, ArgsError> {
         let bytes = arg.as_encoded_bytes();
         if self.options_done || arg == "-" {
         } else if arg == "--" {
             self.options_done = true;
-        } else if arg == "--help" {
-            return Ok(Some(Args::Help));
         } else if bytes.starts_with(b"--") {
-            return Err(ArgsError::BadOption(arg));
+            let (name, value) = split_long(&arg);
+            self.inline_value = value;
+            let args = self.long(name)?;
+            if self.inline_value.take().is_some() {
+                return Err(ArgsError::UnexpectedValue(arg));
+            }
+            return Ok(args);
         } else if let Some(mut opts) = bytes.strip_prefix(b"-") {
             while let Some((&opt, rest)) = opts.split_first() {
                 opts = rest;
@@ -156,45 +1041,812 @@ impl<A: Iterator<Item = OsString>> Parser<A> {
             }
             return Ok(None);
         }
-        if self.input.is_some() {
+        let stdin = bytes == b"-" && !self.options_done;
+        self.input(arg, stdin)?;
+        Ok(None)
+    }
+
+    /// Fails if the options are defaults, which can't include `opt`.
+    fn not_default(&self, opt: &'static str) -> Result<(), ArgsError> {
+        if self.defaults {
+            return Err(ArgsError::NotDefault(opt));
+        }
+        Ok(())
+    }
+
+    /// Adds the session `arg`, which is standard input if `stdin` is true.
+    fn input(&mut self, arg: OsString, stdin: bool) -> Result<(), ArgsError> {
+        if self.defaults {
             return Err(ArgsError::UnexpectedArg(arg));
         }
-        self.input = Some(match bytes {
-            b"-" if !self.options_done => Input::Stdin,
-            _ => Input::Path(arg.into()),
+        self.input = Some(match self.input.take() {
+            None if stdin => Input::Stdin,
+            None => Input::Path(arg.into()),
+            Some(Input::Path(first)) if !stdin => {
+                Input::Paths(vec![first, arg.into()])
+            }
+            Some(Input::Paths(mut paths)) if !stdin => {
+                paths.push(arg.into());
+                Input::Paths(paths)
+            }
+            Some(_) => return Err(ArgsError::UnexpectedArg(arg)),
         });
-        Ok(None)
+        Ok(())
     }
 
-    fn parse(mut self) -> Result<Args, ArgsError> {
+    /// Parses every argument. If one of them selects a different mode (like
+    /// `--help`), that mode is returned immediately.
+    fn read(&mut self) -> Result<Option<Args>, ArgsError> {
         while let Some(arg) = self.args.next() {
             if let Some(args) = self.arg(arg)? {
-                return Ok(args);
+                return Ok(Some(args));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Uses the options in `defaults` wherever they weren't given to this
+    /// parser. Options that can be repeated are combined, with those in
+    /// `defaults` first.
+    fn merge_defaults<B>(&mut self, defaults: Parser<B>) {
+        let Parser {
+            args: _,
+            options_done: _,
+            inline_value: _,
+            defaults: _,
+            // Rejected in defaults.
+            input: _,
+            output: _,
+            files_from: _,
+            null: _,
+            ignore_lock: _,
+            snapshots,
+            settle_time,
+            include_backups,
+            index_map,
+            export_index_map,
+            port_map,
+            backend,
+            lv2_path,
+            plugins_as_of,
+            simulate_missing,
+            refresh_plugins,
+            state_dir,
+            config,
+            reports,
+            backup_strategy,
+            backup_suffix,
+            backup_dir,
+            backup_naming,
+            keep_backups,
+            max_change_ratio,
+            force,
+            dry_run,
+            check,
+            strict,
+            verify,
+            conservative,
+            skip_bypassed,
+            plugin_name,
+            no_annotation,
+            warn_immediately,
+            verbosity,
+            diagnostics,
+            lints,
+            max_warnings,
+            color,
+            no_pager,
+            no_cache,
+            refresh_cache,
+            bundles,
+            compress,
+            preserve_mtime,
+        } = defaults;
+
+        fn prepend<T>(list: &mut Vec<T>, mut defaults: Vec<T>) {
+            defaults.append(list);
+            *list = defaults;
+        }
+
+        prepend(&mut self.snapshots, snapshots);
+        self.settle_time = self.settle_time.or(settle_time);
+        self.include_backups = self.include_backups.or(include_backups);
+        // The options in each of these groups can't be combined, so if one
+        // of them was given, the defaults for the others are ignored.
+        if self.index_map.is_none()
+            && self.port_map.is_none()
+            && self.backend.is_none()
+        {
+            self.index_map = index_map;
+            self.port_map = port_map;
+            self.backend = backend;
+        }
+        if self.lv2_path.is_none() && self.plugins_as_of.is_none() {
+            self.lv2_path = lv2_path;
+            self.plugins_as_of = plugins_as_of;
+        }
+        if self.no_cache.is_none() && self.refresh_cache.is_none() {
+            self.no_cache = no_cache;
+            self.refresh_cache = refresh_cache;
+        }
+        self.export_index_map =
+            self.export_index_map.take().or(export_index_map);
+        prepend(&mut self.simulate_missing, simulate_missing);
+        prepend(&mut self.refresh_plugins, refresh_plugins);
+        self.state_dir = self.state_dir.take().or(state_dir);
+        self.config = self.config.take().or(config);
+        prepend(&mut self.reports, reports);
+        self.backup_strategy = self.backup_strategy.or(backup_strategy);
+        self.backup_suffix = self.backup_suffix.take().or(backup_suffix);
+        self.backup_dir = self.backup_dir.take().or(backup_dir);
+        self.backup_naming = self.backup_naming.or(backup_naming);
+        self.keep_backups = self.keep_backups.or(keep_backups);
+        self.max_change_ratio = self.max_change_ratio.or(max_change_ratio);
+        self.force = self.force.or(force);
+        self.dry_run = self.dry_run.or(dry_run);
+        self.check = self.check.or(check);
+        self.strict = self.strict.or(strict);
+        self.verify = self.verify.or(verify);
+        self.conservative = self.conservative.or(conservative);
+        self.skip_bypassed = self.skip_bypassed.or(skip_bypassed);
+        self.plugin_name = self.plugin_name.take().or(plugin_name);
+        self.no_annotation = self.no_annotation.or(no_annotation);
+        self.warn_immediately = self.warn_immediately.or(warn_immediately);
+        if self.verbosity == Verbosity::Normal {
+            self.verbosity = verbosity;
+        }
+        self.diagnostics = self.diagnostics.or(diagnostics);
+        prepend(&mut self.lints, lints);
+        self.max_warnings = self.max_warnings.or(max_warnings);
+        self.color = self.color.or(color);
+        self.no_pager = self.no_pager.or(no_pager);
+        prepend(&mut self.bundles, bundles);
+        self.compress = self.compress.or(compress);
+        self.preserve_mtime = self.preserve_mtime.or(preserve_mtime);
+    }
+
+    /// Gets the path of the configuration file and the default options in
+    /// it, if it exists.
+    fn config_defaults(
+        &self,
+    ) -> Result<Option<(PathBuf, Vec<OsString>)>, ArgsError> {
+        let dirs = Dirs::new(self.state_dir.as_deref());
+        let path = match (&self.config, dirs.config()) {
+            (Some(path), _) => path.clone(),
+            (None, Some(dir)) => dir.join(config::FILE_NAME),
+            (None, None) => return Ok(None),
+        };
+        let config = match Config::load(&path) {
+            Ok(config) => config,
+            Err(config::Error::Io(e))
+                if self.config.is_none()
+                    && e.kind() == io::ErrorKind::NotFound =>
+            {
+                return Ok(None);
+            }
+            Err(e) => return Err(ArgsError::Config(path, e)),
+        };
+        let args = config.defaults.into_iter().map(OsString::from).collect();
+        Ok(Some((path, args)))
+    }
+
+    fn finish(mut self) -> Result<Args, ArgsError> {
+        if let Some(path) = self.files_from.take() {
+            for session in read_file_list(&path, self.null)? {
+                self.input(session, false)?;
             }
         }
         let Some(input) = self.input else {
             return Err(ArgsError::MissingArg);
         };
+        let check = self.check.unwrap_or_default();
         let output = match (&input, self.output) {
-            (Input::Stdin, Output::InPlace) => Output::Stdout,
-            (_, out) => out,
+            (Input::Stdin, None) => Output::Stdout,
+            (Input::Stdin, Some(Output::InPlace)) => {
+                return Err(ArgsError::Conflict("--in-place", "--stdin"));
+            }
+            (_, out) => out.unwrap_or(Output::InPlace),
         };
-        Ok(Args::Run(RunArgs {
+        Ok(Args::Run(Box::new(RunArgs {
             input,
             output,
-        }))
+            snapshots: self.snapshots,
+            settle_time: self.settle_time.unwrap_or(DEFAULT_SETTLE_TIME),
+            include_backups: self.include_backups.unwrap_or_default(),
+            index_map: self.index_map,
+            export_index_map: self.export_index_map,
+            port_map: self.port_map,
+            backend: self.backend,
+            lv2_path: self.lv2_path,
+            plugins_as_of: self.plugins_as_of,
+            simulate_missing: self.simulate_missing,
+            refresh_plugins: self.refresh_plugins,
+            state_dir: self.state_dir,
+            config: self.config,
+            reports: self.reports,
+            backup: backup::Options {
+                strategy: self.backup_strategy.unwrap_or_default(),
+                suffix: self
+                    .backup_suffix
+                    .unwrap_or_else(|| backup::DEFAULT_SUFFIX.to_owned()),
+                dir: self.backup_dir,
+                naming: self.backup_naming.unwrap_or_default(),
+                keep: self.keep_backups,
+            },
+            max_change_ratio: self.max_change_ratio,
+            force: self.force.unwrap_or_default(),
+            ignore_lock: self.ignore_lock,
+            dry_run: self.dry_run.unwrap_or_default() || check,
+            check,
+            strict: self.strict.unwrap_or_default(),
+            verify: self.verify.unwrap_or_default(),
+            conservative: self.conservative.unwrap_or_default(),
+            skip_bypassed: self.skip_bypassed.unwrap_or_default(),
+            plugin_name: self.plugin_name,
+            no_annotation: self.no_annotation.unwrap_or_default(),
+            warn_immediately: self.warn_immediately.unwrap_or_default(),
+            verbosity: self.verbosity,
+            diagnostics: self.diagnostics.unwrap_or_default(),
+            lints: self.lints,
+            max_warnings: self.max_warnings,
+            color: self.color.unwrap_or_default(),
+            no_pager: self.no_pager.unwrap_or_default(),
+            no_cache: self.no_cache.unwrap_or_default(),
+            refresh_cache: self.refresh_cache.unwrap_or_default(),
+            bundles: self.bundles,
+            compress: self.compress.unwrap_or_default(),
+            preserve_mtime: self.preserve_mtime.unwrap_or_default(),
+        })))
+    }
+}
+
+fn parse_merge_reports<A>(args: A) -> Result<Args, ArgsError>
+where
+    A: Iterator<Item = OsString>,
+{
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_encoded_bytes() {
+            b"-h" | b"--help" => return Ok(Args::Help),
+            [b'-', _, ..] => return Err(ArgsError::BadOption(arg)),
+            _ => paths.push(arg.into()),
+        }
+    }
+    if paths.is_empty() {
+        return Err(ArgsError::MissingArg);
+    }
+    Ok(Args::MergeReports(paths))
+}
+
+fn parse_doctor<A>(args: A) -> Result<Args, ArgsError>
+where
+    A: Iterator<Item = OsString>,
+{
+    let mut uris = Vec::new();
+    for arg in args {
+        match arg.as_encoded_bytes() {
+            b"-h" | b"--help" => return Ok(Args::Help),
+            [b'-', _, ..] => return Err(ArgsError::BadOption(arg)),
+            _ => uris.push(
+                arg.into_string()
+                    .map_err(|uri| ArgsError::InvalidValue("doctor", uri))?,
+            ),
+        }
+    }
+    Ok(Args::Doctor(uris))
+}
+
+fn parse_plugins_diff<A>(mut args: A) -> Result<Args, ArgsError>
+where
+    A: Iterator<Item = OsString>,
+{
+    let mut since = None;
+    let mut save = None;
+    while let Some(arg) = args.next() {
+        let (option, inline) = split_long(&arg);
+        let (name, slot) = match option.as_encoded_bytes() {
+            b"-h" | b"--help" if inline.is_none() => return Ok(Args::Help),
+            b"--since" => ("--since", &mut since),
+            b"--save" => ("--save", &mut save),
+            [b'-', _, ..] => return Err(ArgsError::BadOption(arg)),
+            _ => return Err(ArgsError::UnexpectedArg(arg)),
+        };
+        if slot.is_some() {
+            return Err(ArgsError::DuplicateOption(name));
+        }
+        let value = inline.or_else(|| args.next());
+        let value = value.ok_or(ArgsError::IncompleteOption(name))?;
+        *slot = Some(PathBuf::from(value));
+    }
+    if since.is_none() && save.is_none() {
+        return Err(ArgsError::MissingArg);
+    }
+    Ok(Args::PluginsDiff {
+        since,
+        save,
+    })
+}
+
+fn parse_export_port_map<A>(mut args: A) -> Result<Args, ArgsError>
+where
+    A: Iterator<Item = OsString>,
+{
+    let mut uris = Vec::new();
+    let mut sessions = Vec::new();
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        let (option, mut inline) = split_long(&arg);
+        match option.as_encoded_bytes() {
+            b"-h" | b"--help" if inline.is_none() => return Ok(Args::Help),
+            b"-o" | b"--output" => {
+                if output.is_some() {
+                    return Err(ArgsError::DuplicateOption("--output"));
+                }
+                let value = inline.take().or_else(|| args.next());
+                output = Some(value.ok_or(ArgsError::IncompleteOption("-o"))?);
+            }
+            b"--session" => {
+                let value = inline.take().or_else(|| args.next());
+                let value =
+                    value.ok_or(ArgsError::IncompleteOption("--session"))?;
+                sessions.push(PathBuf::from(value));
+            }
+            [b'-', _, ..] => return Err(ArgsError::BadOption(arg)),
+            _ => uris.push(arg.into_string().map_err(|uri| {
+                ArgsError::InvalidValue("export-portmap", uri)
+            })?),
+        }
+    }
+    if uris.is_empty() && sessions.is_empty() {
+        return Err(ArgsError::MissingArg);
+    }
+    Ok(Args::ExportPortMap {
+        uris,
+        sessions,
+        output: output.map(PathBuf::from),
+    })
+}
+
+fn parse_restore<A>(mut args: A) -> Result<Args, ArgsError>
+where
+    A: Iterator<Item = OsString>,
+{
+    let mut session = None;
+    let mut keep_patched = None;
+    let mut backup = backup::Options::default();
+    let mut suffix = None;
+    let mut dir = None;
+    while let Some(arg) = args.next() {
+        let (option, inline) = split_long(&arg);
+        let (name, slot) = match option.as_encoded_bytes() {
+            b"-h" | b"--help" if inline.is_none() => return Ok(Args::Help),
+            b"--keep-patched" => ("--keep-patched", &mut keep_patched),
+            b"--backup-suffix" => ("--backup-suffix", &mut suffix),
+            b"--backup-dir" => ("--backup-dir", &mut dir),
+            [b'-', _, ..] => return Err(ArgsError::BadOption(arg)),
+            _ if session.is_some() => {
+                return Err(ArgsError::UnexpectedArg(arg));
+            }
+            _ => {
+                session = Some(PathBuf::from(arg));
+                continue;
+            }
+        };
+        if slot.is_some() {
+            return Err(ArgsError::DuplicateOption(name));
+        }
+        let value = inline.or_else(|| args.next());
+        *slot = Some(value.ok_or(ArgsError::IncompleteOption(name))?);
+    }
+    if let Some(value) = suffix {
+        let Some(suffix) = value.to_str().filter(|s| valid_suffix(s)) else {
+            return Err(ArgsError::InvalidValue("--backup-suffix", value));
+        };
+        backup.suffix = suffix.to_owned();
+    }
+    backup.dir = dir.map(PathBuf::from);
+    Ok(Args::Restore {
+        session: session.ok_or(ArgsError::MissingArg)?,
+        keep_patched: keep_patched.map(PathBuf::from),
+        backup,
+    })
+}
+
+/// Splits an argument of the form `--option=value` into the option and its
+/// value. Other arguments are returned unchanged, without a value.
+fn split_long(arg: &OsStr) -> (OsString, Option<OsString>) {
+    let bytes = arg.as_encoded_bytes();
+    let i = match bytes.iter().position(|&b| b == b'=') {
+        Some(i) if bytes.starts_with(b"--") => i,
+        _ => return (arg.to_owned(), None),
+    };
+    // SAFETY: `bytes[..i]` starts at the start of `arg` and ends immediately
+    // before an ASCII byte, and `bytes[i + 1..]` starts immediately after
+    // that byte and ends at the end of `arg`, a valid `OsStr`.
+    let (option, value) = unsafe {
+        (
+            OsStr::from_encoded_bytes_unchecked(&bytes[..i]),
+            OsStr::from_encoded_bytes_unchecked(&bytes[i + 1..]),
+        )
+    };
+    (option.to_owned(), Some(value.to_owned()))
+}
+
+/// Whether `suffix` can be used with `--backup-suffix`. Numbers are appended
+/// to the suffix, so it can't end with a digit.
+fn valid_suffix(suffix: &str) -> bool {
+    !suffix.contains(['/', '\\'])
+        && suffix.ends_with(|c: char| !c.is_ascii_digit())
+}
+
+/// Splits the contents of an argument file into arguments, or returns
+/// [`None`] if a quote is unterminated or the file ends with a backslash.
+fn split_args(s: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            args.extend(current.take());
+            continue;
+        }
+        let arg = current.get_or_insert_with(String::new);
+        match c {
+            '\'' => loop {
+                match chars.next()? {
+                    '\'' => break,
+                    c => arg.push(c),
+                }
+            },
+            '"' => loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => arg.push(chars.next()?),
+                    c => arg.push(c),
+                }
+            },
+            '\\' => arg.push(chars.next()?),
+            c => arg.push(c),
+        }
+    }
+    args.extend(current);
+    Some(args)
+}
+
+/// Reads the sessions listed in the file at `path`, or in standard input if
+/// `path` is `-`. They are separated by newlines, or by NUL bytes if `null`
+/// is true, and empty entries are ignored.
+fn read_file_list(
+    path: &Path,
+    null: bool,
+) -> Result<Vec<OsString>, ArgsError> {
+    let contents = if path == Path::new("-") {
+        let mut contents = Vec::new();
+        io::stdin().lock().read_to_end(&mut contents).map(|_| contents)
+    } else {
+        std::fs::read(path)
+    };
+    let contents =
+        contents.map_err(|e| ArgsError::ArgFile(path.to_owned(), e))?;
+    let separator = if null {
+        b'\0'
+    } else {
+        b'\n'
+    };
+    Ok(contents
+        .split(|&b| b == separator)
+        .filter(|s| !s.is_empty())
+        .map(|s| OsString::from_vec(s.to_vec()))
+        .collect())
+}
+
+/// Replaces arguments of the form `@<file>` with the arguments in `<file>`,
+/// and those of the form `@@<file>` with the lines of `<file>`. Arguments in
+/// the file are not expanded further.
+fn expand_arg_files<A>(args: A) -> Result<Vec<OsString>, ArgsError>
+where
+    A: IntoIterator<Item = OsString>,
+{
+    let mut expanded = Vec::new();
+    let mut options_done = false;
+    for arg in args {
+        let bytes = arg.as_encoded_bytes();
+        let (path, lines) = match bytes.strip_prefix(b"@") {
+            Some(path) if !options_done => match path.strip_prefix(b"@") {
+                Some(path) => (path, true),
+                None => (path, false),
+            },
+            _ => {
+                options_done |= arg == "--";
+                expanded.push(arg);
+                continue;
+            }
+        };
+        // SAFETY: `path` starts immediately after an ASCII byte and ends at
+        // the end of `arg`, a valid `OsStr`.
+        let path = PathBuf::from(unsafe {
+            OsStr::from_encoded_bytes_unchecked(path)
+        });
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => return Err(ArgsError::ArgFile(path, e)),
+        };
+        if lines {
+            let lines = contents.lines().filter(|line| !line.is_empty());
+            expanded.extend(lines.map(OsString::from));
+            continue;
+        }
+        let Some(args) = split_args(&contents) else {
+            return Err(ArgsError::ArgFileSyntax(path));
+        };
+        expanded.extend(args.into_iter().map(OsString::from));
     }
+    Ok(expanded)
+}
+
+/// Gets the default options in the environment, if any.
+fn default_options() -> Result<Option<Vec<OsString>>, ArgsError> {
+    let Some(value) = std::env::var_os(OPTIONS_VAR) else {
+        return Ok(None);
+    };
+    let Some(args) = split_args(&value.to_string_lossy()) else {
+        return Err(ArgsError::OptionsVarSyntax);
+    };
+    expand_arg_files(args.into_iter().map(OsString::from)).map(Some)
+}
+
+/// Parses default options, from the environment or the configuration file.
+fn read_defaults(
+    args: Vec<OsString>,
+) -> Result<Parser<std::vec::IntoIter<OsString>>, ArgsError> {
+    let mut parser = Parser::defaults(args.into_iter());
+    let args = parser.read()?;
+    debug_assert!(args.is_none(), "defaults can't select another mode");
+    Ok(parser)
 }
 
 pub fn parse<A>(args: A) -> Result<Args, ArgsError>
 where
     A: IntoIterator<Item = OsString>,
 {
-    Parser {
-        args: args.into_iter(),
-        options_done: false,
-        input: None,
-        output: Output::InPlace,
+    let mut args = expand_arg_files(args)?.into_iter().peekable();
+    if args.peek().is_some_and(|a| a == "merge-reports") {
+        args.next();
+        return parse_merge_reports(args);
+    }
+    if args.peek().is_some_and(|a| a == "check-consistency") {
+        args.next();
+        let dir = args.next().ok_or(ArgsError::MissingArg)?;
+        if let Some(arg) = args.next() {
+            return Err(ArgsError::UnexpectedArg(arg));
+        }
+        return Ok(Args::CheckConsistency(dir.into()));
+    }
+    if args.peek().is_some_and(|a| a == "preflight") {
+        args.next();
+        return parse_preflight(args);
+    }
+    if args.peek().is_some_and(|a| a == "plugins-diff") {
+        args.next();
+        return parse_plugins_diff(args);
+    }
+    if args.peek().is_some_and(|a| a == "export-portmap") {
+        args.next();
+        return parse_export_port_map(args);
+    }
+    if args.peek().is_some_and(|a| a == "restore") {
+        args.next();
+        return parse_restore(args);
+    }
+    if args.peek().is_some_and(|a| a == "list-ports") {
+        args.next();
+        let uri = args.next().ok_or(ArgsError::MissingArg)?;
+        if let Some(arg) = args.next() {
+            return Err(ArgsError::UnexpectedArg(arg));
+        }
+        let uri = uri
+            .into_string()
+            .map_err(|uri| ArgsError::InvalidValue("list-ports", uri))?;
+        return Ok(Args::ListPorts(uri));
+    }
+    if args.peek().is_some_and(|a| a == "doctor") {
+        args.next();
+        return parse_doctor(args);
+    }
+    if args.peek().is_some_and(|a| a == "completions") {
+        args.next();
+        let name = args.next().ok_or(ArgsError::MissingArg)?;
+        if let Some(arg) = args.next() {
+            return Err(ArgsError::UnexpectedArg(arg));
+        }
+        let Some(shell) = Shell::from_name(name.as_encoded_bytes()) else {
+            return Err(ArgsError::InvalidValue("completions", name));
+        };
+        return Ok(Args::Completions(shell));
+    }
+    if args.peek().is_some_and(|a| a == "patch-string") {
+        args.next();
+        let xml = args.next().ok_or(ArgsError::MissingArg)?;
+        if let Some(arg) = args.next() {
+            return Err(ArgsError::UnexpectedArg(arg));
+        }
+        return Ok(Args::PatchString(xml));
+    }
+    parse_run(args)
+}
+
+/// Parses the options for fixing sessions, merging in the default options
+/// from the environment and the configuration file.
+fn parse_run<A>(args: A) -> Result<Args, ArgsError>
+where
+    A: Iterator<Item = OsString>,
+{
+    let mut parser = Parser::new(args);
+    if let Some(args) = parser.read()? {
+        return Ok(args);
+    }
+    if let Some(defaults) = default_options()? {
+        let defaults = read_defaults(defaults)
+            .map_err(|e| ArgsError::OptionsVar(Box::new(e)))?;
+        parser.merge_defaults(defaults);
+    }
+    if let Some((path, defaults)) = parser.config_defaults()? {
+        let defaults = read_defaults(defaults)
+            .map_err(|e| ArgsError::ConfigDefault(path, Box::new(e)))?;
+        parser.merge_defaults(defaults);
+    }
+    parser.finish()
+}
+
+/// Parses `preflight`, which accepts the same options as fixing a session
+/// (and the same defaults), except those that select the output.
+fn parse_preflight<A>(args: A) -> Result<Args, ArgsError>
+where
+    A: Iterator<Item = OsString>,
+{
+    let args = match parse_run(args)? {
+        Args::Run(args) => args,
+        args => return Ok(args),
+    };
+    match (&args.input, &args.output) {
+        (Input::Path(_), Output::InPlace) if !args.dry_run => {
+            Ok(Args::Preflight(args))
+        }
+        (Input::Stdin, _) => Err(ArgsError::Conflict("preflight", "--stdin")),
+        (Input::Paths(_), _) => {
+            Err(ArgsError::Conflict("preflight", "multiple sessions"))
+        }
+        _ if args.dry_run => {
+            Err(ArgsError::Conflict("preflight", "--dry-run and --check"))
+        }
+        _ => Err(ArgsError::Conflict("preflight", "output options")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn os_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    /// Parses `args` with the default options `defaults`.
+    fn parse_with(
+        args: &[&str],
+        defaults: &[&str],
+    ) -> Result<RunArgs, ArgsError> {
+        let mut parser = Parser::new(os_args(args).into_iter());
+        assert!(parser.read()?.is_none());
+        parser.merge_defaults(read_defaults(os_args(defaults))?);
+        match parser.finish()? {
+            Args::Run(args) => Ok(*args),
+            args => panic!("unexpected mode: {args:?}"),
+        }
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        let defaults = ["--force", "--no-annotation", "--no-cache"];
+        let args = parse_with(&["S.ardour"], &defaults).unwrap();
+        assert!(args.force && args.no_annotation && args.no_cache);
+        let cli = ["S.ardour", "--no-force", "--annotation", "--verify"];
+        let args = parse_with(&cli, &defaults).unwrap();
+        assert!(!args.force && !args.no_annotation && args.no_cache);
+        assert!(args.verify);
+        assert!(parse_with(&["S.ardour", "--no-no-cache"], &[]).is_err());
+    }
+
+    #[test]
+    fn conflicting_defaults_replaced() {
+        let defaults = ["--index-map=a.json", "--lv2-path=/lv2", "--no-cache"];
+        let args = parse_with(&["S.ardour"], &defaults).unwrap();
+        assert_eq!(args.index_map, Some("a.json".into()));
+        assert_eq!(args.lv2_path, Some("/lv2".into()));
+        let cli = [
+            "S.ardour",
+            "--port-map=b.json",
+            "--plugins-as-of=/snapshot",
+            "--refresh-cache",
+        ];
+        let args = parse_with(&cli, &defaults).unwrap();
+        assert_eq!(args.index_map, None);
+        assert_eq!(args.port_map, Some("b.json".into()));
+        assert_eq!(args.lv2_path, None);
+        assert_eq!(args.plugins_as_of, Some("/snapshot".into()));
+        assert!(!args.no_cache && args.refresh_cache);
+    }
+
+    #[test]
+    fn sessions_not_defaults() {
+        let parse = |defaults| parse_with(&["S.ardour"], defaults);
+        assert!(matches!(
+            parse(&["T.ardour"]),
+            Err(ArgsError::UnexpectedArg(_))
+        ));
+        for (defaults, name) in [
+            (&["-o", "out.ardour"][..], "-o"),
+            (&["--output-dir=out"], "--output-dir"),
+            (&["--in-place"], "--in-place"),
+            (&["--stdin"], "--stdin"),
+            (&["--files-from=list"], "--files-from"),
+            (&["-q0"], "-0"),
+            (&["--ignore-lock"], "--ignore-lock"),
+        ] {
+            match parse(defaults) {
+                Err(ArgsError::NotDefault(n)) => assert_eq!(n, name),
+                r => panic!("{defaults:?}: {r:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn modes_not_defaults() {
+        for (defaults, name) in [
+            (&["--help"][..], "--help"),
+            (&["-qh"], "-h"),
+            (&["--version"], "--version"),
+            (&["-V"], "-V"),
+            (&["--capabilities"], "--capabilities"),
+        ] {
+            match read_defaults(os_args(defaults)) {
+                Err(ArgsError::NotDefault(n)) => assert_eq!(n, name),
+                Err(e) => panic!("{defaults:?}: {e}"),
+                Ok(_) => panic!("{defaults:?} accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn arg_files_split() {
+        let path = std::env::temp_dir()
+            .join(
+                format!("fix-ardour-lv2-index-args-{}", std::process::id(),),
+            );
+        std::fs::write(
+            &path,
+            "--backup-dir\nMy \"Backups\"\n\nC:\\S.ardour\n",
+        )
+        .unwrap();
+        let mut words = OsString::from("@");
+        words.push(&path);
+        let mut lines = OsString::from("@");
+        lines.push(&words);
+        let expand = |args: &[&OsStr]| {
+            expand_arg_files(args.iter().map(OsString::from))
+                .map_err(|e| e.to_string())
+        };
+        let expanded = expand(&[&lines, "--".as_ref(), &lines]);
+        let words = expand(&[&words]);
+        std::fs::remove_file(&path).unwrap();
+        let expected = ["--backup-dir", "My \"Backups\"", "C:\\S.ardour"];
+        let mut expected = os_args(&expected);
+        expected.extend(["--".into(), lines]);
+        assert_eq!(expanded.unwrap(), expected);
+        let words = words.unwrap();
+        assert_eq!(
+            words,
+            os_args(&["--backup-dir", "My", "Backups", "C:S.ardour"])
+        );
     }
-    .parse()
 }
@@ -0,0 +1,424 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::time::Timestamp;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The default suffix appended to the names of backups.
+pub const DEFAULT_SUFFIX: &str = ".orig";
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Strategy {
+    /// Move the original file to the backup location. The patched session
+    /// is written to a new file.
+    Rename,
+    /// Copy the original file to the backup location, so the original file
+    /// (and its inode) is kept and overwritten.
+    Copy,
+    /// Like [`Self::Copy`], but share the file's data with the backup via a
+    /// reflink if the filesystem supports it.
+    #[default]
+    Reflink,
+}
+
+impl Strategy {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"rename" => Self::Rename,
+            b"copy" => Self::Copy,
+            b"reflink" => Self::Reflink,
+            _ => return None,
+        })
+    }
+}
+
+/// How backups are named.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Naming {
+    /// `<name><suffix>`, then `<name><suffix><n>` for later backups.
+    #[default]
+    Numbered,
+    /// `<name><suffix>-<time>`, with the time in ISO 8601 basic format.
+    Timestamp,
+}
+
+impl Naming {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"numbered" => Self::Numbered,
+            b"timestamp" => Self::Timestamp,
+            _ => return None,
+        })
+    }
+}
+
+/// Where and how backups are made.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub strategy: Strategy,
+    pub naming: Naming,
+    /// Appended to a file's name to name its backup.
+    pub suffix: String,
+    /// The directory backups are placed in, instead of next to the file.
+    pub dir: Option<PathBuf>,
+    /// The number of backups of each file to keep; older ones are removed
+    /// by [`prune`].
+    pub keep: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::default(),
+            naming: Naming::default(),
+            suffix: DEFAULT_SUFFIX.to_owned(),
+            dir: None,
+            keep: None,
+        }
+    }
+}
+
+/// The order of a backup among the backups of a file.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct Generation {
+    /// The time in the backup's name, in ISO 8601 basic format, so it
+    /// sorts chronologically. Numbered backups are older than timestamped
+    /// ones.
+    time: Option<String>,
+    /// The backup's number, or 0 if it has none.
+    number: u64,
+}
+
+/// Splits a number without leading zeros off the end of `name`, returning 0
+/// if there is none.
+fn split_number(name: &[u8]) -> Option<(&[u8], u64)> {
+    let digits = name.iter().rev().take_while(|b| b.is_ascii_digit()).count();
+    let (name, n) = name.split_at(name.len() - digits);
+    if n.is_empty() {
+        return Some((name, 0));
+    }
+    if n.starts_with(b"0") {
+        return None;
+    }
+    Some((name, std::str::from_utf8(n).ok()?.parse().ok()?))
+}
+
+/// Splits `-<time>` or `-<time>-<n>`, as made by [`Naming::Timestamp`], off
+/// the end of `name`.
+fn split_timestamped(name: &[u8]) -> Option<(&[u8], &[u8], u64)> {
+    const LEN: usize = "-20240501T123456Z".len();
+    let (name, number) = match split_number(name) {
+        Some((rest, n)) if n > 0 => (rest.strip_suffix(b"-")?, n),
+        _ => (name, 0),
+    };
+    let (name, time) = name.split_at(name.len().checked_sub(LEN)?);
+    let valid = time.iter().enumerate().all(|(i, &b)| match i {
+        0 => b == b'-',
+        9 => b == b'T',
+        16 => b == b'Z',
+        _ => b.is_ascii_digit(),
+    });
+    valid.then_some((name, &time[1..], number))
+}
+
+/// If `name` is the file name of a backup made with `suffix`, returns the
+/// name of the file it is a backup of and the backup's generation.
+fn parse<'a>(
+    name: &'a OsStr,
+    suffix: &str,
+) -> Option<(&'a OsStr, Generation)> {
+    let bytes = name.as_encoded_bytes();
+    let (rest, time, number) = match split_timestamped(bytes) {
+        Some((rest, time, n)) => (rest, Some(time), n),
+        None => {
+            let (rest, n) = split_number(bytes)?;
+            (rest, None, n)
+        }
+    };
+    let rest = rest.strip_suffix(suffix.as_bytes())?;
+    if rest.is_empty() {
+        return None;
+    }
+    let generation = Generation {
+        time: time.map(|t| String::from_utf8_lossy(t).into_owned()),
+        number,
+    };
+    // SAFETY: `rest` is a prefix of a valid `OsStr` that ends immediately
+    // before a valid UTF-8 string (`suffix` followed by ASCII characters).
+    Some((unsafe { OsStr::from_encoded_bytes_unchecked(rest) }, generation))
+}
+
+/// A backup of a file.
+#[derive(Debug)]
+pub struct Backup {
+    pub path: PathBuf,
+    generation: Generation,
+}
+
+/// The directory backups of the file at `path` are placed in.
+fn backup_dir<'a>(path: &'a Path, options: &'a Options) -> &'a Path {
+    match &options.dir {
+        Some(dir) => dir,
+        None => path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new(".")),
+    }
+}
+
+/// Returns the backups of the file at `path`, from oldest to newest.
+pub fn list(path: &Path, options: &Options) -> io::Result<Vec<Backup>> {
+    let Some(name) = path.file_name() else {
+        return Ok(Vec::new());
+    };
+    let dir = backup_dir(path, options);
+    let mut backups = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(backups),
+        entries => entries?,
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some((original, generation)) = parse(&file_name, &options.suffix)
+        else {
+            continue;
+        };
+        if original == name {
+            backups.push(Backup {
+                path: entry.path(),
+                generation,
+            });
+        }
+    }
+    backups.sort_by(|a, b| a.generation.cmp(&b.generation));
+    Ok(backups)
+}
+
+/// Removes the oldest backups of the file at `path` beyond the number to
+/// keep, if one is set.
+pub fn prune(path: &Path, options: &Options) -> io::Result<()> {
+    let Some(keep) = options.keep else {
+        return Ok(());
+    };
+    let backups = list(path, options)?;
+    let excess = backups.len().saturating_sub(keep);
+    for backup in &backups[..excess] {
+        std::fs::remove_file(&backup.path)?;
+    }
+    Ok(())
+}
+
+/// If `path` is named like a backup made by [`create`] with `suffix`,
+/// returns the path of the file it is a backup of, assuming the backup is
+/// next to it.
+pub fn original(path: &Path, suffix: &str) -> Option<PathBuf> {
+    let (name, _) = parse(path.file_name()?, suffix)?;
+    Some(path.with_file_name(name))
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(src: &File, dest: &File) -> io::Result<()> {
+    use std::ffi::{c_int, c_ulong};
+    use std::os::fd::AsRawFd;
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    const FICLONE: c_ulong = 0x40049409;
+    // SAFETY: `FICLONE` takes a file descriptor as its only argument, and
+    // both descriptors are valid for the duration of the call.
+    if unsafe { ioctl(dest.as_raw_fd(), FICLONE, src.as_raw_fd()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_: &File, _: &File) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+fn copy(path: &Path, mut dest: File, strategy: Strategy) -> io::Result<()> {
+    let mut src = File::open(path)?;
+    dest.set_permissions(src.metadata()?.permissions())?;
+    if strategy == Strategy::Reflink && reflink(&src, &dest).is_ok() {
+        return Ok(());
+    }
+    io::copy(&mut src, &mut dest)?;
+    dest.sync_all()
+}
+
+/// Backs up the file at `path`, naming the backup according to
+/// `options.naming` and placing it in the backup directory if one is set.
+/// Numbered backups are numbered after the newest existing one. Returns the
+/// path of the backup.
+pub fn create(path: &Path, options: &Options) -> io::Result<PathBuf> {
+    let Some(name) = path.file_name() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "path has no file name",
+        ));
+    };
+    let dir = backup_dir(path, options);
+    if options.dir.is_some() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut name = OsString::from_iter([name, options.suffix.as_ref()]);
+    let mut i = 0;
+    match options.naming {
+        Naming::Numbered => {
+            let newest = list(path, options)?
+                .into_iter()
+                .rev()
+                .find(|b| b.generation.time.is_none());
+            if let Some(newest) = newest {
+                i = newest.generation.number + 1;
+            }
+        }
+        Naming::Timestamp => {
+            let now = Timestamp::from(SystemTime::now());
+            name.push(format!("-{}", now.basic()));
+        }
+    }
+    let separator = match options.naming {
+        Naming::Numbered => "",
+        Naming::Timestamp => "-",
+    };
+    let numbered = |i: u64| {
+        let mut numbered = name.clone();
+        if i > 0 {
+            numbered.push(format!("{separator}{i}"));
+        }
+        dir.join(numbered)
+    };
+    let mut backup = numbered(i);
+    let strategy = options.strategy;
+    loop {
+        match File::options().write(true).create_new(true).open(&backup) {
+            Ok(f) if strategy == Strategy::Rename => {
+                drop(f);
+                let result = std::fs::rename(path, &backup);
+                if result.is_ok() || options.dir.is_none() {
+                    return result.map(|()| backup);
+                }
+                // The backup directory may be on another filesystem, so
+                // copy the file instead and remove the original.
+                let result = File::create(&backup)
+                    .and_then(|f| copy(path, f, Strategy::Copy));
+                if let Err(e) = result {
+                    let _ = std::fs::remove_file(&backup);
+                    return Err(e);
+                }
+                return std::fs::remove_file(path).map(|()| backup);
+            }
+            Ok(f) => {
+                let result = copy(path, f, strategy);
+                if result.is_err() {
+                    let _ = std::fs::remove_file(&backup);
+                }
+                return result.map(|()| backup);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                i += 1;
+                backup = numbered(i);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Moves the backup at `backup` to `path`, replacing it. If the backup is on
+/// another filesystem, it is copied next to `path` first, so `path` is
+/// replaced atomically either way.
+pub fn restore(backup: &Path, path: &Path) -> io::Result<()> {
+    if std::fs::rename(backup, path).is_ok() {
+        return Ok(());
+    }
+    let temp: PathBuf =
+        OsString::from_iter([path.as_os_str(), ".restoring".as_ref()]).into();
+    let file = File::options().write(true).create_new(true).open(&temp)?;
+    let result = copy(backup, file, Strategy::Copy)
+        .and_then(|()| std::fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+        return result;
+    }
+    std::fs::remove_file(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original_name(name: &str, suffix: &str) -> Option<PathBuf> {
+        original(Path::new(name), suffix)
+    }
+
+    #[test]
+    fn original_numbered() {
+        let original = Some(PathBuf::from("dir/S.ardour"));
+        assert_eq!(original_name("dir/S.ardour.orig", ".orig"), original);
+        assert_eq!(original_name("dir/S.ardour.orig1", ".orig"), original);
+        assert_eq!(original_name("dir/S.ardour.orig12", ".orig"), original);
+        assert_eq!(original_name("dir/S.ardour.orig01", ".orig"), None);
+        assert_eq!(original_name("dir/S.ardour.orig.x", ".orig"), None);
+        assert_eq!(original_name("dir/S.ardour", ".orig"), None);
+        assert_eq!(original_name(".orig", ".orig"), None);
+    }
+
+    #[test]
+    fn original_timestamped() {
+        let original = Some(PathBuf::from("S.ardour"));
+        let name = "S.ardour.orig-20240501T123456Z";
+        assert_eq!(original_name(name, ".orig"), original);
+        assert_eq!(original_name(&format!("{name}-2"), ".orig"), original);
+        let name = "S.ardour.orig-20240501T1234Z";
+        assert_eq!(original_name(name, ".orig"), None);
+    }
+
+    #[test]
+    fn original_suffix() {
+        let original = Some(PathBuf::from("S.ardour"));
+        assert_eq!(original_name("S.ardour.bak3", ".bak"), original);
+        let name = "S.ardour-old.ardour";
+        assert_eq!(original_name(name, "-old.ardour"), original);
+        assert_eq!(original_name("S.ardour.orig", ".bak"), None);
+    }
+
+    #[test]
+    fn dir_of_bare_name() {
+        let options = Options::default();
+        let dir = |name| backup_dir(Path::new(name), &options);
+        assert_eq!(dir("S.ardour"), Path::new("."));
+        assert_eq!(dir("dir/S.ardour"), Path::new("dir"));
+        assert_eq!(dir("/S.ardour"), Path::new("/"));
+        let options = Options {
+            dir: Some("backups".into()),
+            ..Options::default()
+        };
+        let dir = backup_dir(Path::new("S.ardour"), &options);
+        assert_eq!(dir, Path::new("backups"));
+    }
+}
@@ -0,0 +1,93 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A machine-readable description of the features supported by this build,
+//! printed by `--capabilities`.
+
+use super::json::Str;
+use super::report;
+use std::fmt::{self, Display};
+
+/// The version of the capabilities document itself. Only incremented for
+/// incompatible changes; new keys may be added at any time.
+const VERSION: u32 = 1;
+
+const INPUTS: &[&str] = &[
+    "session-file",
+    "session-directory",
+    "stdin",
+    "file-list",
+    #[cfg(feature = "gzip")]
+    "gzip",
+    #[cfg(feature = "zip")]
+    "zip-archive",
+];
+const BACKENDS: &[&str] = &[
+    "lilv",
+    "index-map",
+    "port-map",
+    #[cfg(feature = "http")]
+    "http",
+];
+pub const SUBCOMMANDS: &[&str] = &[
+    "merge-reports",
+    "check-consistency",
+    "preflight",
+    "plugins-diff",
+    "export-portmap",
+    "restore",
+    "list-ports",
+    "doctor",
+    "completions",
+];
+pub const REPORT_FORMATS: &[&str] = &["text", "json", "csv", "html"];
+pub const BACKUP_STRATEGIES: &[&str] = &["rename", "copy", "reflink"];
+
+struct List(&'static [&'static str]);
+
+impl Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, item) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", Str(item))?;
+        }
+        write!(f, "]")
+    }
+}
+
+pub struct Capabilities;
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{{")?;
+        writeln!(f, "  \"capabilities_version\": {VERSION},")?;
+        writeln!(f, "  \"version\": {},", Str(env!("CARGO_PKG_VERSION")),)?;
+        writeln!(f, "  \"inputs\": {},", List(INPUTS))?;
+        writeln!(f, "  \"backends\": {},", List(BACKENDS))?;
+        writeln!(f, "  \"subcommands\": {},", List(SUBCOMMANDS))?;
+        writeln!(f, "  \"report_formats\": {},", List(REPORT_FORMATS))?;
+        let schema = report::JSON_VERSION;
+        writeln!(f, "  \"report_schema_version\": {schema},")?;
+        writeln!(f, "  \"backup_strategies\": {}", List(BACKUP_STRATEGIES))?;
+        writeln!(f, "}}")
+    }
+}
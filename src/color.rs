@@ -0,0 +1,136 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Terminal colors.
+//!
+//! Whether output to a stream is colored is decided by [`enabled`], from the
+//! [`Choice`] set with [`set_choice`] and, when it is [`Choice::Auto`], the
+//! `NO_COLOR` environment variable and whether the stream is a terminal.
+
+use std::fmt::{self, Display};
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Choice {
+    /// Color output to terminals, unless `NO_COLOR` is set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Choice {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"auto" => Self::Auto,
+            b"always" => Self::Always,
+            b"never" => Self::Never,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+static CHOICE: Mutex<Choice> = Mutex::new(Choice::Auto);
+
+pub fn set_choice(choice: Choice) {
+    *CHOICE.lock().unwrap_or_else(|e| e.into_inner()) = choice;
+}
+
+/// Returns whether output to `stream` should be colored.
+pub fn enabled(stream: Stream) -> bool {
+    match *CHOICE.lock().unwrap_or_else(|e| e.into_inner()) {
+        Choice::Always => true,
+        Choice::Never => false,
+        Choice::Auto => {
+            // See <https://no-color.org/>: any non-empty value disables
+            // color.
+            let no_color = std::env::var_os("NO_COLOR");
+            if no_color.is_some_and(|v| !v.is_empty()) {
+                return false;
+            }
+            match stream {
+                Stream::Stdout => io::stdout().is_terminal(),
+                Stream::Stderr => io::stderr().is_terminal(),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Color {
+    /// Missing plugins and removed ports.
+    Red,
+    /// Synthetic indices, changes, and warnings.
+    Yellow,
+    /// Clean sessions and added ports.
+    Green,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Self::Red => "31",
+            Self::Yellow => "33",
+            Self::Green => "32",
+        }
+    }
+
+    /// Displays `value` in this color if `enabled` is true, or as is
+    /// otherwise.
+    pub fn paint<T: Display>(self, value: T, enabled: bool) -> Paint<T> {
+        paint(Some(self), value, enabled)
+    }
+}
+
+/// Displays `value` in `color` if it is [`Some`] and `enabled` is true, or
+/// as is otherwise.
+pub fn paint<T: Display>(
+    color: Option<Color>,
+    value: T,
+    enabled: bool,
+) -> Paint<T> {
+    Paint {
+        color: color.filter(|_| enabled),
+        value,
+    }
+}
+
+/// A value displayed in a color, returned by [`paint`].
+pub struct Paint<T> {
+    color: Option<Color>,
+    value: T,
+}
+
+impl<T: Display> Display for Paint<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.color {
+            Some(color) => {
+                write!(f, "\x1b[{}m{}\x1b[0m", color.code(), self.value)
+            }
+            None => write!(f, "{}", self.value),
+        }
+    }
+}
@@ -0,0 +1,309 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Shell completion scripts, printed by the `completions` subcommand.
+//!
+//! The options are read from the usage message, so that the completions
+//! always match `--help`.
+
+use super::USAGE;
+use super::capabilities::{BACKUP_STRATEGIES, REPORT_FORMATS, SUBCOMMANDS};
+use std::fmt::{self, Display};
+
+/// The name the completions are registered for.
+const COMMAND: &str = env!("CARGO_PKG_NAME");
+
+/// The column option descriptions start at in the usage message.
+const HELP_COLUMN: usize = 21;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"bash" => Self::Bash,
+            b"zsh" => Self::Zsh,
+            b"fish" => Self::Fish,
+            _ => return None,
+        })
+    }
+}
+
+/// What the value of an option is completed with.
+enum Values {
+    /// The option doesn't take a value.
+    None,
+    Files,
+    Dirs,
+    Words(&'static [&'static str]),
+    /// A value that can't be completed, like a number.
+    Any,
+}
+
+/// An option listed in the usage message.
+struct Opt {
+    short: Option<char>,
+    long: &'static str,
+    /// The placeholder for the value, like `<file>`.
+    value: Option<&'static str>,
+    help: String,
+}
+
+impl Opt {
+    fn values(&self) -> Values {
+        match self.long {
+            "--color" => Values::Words(&["auto", "always", "never"]),
+            "--diagnostics" => Values::Words(&["text", "json"]),
+            "--backup" => Values::Words(&["numbered", "timestamp"]),
+            "--backup-strategy" => Values::Words(BACKUP_STRATEGIES),
+            "--report" => Values::Words(REPORT_FORMATS),
+            _ => match self.value {
+                None => Values::None,
+                Some("<file>" | "<session>") => Values::Files,
+                Some("<dir>") => Values::Dirs,
+                Some(_) => Values::Any,
+            },
+        }
+    }
+}
+
+/// Parses the options in the usage message.
+fn options() -> Vec<Opt> {
+    let mut lines = USAGE
+        .lines()
+        .skip_while(|line| *line != "Options:")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .peekable();
+    let mut options = Vec::new();
+    while let Some(line) = lines.next() {
+        let Some(head) =
+            line.strip_prefix("  ").filter(|l| l.starts_with('-'))
+        else {
+            continue;
+        };
+        let mut help = String::new();
+        let head = match line.as_bytes().get(HELP_COLUMN - 1) {
+            Some(b' ') if line.len() > HELP_COLUMN => {
+                help.push_str(&line[HELP_COLUMN..]);
+                &line[2..HELP_COLUMN]
+            }
+            _ => head,
+        };
+        while let Some(next) = lines.next_if(|l| l.starts_with("   ")) {
+            if !help.is_empty() {
+                help.push(' ');
+            }
+            help.push_str(next.trim_start());
+        }
+        let mut opt = Opt {
+            short: None,
+            long: "",
+            value: None,
+            help,
+        };
+        for word in head.split_whitespace() {
+            let word = word.trim_end_matches(',');
+            if word.starts_with("--") {
+                opt.long = word;
+            } else if let Some(short) = word.strip_prefix('-') {
+                opt.short = short.chars().next();
+            } else {
+                opt.value = Some(word);
+            }
+        }
+        options.push(opt);
+    }
+    options
+}
+
+/// Writes `s` in single quotes for bash and zsh.
+fn quote(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Writes `s` in single quotes for fish.
+fn quote_fish(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// The name of the shell function that completes the command.
+fn function_name() -> String {
+    format!("_{}", COMMAND.replace('-', "_"))
+}
+
+fn bash(f: &mut fmt::Formatter<'_>, options: &[Opt]) -> fmt::Result {
+    let function = function_name();
+    let names = |opt: &Opt| {
+        let mut names = opt.long.to_owned();
+        if let Some(short) = opt.short {
+            names = format!("-{short}|{names}");
+        }
+        names
+    };
+    writeln!(f, "{function}() {{")?;
+    writeln!(f, "    local cur=${{COMP_WORDS[COMP_CWORD]}}")?;
+    writeln!(f, "    local prev=${{COMP_WORDS[COMP_CWORD-1]}}")?;
+    writeln!(f, "    case $prev in")?;
+    for opt in options {
+        let reply = match opt.values() {
+            Values::None => continue,
+            Values::Files => "COMPREPLY=($(compgen -f -- \"$cur\"))".into(),
+            Values::Dirs => "COMPREPLY=($(compgen -d -- \"$cur\"))".into(),
+            Values::Words(words) => format!(
+                "COMPREPLY=($(compgen -W '{}' -- \"$cur\"))",
+                words.join(" "),
+            ),
+            Values::Any => String::new(),
+        };
+        writeln!(f, "        {})", names(opt))?;
+        if !reply.is_empty() {
+            writeln!(f, "            {reply}")?;
+        }
+        writeln!(f, "            return")?;
+        writeln!(f, "            ;;")?;
+    }
+    writeln!(f, "    esac")?;
+    writeln!(f, "    if [[ $cur == -* ]]; then")?;
+    write!(f, "        COMPREPLY=($(compgen -W '")?;
+    for (i, opt) in options.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        if let Some(short) = opt.short {
+            write!(f, "-{short} ")?;
+        }
+        write!(f, "{}", opt.long)?;
+    }
+    writeln!(f, "' -- \"$cur\"))")?;
+    writeln!(f, "    elif ((COMP_CWORD == 1)); then")?;
+    writeln!(
+        f,
+        "        COMPREPLY=($(compgen -W '{}' -f -- \"$cur\"))",
+        SUBCOMMANDS.join(" "),
+    )?;
+    writeln!(f, "    else")?;
+    writeln!(f, "        COMPREPLY=($(compgen -f -- \"$cur\"))")?;
+    writeln!(f, "    fi")?;
+    writeln!(f, "}}")?;
+    writeln!(f, "complete -o filenames -F {function} {COMMAND}")
+}
+
+fn zsh(f: &mut fmt::Formatter<'_>, options: &[Opt]) -> fmt::Result {
+    let function = function_name();
+    writeln!(f, "#compdef {COMMAND}")?;
+    writeln!(f)?;
+    writeln!(f, "{function}_first() {{")?;
+    writeln!(f, "    _alternative \\")?;
+    writeln!(
+        f,
+        "        'subcommands:subcommand:({})' \\",
+        SUBCOMMANDS.join(" "),
+    )?;
+    writeln!(f, "        'files:session:_files'")?;
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+    writeln!(f, "_arguments -s -S \\")?;
+    for opt in options {
+        let values = opt.values();
+        let takes_value = !matches!(values, Values::None);
+        let (short_suffix, long_suffix) = if takes_value {
+            ("+", "=")
+        } else {
+            ("", "")
+        };
+        let long = opt.long;
+        write!(f, "    ")?;
+        if let Some(short) = opt.short {
+            write!(f, "'(-{short} {long})'")?;
+            write!(f, "{{-{short}{short_suffix},{long}{long_suffix}}}")?;
+        } else {
+            write!(f, "{long}{long_suffix}")?;
+        }
+        let help = opt.help.replace('[', r"\[").replace(']', r"\]");
+        let mut spec = format!("[{help}]");
+        if let Some(value) = opt.value {
+            let value = value.trim_start_matches('<');
+            let value = &value[..value.find('>').unwrap_or(value.len())];
+            spec.push(':');
+            spec.push_str(value);
+            spec.push(':');
+            match values {
+                Values::None | Values::Any => spec.push(' '),
+                Values::Files => spec.push_str("_files"),
+                Values::Dirs => spec.push_str("_files -/"),
+                Values::Words(words) => {
+                    spec.push_str(&format!("({})", words.join(" ")));
+                }
+            }
+        }
+        quote(f, &spec)?;
+        writeln!(f, " \\")?;
+    }
+    writeln!(f, "    '1: :{function}_first' \\")?;
+    writeln!(f, "    '*:session:_files'")
+}
+
+fn fish(f: &mut fmt::Formatter<'_>, options: &[Opt]) -> fmt::Result {
+    write!(f, "complete -c {COMMAND} -n __fish_use_subcommand -a ")?;
+    quote_fish(f, &SUBCOMMANDS.join(" "))?;
+    writeln!(f)?;
+    for opt in options {
+        write!(f, "complete -c {COMMAND}")?;
+        if let Some(short) = opt.short {
+            write!(f, " -s {short}")?;
+        }
+        write!(f, " -l {}", &opt.long[2..])?;
+        match opt.values() {
+            Values::None => {}
+            Values::Files => write!(f, " -r -F")?,
+            Values::Dirs => {
+                write!(f, " -x -a '(__fish_complete_directories)'")?;
+            }
+            Values::Words(words) => {
+                write!(f, " -x -a ")?;
+                quote_fish(f, &words.join(" "))?;
+            }
+            Values::Any => write!(f, " -x")?,
+        }
+        write!(f, " -d ")?;
+        quote_fish(f, &opt.help)?;
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+/// The completion script for a shell.
+pub struct Script(pub Shell);
+
+impl Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let options = options();
+        match self.0 {
+            Shell::Bash => bash(f, &options),
+            Shell::Zsh => zsh(f, &options),
+            Shell::Fish => fish(f, &options),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The configuration file.
+//!
+//! The configuration is a TOML document (see [`toml`] for the supported
+//! subset). The table `pins` maps plugin URIs to the backend their port
+//! metadata should be retrieved from, overriding the backend chosen on the
+//! command line, and `defaults` holds default values for command-line
+//! options:
+//!
+//! ```toml
+//! [pins]
+//! "http://example.org/plug" = { backend = "manifest", file = "plug.json" }
+//!
+//! [defaults]
+//! backup = "numbered"
+//! lv2-path = "/opt/lv2"
+//! deny = ["missing-plugin", "missing-port"]
+//! report = "html=/tmp/report.html"
+//! verify = true
+//! ```
+//!
+//! Backends are `lilv`, `index-map` and `port-map` or its alias `manifest`
+//! (with a `file`, relative to the configuration file), and `http` (with a
+//! `url`).
+//!
+//! Each key in `defaults` is the name of a long option. A string or number
+//! is the option's value, `true` gives an option without a value, `false`
+//! omits the option, and an array gives the option once for each element.
+
+use super::json::Value;
+use super::toml;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The name of the configuration file in the configuration directory.
+pub const FILE_NAME: &str = "config.toml";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Backend {
+    Lilv,
+    IndexMap(PathBuf),
+    PortMap(PathBuf),
+    Http(String),
+}
+
+#[derive(Debug)]
+pub struct Pin {
+    pub uri: String,
+    pub backend: Backend,
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub pins: Vec<Pin>,
+    /// The default options in `defaults`, as command-line arguments.
+    pub defaults: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Toml(toml::Error),
+    Format(&'static str),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Toml(e) => write!(f, "invalid toml: {e}"),
+            Self::Format(s) => write!(f, "invalid config: {s}"),
+        }
+    }
+}
+
+fn parse_backend(value: &Value, dir: &Path) -> Result<Backend, Error> {
+    let field = |name| value.get(name).and_then(Value::as_str);
+    Ok(match field("backend") {
+        Some("lilv") => Backend::Lilv,
+        Some("index-map") => {
+            let Some(file) = field("file") else {
+                return Err(Error::Format("index-map pin must have `file`"));
+            };
+            Backend::IndexMap(dir.join(file))
+        }
+        Some("port-map" | "manifest") => {
+            let Some(file) = field("file") else {
+                return Err(Error::Format("port-map pin must have `file`"));
+            };
+            Backend::PortMap(dir.join(file))
+        }
+        Some("http") => {
+            let Some(url) = field("url") else {
+                return Err(Error::Format("http pin must have `url`"));
+            };
+            Backend::Http(url.to_owned())
+        }
+        Some(_) => return Err(Error::Format("unknown backend")),
+        None => return Err(Error::Format("pin must have `backend`")),
+    })
+}
+
+/// Appends the arguments for the default option `name` with `value`.
+fn push_default(
+    args: &mut Vec<String>,
+    name: &str,
+    value: &Value,
+) -> Result<(), Error> {
+    let value = match value {
+        Value::Bool(false) => return Ok(()),
+        Value::Bool(true) => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Array(values) => {
+            for value in values {
+                if matches!(value, Value::Array(_)) {
+                    return Err(Error::Format("nested array in `defaults`"));
+                }
+                push_default(args, name, value)?;
+            }
+            return Ok(());
+        }
+        _ => return Err(Error::Format("invalid value in `defaults`")),
+    };
+    args.push(match value {
+        // Given as `--option=value`, so that a value for an option without
+        // one is an error rather than a separate argument.
+        Some(value) => format!("--{name}={value}"),
+        None => format!("--{name}"),
+    });
+    Ok(())
+}
+
+impl Config {
+    /// Parses a configuration. Relative paths are resolved against `dir`.
+    pub fn parse(s: &str, dir: &Path) -> Result<Self, Error> {
+        let value = toml::parse(s).map_err(Error::Toml)?;
+        let mut this = Self::default();
+        if let Some(defaults) = value.get("defaults") {
+            let Some(defaults) = defaults.as_object() else {
+                return Err(Error::Format("`defaults` must be a table"));
+            };
+            for (name, value) in defaults {
+                push_default(&mut this.defaults, name, value)?;
+            }
+        }
+        let Some(pins) = value.get("pins") else {
+            return Ok(this);
+        };
+        let Some(pins) = pins.as_object() else {
+            return Err(Error::Format("`pins` must be a table"));
+        };
+        for (uri, backend) in pins {
+            this.pins.push(Pin {
+                uri: uri.clone(),
+                backend: parse_backend(backend, dir)?,
+            });
+        }
+        Ok(this)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let s = std::fs::read_to_string(path).map_err(Error::Io)?;
+        Self::parse(&s, path.parent().unwrap_or(Path::new("")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pins() {
+        let config = Config::parse(
+            r#"
+[pins]
+"urn:a" = { backend = "manifest", file = "a.json" }
+"urn:b" = { backend = "index-map", file = "/b.json" }
+"urn:c" = { backend = "lilv" }
+"#,
+            Path::new("/etc"),
+        )
+        .unwrap();
+        let pins: Vec<_> =
+            config.pins.iter().map(|p| (p.uri.as_str(), &p.backend)).collect();
+        assert_eq!(pins, [
+            ("urn:a", &Backend::PortMap("/etc/a.json".into())),
+            ("urn:b", &Backend::IndexMap("/b.json".into())),
+            ("urn:c", &Backend::Lilv),
+        ]);
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::parse(
+            r#"
+[defaults]
+deny = ["missing-plugin", "missing-port"]
+keep-backups = 5
+verify = true
+force = false
+"#,
+            Path::new(""),
+        )
+        .unwrap();
+        assert_eq!(config.defaults, [
+            "--deny=missing-plugin",
+            "--deny=missing-port",
+            "--keep-backups=5",
+            "--verify",
+        ]);
+    }
+
+    #[test]
+    fn invalid_pin() {
+        let parse = |s| Config::parse(s, Path::new(""));
+        assert!(
+            parse("[pins]\n\"urn:a\" = { backend = \"manifest\" }").is_err()
+        );
+        assert!(parse("[pins]\n\"urn:a\" = { backend = \"other\" }").is_err());
+        assert!(parse("pins = 1").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Checking that the snapshots of a session agree on the parameter indices
+//! of each processor, printed by the `check-consistency` subcommand.
+
+use super::lines::LineIndex;
+use super::session::Processor;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+/// A port of a processor, identified across snapshots by the processor's id.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
+struct PortKey<'a> {
+    id: &'a str,
+    uri: &'a str,
+    symbol: &'a str,
+}
+
+/// The indices used for each port, with the snapshots that use them.
+#[derive(Debug, Default)]
+pub struct Consistency<'a> {
+    ports: BTreeMap<PortKey<'a>, BTreeMap<u32, Vec<&'a str>>>,
+}
+
+impl<'a> Consistency<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the indices used by the snapshot `name`.
+    pub fn add(&mut self, name: &'a str, doc: &'a roxmltree::Document<'_>) {
+        let lines = LineIndex::new(doc.input_text());
+        let processors = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("Processor"))
+            .filter_map(|n| Processor::parse(n, &lines));
+        for processor in processors {
+            let Some(id) = processor.id() else {
+                continue;
+            };
+            for parameter in processor.parameters() {
+                let key = PortKey {
+                    id,
+                    uri: processor.uri(),
+                    symbol: parameter.symbol,
+                };
+                let snapshots = self
+                    .ports
+                    .entry(key)
+                    .or_default()
+                    .entry(parameter.old_index)
+                    .or_default();
+                if snapshots.last() != Some(&name) {
+                    snapshots.push(name);
+                }
+            }
+        }
+    }
+
+    /// The number of ports whose index differs between snapshots.
+    pub fn divergences(&self) -> usize {
+        self.ports.values().filter(|indices| indices.len() > 1).count()
+    }
+}
+
+impl Display for Consistency<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, indices) in &self.ports {
+            if indices.len() < 2 {
+                continue;
+            }
+            writeln!(
+                f,
+                "processor {} ({}), port \"{}\":",
+                key.id,
+                key.uri,
+                key.symbol.escape_default(),
+            )?;
+            for (index, snapshots) in indices {
+                writeln!(f, "  index {index}: {}", snapshots.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A snapshot whose processor 30 has the port "gain" at `index`.
+    fn snapshot(index: u32) -> String {
+        format!(
+            r#"<Session version="7003" name="S"><Routes>
+<Route id="20" name="Audio 1">
+<Processor id="30" name="Amp" type="lv2" unique-id="urn:amp">
+<Controllable name="gain" id="31" parameter="{index}" symbol="gain"/>
+<Controllable name="mute" id="32" parameter="4" symbol="mute"/>
+</Processor>
+</Route>
+</Routes></Session>"#
+        )
+    }
+
+    #[test]
+    fn divergent() {
+        let (a, b) = (snapshot(3), snapshot(2));
+        let a = roxmltree::Document::parse(&a).unwrap();
+        let b = roxmltree::Document::parse(&b).unwrap();
+        let mut consistency = Consistency::new();
+        consistency.add("A.ardour", &a);
+        consistency.add("B.ardour", &b);
+        assert_eq!(consistency.divergences(), 1);
+        assert_eq!(
+            consistency.to_string(),
+            "processor 30 (urn:amp), port \"gain\":\n  index 2: B.ardour\n  \
+             index 3: A.ardour\n",
+        );
+    }
+
+    #[test]
+    fn consistent() {
+        let (a, b) = (snapshot(3), snapshot(3));
+        let a = roxmltree::Document::parse(&a).unwrap();
+        let b = roxmltree::Document::parse(&b).unwrap();
+        let mut consistency = Consistency::new();
+        consistency.add("A.ardour", &a);
+        consistency.add("B.ardour", &b);
+        assert_eq!(consistency.divergences(), 0);
+        assert_eq!(consistency.to_string(), "");
+    }
+}
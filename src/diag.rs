@@ -0,0 +1,807 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Collection and display of warnings and other messages.
+//!
+//! All warnings are sent to a single sink with [`warn`]. Depending on the
+//! [`Mode`], they are printed immediately or buffered and printed grouped by
+//! plugin by [`flush`]. Other messages are printed with [`emit`], and the
+//! progress of long runs with [`progress`]. Whether messages are printed
+//! depends on the [`Verbosity`], and with [`Format::Json`], each is printed
+//! immediately as a line of JSON.
+
+use super::color::{self, Color, Stream};
+use super::json::Str;
+use super::lines::Position;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::io::IsTerminal;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Kind {
+    MissingPlugin,
+    MissingPort,
+    UnparsableIndex,
+    MissingSymbol,
+    MissingUri,
+    InvalidUri,
+    InvalidSymbol,
+    ConflictingMapping,
+    MissingMapping,
+    DuplicateProcessor,
+    UnreadableBundle,
+    #[cfg(feature = "http")]
+    Backend,
+    Cache,
+}
+
+impl Kind {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"missing-plugin" => Self::MissingPlugin,
+            b"missing-port" => Self::MissingPort,
+            b"unparsable-index" => Self::UnparsableIndex,
+            b"missing-symbol" => Self::MissingSymbol,
+            b"missing-uri" => Self::MissingUri,
+            b"invalid-uri" => Self::InvalidUri,
+            b"invalid-symbol" => Self::InvalidSymbol,
+            b"conflicting-mapping" => Self::ConflictingMapping,
+            b"missing-mapping" => Self::MissingMapping,
+            b"duplicate-processor" => Self::DuplicateProcessor,
+            b"unreadable-bundle" => Self::UnreadableBundle,
+            #[cfg(feature = "http")]
+            b"backend" => Self::Backend,
+            b"cache" => Self::Cache,
+            _ => return None,
+        })
+    }
+
+    /// The color warnings of this kind are highlighted in, if any.
+    fn color(self) -> Option<Color> {
+        match self {
+            Self::MissingPlugin => Some(Color::Red),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::MissingPlugin => "missing-plugin",
+            Self::MissingPort => "missing-port",
+            Self::UnparsableIndex => "unparsable-index",
+            Self::MissingSymbol => "missing-symbol",
+            Self::MissingUri => "missing-uri",
+            Self::InvalidUri => "invalid-uri",
+            Self::InvalidSymbol => "invalid-symbol",
+            Self::ConflictingMapping => "conflicting-mapping",
+            Self::MissingMapping => "missing-mapping",
+            Self::DuplicateProcessor => "duplicate-processor",
+            Self::UnreadableBundle => "unreadable-bundle",
+            #[cfg(feature = "http")]
+            Self::Backend => "backend",
+            Self::Cache => "cache",
+        }
+    }
+
+    /// A short description of the warning, used when grouping.
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::MissingPlugin => "could not find plugin",
+            Self::MissingPort => "could not find port",
+            Self::UnparsableIndex => "could not parse parameter index",
+            Self::MissingSymbol => "missing port symbol in controllable",
+            Self::MissingUri => "missing uri for processor",
+            Self::InvalidUri => "invalid uri",
+            Self::InvalidSymbol => "invalid symbol",
+            Self::ConflictingMapping => "conflicting index mapping",
+            Self::MissingMapping => "no usable index map entry",
+            Self::DuplicateProcessor => "duplicate processor id",
+            Self::UnreadableBundle => "could not read plugin bundle",
+            #[cfg(feature = "http")]
+            Self::Backend => "could not query backend",
+            Self::Cache => "could not write to cache",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Warning {
+    pub kind: Kind,
+    /// The full warning message.
+    pub message: String,
+    /// The plugin the warning concerns.
+    pub uri: Option<String>,
+    /// The port symbol the warning concerns.
+    pub symbol: Option<String>,
+    /// The name of the processor (plugin instance) the warning concerns.
+    pub processor_name: Option<String>,
+    /// The `id` attribute of the processor the warning concerns.
+    pub processor_id: Option<String>,
+    /// The name of the route (track or bus) containing the processor the
+    /// warning concerns.
+    pub route: Option<String>,
+    /// The byte offset in the session the warning concerns.
+    pub offset: Option<usize>,
+    /// The line and column of `offset`.
+    pub position: Option<Position>,
+    /// A suggestion for resolving the warning, such as `did you mean ...?`.
+    pub hint: Option<String>,
+}
+
+impl Warning {
+    pub fn new(kind: Kind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            uri: None,
+            symbol: None,
+            processor_name: None,
+            processor_id: None,
+            route: None,
+            offset: None,
+            position: None,
+            hint: None,
+        }
+    }
+
+    pub fn uri(mut self, uri: &str) -> Self {
+        self.uri = Some(uri.to_owned());
+        self
+    }
+
+    pub fn symbol(mut self, symbol: &str) -> Self {
+        self.symbol = Some(symbol.to_owned());
+        self
+    }
+
+    pub fn processor_name(mut self, name: &str) -> Self {
+        self.processor_name = Some(name.to_owned());
+        self
+    }
+
+    pub fn processor_id(mut self, id: &str) -> Self {
+        self.processor_id = Some(id.to_owned());
+        self
+    }
+
+    pub fn route(mut self, route: &str) -> Self {
+        self.route = Some(route.to_owned());
+        self
+    }
+
+    /// Sets the warning's location: the byte `offset` in the session and
+    /// its `position`.
+    pub fn at(mut self, offset: usize, position: Position) -> Self {
+        self.offset = Some(offset);
+        self.position = Some(position);
+        self
+    }
+
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Mode {
+    /// Print warnings as soon as they occur.
+    Immediate,
+    /// Buffer warnings and print them grouped by plugin in [`flush`].
+    #[default]
+    Grouped,
+}
+
+/// How warnings of a [`Kind`] are treated, set with [`set_lint`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Lint {
+    /// Ignore the warnings.
+    Allow,
+    /// Print the warnings.
+    #[default]
+    Warn,
+    /// Print the warnings as errors, and count them with [`counts`].
+    Deny,
+}
+
+/// How messages are printed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Format {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// One JSON object per line, for other programs to parse.
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"text" => Self::Text,
+            b"json" => Self::Json,
+            _ => return None,
+        })
+    }
+}
+
+/// How much is printed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Verbosity {
+    /// Print only errors.
+    Quiet,
+    /// Print errors, warnings, and informational messages.
+    #[default]
+    Normal,
+    /// Also print debugging messages, such as every resolved parameter.
+    Verbose,
+}
+
+/// The level of a message printed with [`emit`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Info => "info",
+            Self::Debug => "debug",
+        }
+    }
+
+    /// The lowest verbosity at which messages of this level are printed.
+    fn verbosity(self) -> Verbosity {
+        match self {
+            Self::Error => Verbosity::Quiet,
+            Self::Warning | Self::Info => Verbosity::Normal,
+            Self::Debug => Verbosity::Verbose,
+        }
+    }
+
+    fn prefix(self) -> Option<(&'static str, Color)> {
+        match self {
+            Self::Error => Some(("error:", Color::Red)),
+            Self::Warning => Some(("warning:", Color::Yellow)),
+            Self::Info | Self::Debug => None,
+        }
+    }
+}
+
+struct Sink {
+    mode: Mode,
+    format: Format,
+    verbosity: Verbosity,
+    lints: BTreeMap<Kind, Lint>,
+    warnings: Vec<Warning>,
+    /// If set, errors are kept here instead of printed, for [`take_errors`].
+    errors: Option<Vec<String>>,
+    /// The number of warnings emitted so far, including those printed or
+    /// taken, but not those allowed.
+    count: usize,
+    /// The number of denied warnings emitted so far.
+    denied: usize,
+    /// The number of warnings of each kind printed before further ones are
+    /// suppressed.
+    max: Option<usize>,
+    /// The number of warnings of each kind printed so far.
+    shown: BTreeMap<Kind, usize>,
+    /// The number of warnings of each kind suppressed because of `max`.
+    hidden: BTreeMap<Kind, usize>,
+    /// Whether a progress message is shown on the last line of the
+    /// terminal, which is cleared before anything else is printed.
+    status: bool,
+}
+
+static SINK: Mutex<Sink> = Mutex::new(Sink {
+    mode: Mode::Grouped,
+    format: Format::Text,
+    verbosity: Verbosity::Normal,
+    lints: BTreeMap::new(),
+    warnings: Vec::new(),
+    errors: None,
+    count: 0,
+    denied: 0,
+    max: None,
+    shown: BTreeMap::new(),
+    hidden: BTreeMap::new(),
+    status: false,
+});
+
+impl Sink {
+    /// Returns whether another warning of `kind` can be printed, counting it
+    /// as printed if so and as suppressed otherwise.
+    fn show(&mut self, kind: Kind) -> bool {
+        let shown = self.shown.entry(kind).or_default();
+        if self.max.is_some_and(|max| *shown >= max) {
+            *self.hidden.entry(kind).or_default() += 1;
+            return false;
+        }
+        *shown += 1;
+        true
+    }
+
+    /// Clears the progress message shown by [`progress`], if any.
+    fn clear_status(&mut self) {
+        if std::mem::take(&mut self.status) {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+fn sink() -> std::sync::MutexGuard<'static, Sink> {
+    SINK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn set_mode(mode: Mode) {
+    sink().mode = mode;
+}
+
+pub fn set_lint(kind: Kind, lint: Lint) {
+    sink().lints.insert(kind, lint);
+}
+
+/// Prints at most `max` warnings of each kind; the number suppressed is
+/// printed by [`flush`].
+pub fn set_max_warnings(max: usize) {
+    sink().max = Some(max);
+}
+
+pub fn set_format(format: Format) {
+    sink().format = format;
+}
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    sink().verbosity = verbosity;
+}
+
+/// Returns whether messages may contain colors: they're printed as text to
+/// standard error, and [`color::enabled`] allows it.
+pub fn color() -> bool {
+    sink().format == Format::Text && color::enabled(Stream::Stderr)
+}
+
+/// Returns whether messages of `level` are printed.
+pub fn enabled(level: Level) -> bool {
+    sink().verbosity >= level.verbosity()
+}
+
+/// Prints `message` to standard error, prefixed according to its `level`,
+/// unless the verbosity is too low.
+pub fn emit(level: Level, message: impl Display) {
+    let format = {
+        let mut sink = sink();
+        if sink.verbosity < level.verbosity() {
+            return;
+        }
+        if level == Level::Error {
+            if let Some(errors) = &mut sink.errors {
+                errors.push(message.to_string());
+                return;
+            }
+        }
+        sink.clear_status();
+        sink.format
+    };
+    if format == Format::Json {
+        let message = message.to_string();
+        eprintln!("{}", JsonLine::new(level, &message));
+        return;
+    }
+    match level.prefix() {
+        Some((prefix, c)) => {
+            let prefix = c.paint(prefix, color::enabled(Stream::Stderr));
+            eprintln!("{prefix} {message}");
+        }
+        None => eprintln!("{message}"),
+    }
+}
+
+pub fn warn(warning: Warning) {
+    let mut sink = sink();
+    let lint = sink.lints.get(&warning.kind).copied().unwrap_or_default();
+    if lint == Lint::Allow {
+        return;
+    }
+    sink.count += 1;
+    if lint == Lint::Deny {
+        sink.denied += 1;
+        sink.clear_status();
+        match sink.format {
+            Format::Json => {
+                eprintln!("{}", JsonLine::warning(&warning, Level::Error));
+            }
+            Format::Text => print_warning(&warning, Level::Error),
+        }
+        return;
+    }
+    if sink.verbosity < Verbosity::Normal {
+        if sink.mode == Mode::Grouped {
+            sink.warnings.push(warning);
+        }
+        return;
+    }
+    let immediate =
+        sink.mode == Mode::Immediate || sink.format == Format::Json;
+    if immediate && !sink.show(warning.kind) {
+        return;
+    }
+    if immediate {
+        sink.clear_status();
+    }
+    match (sink.format, sink.mode) {
+        (Format::Json, _) => {
+            eprintln!("{}", JsonLine::warning(&warning, Level::Warning));
+        }
+        (Format::Text, Mode::Immediate) => {
+            print_warning(&warning, Level::Warning);
+        }
+        (Format::Text, Mode::Grouped) => sink.warnings.push(warning),
+    }
+}
+
+/// Prints `warning` as a message of `level`, which is an error if the
+/// warning was denied.
+fn print_warning(warning: &Warning, level: Level) {
+    let color = color::enabled(Stream::Stderr);
+    let (prefix, prefix_color) = level.prefix().unwrap();
+    eprint!(
+        "{} {}{}",
+        prefix_color.paint(prefix, color),
+        color::paint(warning.kind.color(), &warning.message, color),
+        Instance::of(warning),
+    );
+    match &warning.hint {
+        Some(hint) => eprintln!(" ({hint})"),
+        None => eprintln!(),
+    }
+}
+
+/// The plugin instance a warning concerns, displayed as, e.g.,
+/// ` for "Comp" (id 123) on route "Drums"`, or as nothing if it is unknown.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+struct Instance<'a> {
+    name: Option<&'a str>,
+    id: Option<&'a str>,
+    route: Option<&'a str>,
+}
+
+impl<'a> Instance<'a> {
+    fn of(warning: &'a Warning) -> Self {
+        Self {
+            name: warning.processor_name.as_deref(),
+            id: warning.processor_id.as_deref(),
+            route: warning.route.as_deref(),
+        }
+    }
+}
+
+impl Instance<'_> {
+    /// Writes the instance, with `prefix` before its name or id.
+    fn write(&self, f: &mut fmt::Formatter<'_>, prefix: &str) -> fmt::Result {
+        match (self.name, self.id) {
+            (Some(name), Some(id)) => {
+                write!(f, "{prefix}\"{}\" (id {id})", name.escape_default())?;
+            }
+            (Some(name), None) => {
+                write!(f, "{prefix}\"{}\"", name.escape_default())?;
+            }
+            (None, Some(id)) => write!(f, "{prefix}processor {id}")?,
+            (None, None) => {}
+        }
+        match self.route {
+            Some(route) => {
+                write!(f, " on route \"{}\"", route.escape_default())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for Instance<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, " for ")
+    }
+}
+
+/// The plugin instances that a group of warnings concern, displayed as the
+/// single [`Instance`], or as, e.g., ` on 2 processors: "Comp" (id 12) on
+/// route "Drums"; "Comp" (id 15) on route "Bass"`. Unknown instances are
+/// left out.
+#[derive(Debug, Default)]
+struct Instances<'a>(BTreeSet<Instance<'a>>);
+
+impl<'a> Instances<'a> {
+    fn insert(&mut self, instance: Instance<'a>) {
+        if instance != Instance::default() {
+            self.0.insert(instance);
+        }
+    }
+}
+
+impl Display for Instances<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = self.0.len();
+        if n == 1 {
+            return write!(f, "{}", self.0.first().unwrap());
+        } else if n > 1 {
+            write!(f, " on {n} processors:")?;
+        }
+        for (i, instance) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ";")?;
+            }
+            instance.write(f, " ")?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows the progress of a long run, such as the file being processed. On a
+/// terminal, `message` replaces the previous progress message on the last
+/// line until something else is printed or [`clear_progress`] is called;
+/// otherwise, it is printed as a line of its own.
+pub fn progress(message: impl Display) {
+    let mut sink = sink();
+    if sink.verbosity < Verbosity::Normal {
+        return;
+    }
+    sink.clear_status();
+    if sink.format == Format::Json {
+        let message = message.to_string();
+        eprintln!("{}", JsonLine::new(Level::Info, &message));
+    } else if std::io::stderr().is_terminal() {
+        eprint!("{message}");
+        sink.status = true;
+    } else {
+        eprintln!("{message}");
+    }
+}
+
+/// Clears the progress message shown on the terminal by [`progress`].
+pub fn clear_progress() {
+    sink().clear_status();
+}
+
+/// Returns the number of warnings emitted so far, not including those
+/// allowed.
+pub fn count() -> usize {
+    sink().count
+}
+
+/// The numbers of warnings emitted up to some point, returned by [`counts`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Counts {
+    /// The number of warnings, including denied ones but not allowed ones.
+    pub warnings: usize,
+    /// The number of denied warnings.
+    pub denied: usize,
+}
+
+/// Returns the numbers of warnings emitted so far.
+pub fn counts() -> Counts {
+    let sink = sink();
+    Counts {
+        warnings: sink.count,
+        denied: sink.denied,
+    }
+}
+
+/// Prints all buffered warnings, grouped by plugin, and the number of
+/// warnings of each kind that were suppressed.
+pub fn flush() {
+    let warnings = take();
+    if !enabled(Level::Warning) {
+        return;
+    }
+    let mut sink = sink();
+    sink.clear_status();
+    // Warnings of the same kind about the same port are listed on one line,
+    // with the instances they concern.
+    let mut plugins = BTreeMap::<_, BTreeMap<_, (usize, Instances)>>::new();
+    let mut hints = BTreeMap::<_, BTreeSet<_>>::new();
+    let mut other = Vec::new();
+    for warning in &warnings {
+        let Some(uri) = &warning.uri else {
+            other.push(warning);
+            continue;
+        };
+        let key = (warning.kind, warning.symbol.as_deref());
+        let (count, instances) =
+            plugins.entry(uri).or_default().entry(key).or_default();
+        *count += 1;
+        instances.insert(Instance::of(warning));
+        if let Some(hint) = &warning.hint {
+            hints.entry(uri).or_default().insert(hint.as_str());
+        }
+    }
+    // Each line counts as one warning. Lines over the limit are removed
+    // before printing so that no plugin is listed without any lines.
+    for counts in plugins.values_mut() {
+        counts.retain(|&(kind, _), _| sink.show(kind));
+    }
+    plugins.retain(|_, counts| !counts.is_empty());
+    other.retain(|warning| sink.show(warning.kind));
+    let color = color::enabled(Stream::Stderr);
+    for (uri, counts) in &plugins {
+        let prefix = Color::Yellow.paint("warning:", color);
+        let missing = counts.keys().any(|&(k, _)| k == Kind::MissingPlugin);
+        let hints = hints.get(uri);
+        let uri = Color::Red.paint(uri, color && missing);
+        eprintln!("{prefix} {uri}:");
+        for (&(kind, symbol), (count, instances)) in counts {
+            let desc = color::paint(kind.color(), kind.description(), color);
+            match symbol {
+                Some(s) => eprint!("  {desc} \"{}\"", s.escape_default()),
+                None => eprint!("  {desc}"),
+            }
+            eprint!("{instances}");
+            // Repeated warnings about the same instance are counted.
+            if *count > instances.0.len().max(1) {
+                eprint!(" ({count} times)");
+            }
+            eprintln!();
+        }
+        for hint in hints.into_iter().flatten() {
+            eprintln!("  {hint}");
+        }
+    }
+    for warning in other {
+        print_warning(warning, Level::Warning);
+    }
+    for (kind, n) in std::mem::take(&mut sink.hidden) {
+        let message = format!("... and {n} more {} warnings", kind.as_str());
+        match sink.format {
+            Format::Json => {
+                let mut line = JsonLine::new(Level::Warning, &message);
+                line.kind = Some(kind);
+                eprintln!("{line}");
+            }
+            Format::Text => {
+                let prefix = Color::Yellow.paint("warning:", color);
+                eprintln!("{prefix} {message}");
+            }
+        }
+    }
+}
+
+/// Removes and returns all buffered warnings.
+pub fn take() -> Vec<Warning> {
+    std::mem::take(&mut sink().warnings)
+}
+
+/// Keeps errors passed to [`emit`] instead of printing them, until they're
+/// removed with [`take_errors`].
+pub fn capture_errors() {
+    sink().errors.get_or_insert_with(Vec::new);
+}
+
+/// Removes and returns the errors kept since [`capture_errors`].
+pub fn take_errors() -> Vec<String> {
+    sink().errors.as_mut().map(std::mem::take).unwrap_or_default()
+}
+
+/// Displays warnings and errors as a JSON array, each in the same form as
+/// the lines printed with [`Format::Json`].
+pub struct Json<'a> {
+    pub warnings: &'a [Warning],
+    pub errors: &'a [String],
+}
+
+impl Display for Json<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let warnings =
+            self.warnings.iter().map(|w| JsonLine::warning(w, Level::Warning));
+        let errors =
+            self.errors.iter().map(|e| JsonLine::new(Level::Error, e));
+        write!(f, "[")?;
+        for (i, line) in warnings.chain(errors).enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "\n  {line}")?;
+        }
+        if !self.warnings.is_empty() || !self.errors.is_empty() {
+            writeln!(f)?;
+        }
+        writeln!(f, "]")
+    }
+}
+
+/// Displays a message as a single line of JSON, for [`Format::Json`].
+struct JsonLine<'a> {
+    level: Level,
+    /// The warning's kind, if the message is a warning.
+    kind: Option<Kind>,
+    message: &'a str,
+    uri: Option<&'a str>,
+    symbol: Option<&'a str>,
+    instance: Instance<'a>,
+    offset: Option<usize>,
+    position: Option<Position>,
+    hint: Option<&'a str>,
+}
+
+impl<'a> JsonLine<'a> {
+    fn new(level: Level, message: &'a str) -> Self {
+        Self {
+            level,
+            kind: None,
+            message,
+            uri: None,
+            symbol: None,
+            instance: Instance::default(),
+            offset: None,
+            position: None,
+            hint: None,
+        }
+    }
+
+    fn warning(warning: &'a Warning, level: Level) -> Self {
+        Self {
+            kind: Some(warning.kind),
+            uri: warning.uri.as_deref(),
+            symbol: warning.symbol.as_deref(),
+            instance: Instance::of(warning),
+            offset: warning.offset,
+            position: warning.position,
+            hint: warning.hint.as_deref(),
+            ..Self::new(level, &warning.message)
+        }
+    }
+}
+
+impl Display for JsonLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"severity\": {}", Str(self.level.as_str()))?;
+        let code = self.kind.map(Kind::as_str);
+        for (key, value) in [
+            ("code", code),
+            ("message", Some(self.message)),
+            ("uri", self.uri),
+            ("symbol", self.symbol),
+            ("processor_name", self.instance.name),
+            ("processor_id", self.instance.id),
+            ("route", self.instance.route),
+            ("hint", self.hint),
+        ] {
+            match value {
+                Some(v) => write!(f, ", \"{key}\": {}", Str(v))?,
+                None => write!(f, ", \"{key}\": null")?,
+            }
+        }
+        let line = self.position.map(|p| p.line);
+        let column = self.position.map(|p| p.column);
+        for (key, value) in
+            [("line", line), ("column", column), ("offset", self.offset)]
+        {
+            match value {
+                Some(n) => write!(f, ", \"{key}\": {n}")?,
+                None => write!(f, ", \"{key}\": null")?,
+            }
+        }
+        write!(f, "}}")
+    }
+}
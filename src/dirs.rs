@@ -0,0 +1,90 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Locations of files the program stores outside of session directories.
+//!
+//! Paths follow the XDG Base Directory Specification, unless a state
+//! directory is given explicitly, in which case everything is stored in
+//! subdirectories of it.
+
+use std::path::{Path, PathBuf};
+
+const APP_NAME: &str = "fix-ardour-lv2-index";
+
+#[derive(Debug)]
+pub struct Dirs {
+    cache: Option<PathBuf>,
+    config: Option<PathBuf>,
+}
+
+/// Gets an XDG base directory from `var`, falling back to `default` (relative
+/// to the home directory). Relative paths in `var` are ignored, as required
+/// by the specification.
+fn xdg_dir(var: &str, default: &str) -> Option<PathBuf> {
+    let base = std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| {
+            let home = std::env::var_os("HOME").filter(|h| !h.is_empty())?;
+            Some(Path::new(&home).join(default))
+        })?;
+    Some(base.join(APP_NAME))
+}
+
+/// Returns a stable, filename-safe hash of `parts` (FNV-1a).
+pub fn hash_name<'a, I>(parts: I) -> String
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for &b in part.iter().chain([&0]) {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+impl Dirs {
+    pub fn new(state_dir: Option<&Path>) -> Self {
+        if let Some(dir) = state_dir {
+            return Self {
+                cache: Some(dir.join("cache")),
+                config: Some(dir.join("config")),
+            };
+        }
+        Self {
+            cache: xdg_dir("XDG_CACHE_HOME", ".cache"),
+            config: xdg_dir("XDG_CONFIG_HOME", ".config"),
+        }
+    }
+
+    /// The directory for cached data, or [`None`] if it couldn't be
+    /// determined.
+    pub fn cache(&self) -> Option<&Path> {
+        self.cache.as_deref()
+    }
+
+    /// The directory containing the configuration file, or [`None`] if it
+    /// couldn't be determined.
+    pub fn config(&self) -> Option<&Path> {
+        self.config.as_deref()
+    }
+}
@@ -0,0 +1,77 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Matching of plugin names given on the command line.
+
+/// A case-insensitive pattern for plugin names. Patterns containing `*` or
+/// `?` are globs matched against the whole name; other patterns match any
+/// name that contains them.
+#[derive(Clone, Debug)]
+pub struct NameFilter {
+    pattern: Vec<char>,
+}
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any sequence
+/// of characters and `?` matches any one character.
+fn glob(pattern: &[char], name: &[char]) -> bool {
+    // The position after the last `*` seen, and the name position it was
+    // tried at, for backtracking.
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => {
+                let Some((sp, sn)) = star else {
+                    return false;
+                };
+                p = sp;
+                n = sn + 1;
+                star = Some((sp, n));
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl NameFilter {
+    pub fn new(pattern: &str) -> Self {
+        let mut pattern: Vec<_> =
+            pattern.chars().flat_map(char::to_lowercase).collect();
+        if !pattern.iter().any(|&c| c == '*' || c == '?') {
+            pattern.insert(0, '*');
+            pattern.push('*');
+        }
+        Self {
+            pattern,
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name: Vec<_> = name.chars().flat_map(char::to_lowercase).collect();
+        glob(&self.pattern, &name)
+    }
+}
@@ -0,0 +1,83 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Advisory locking of sessions being fixed in place, so that concurrent
+//! runs of this program don't interleave their backups and writes.
+
+use std::ffi::c_int;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const LOCK_EX: c_int = 2;
+const LOCK_NB: c_int = 4;
+
+extern "C" {
+    fn flock(fd: c_int, operation: c_int) -> c_int;
+}
+
+fn lock(file: &File, operation: c_int) -> io::Result<()> {
+    loop {
+        // SAFETY: `flock` takes a file descriptor and an operation, and
+        // `file` is open for the duration of the call.
+        if unsafe { flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// An exclusive lock on a session file, released when dropped.
+pub struct SessionLock {
+    _file: File,
+}
+
+impl SessionLock {
+    /// Locks the file at `path`, calling `on_wait` and then waiting if
+    /// another process holds the lock. If the file was replaced in the
+    /// meantime (as when the other process renamed it to make a backup),
+    /// the new file is locked instead.
+    pub fn acquire(
+        path: &Path,
+        mut on_wait: impl FnMut(),
+    ) -> io::Result<Self> {
+        loop {
+            let file = File::open(path)?;
+            if let Err(e) = lock(&file, LOCK_EX | LOCK_NB) {
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    return Err(e);
+                }
+                on_wait();
+                lock(&file, LOCK_EX)?;
+            }
+            let locked = file.metadata()?;
+            let current = std::fs::metadata(path)?;
+            if (locked.dev(), locked.ino()) == (current.dev(), current.ino()) {
+                return Ok(Self {
+                    _file: file,
+                });
+            }
+        }
+    }
+}
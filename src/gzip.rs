@@ -0,0 +1,148 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Transparent handling of gzip-compressed session files.
+//!
+//! Compression is only supported with the `gzip` feature; without it,
+//! compressed sessions are detected so that a clear error can be shown.
+
+use fix_ardour_lv2_index::patch::PatchedSession;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// The bytes every gzip stream starts with.
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[cfg(not(feature = "gzip"))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "gzip support is not enabled (build with `--features gzip`)",
+    )
+}
+
+#[cfg(feature = "gzip")]
+fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut xml = Vec::new();
+    flate2::read::MultiGzDecoder::new(data).read_to_end(&mut xml)?;
+    Ok(xml)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn decompress(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(unsupported())
+}
+
+/// Reads a session from `reader`, decompressing it if it's gzip-compressed.
+/// Returns the session and whether it was compressed.
+pub fn read<R: Read>(mut reader: R) -> io::Result<(String, bool)> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let compressed = data.starts_with(&MAGIC);
+    if compressed {
+        data = decompress(&data)?;
+    }
+    let xml = String::from_utf8(data).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        )
+    })?;
+    Ok((xml, compressed))
+}
+
+/// Reads the session file at `path` like [`read`].
+pub fn read_file(path: &Path) -> io::Result<(String, bool)> {
+    read(std::fs::File::open(path)?)
+}
+
+/// Writes `patched` to `writer`, gzip-compressed if `compress` is true.
+#[cfg(feature = "gzip")]
+pub fn write<W: Write>(
+    patched: &PatchedSession<'_>,
+    writer: W,
+    compress: bool,
+) -> io::Result<()> {
+    if !compress {
+        return patched.write_to(writer);
+    }
+    let mut encoder =
+        flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    patched.write_to(&mut encoder)?;
+    encoder.finish()?.flush()
+}
+
+/// Writes `patched` to `writer`, gzip-compressed if `compress` is true.
+#[cfg(not(feature = "gzip"))]
+pub fn write<W: Write>(
+    patched: &PatchedSession<'_>,
+    writer: W,
+    compress: bool,
+) -> io::Result<()> {
+    if compress {
+        return Err(unsupported());
+    }
+    patched.write_to(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fix_ardour_lv2_index::index_map::IndexMap;
+    use fix_ardour_lv2_index::patch::{self, Resolver, Source};
+
+    const XML: &str = "<Session version=\"7003\" name=\"S\"/>\n";
+
+    /// Writes [`XML`], which needs no changes, with [`write`].
+    fn write_xml(compress: bool) -> io::Result<Vec<u8>> {
+        let mut resolver =
+            Resolver::new(Source::IndexMap(IndexMap::default()));
+        let patched =
+            patch::patch(XML, &mut resolver, patch::Options::default())
+                .unwrap();
+        let mut data = Vec::new();
+        write(&patched, &mut data, compress)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn uncompressed() {
+        let data = write_xml(false).unwrap();
+        assert_eq!(data, XML.as_bytes());
+        assert_eq!(read(&data[..]).unwrap(), (XML.to_owned(), false));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed() {
+        let data = write_xml(true).unwrap();
+        assert!(data.starts_with(&MAGIC));
+        assert_eq!(read(&data[..]).unwrap(), (XML.to_owned(), true));
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn compressed_unsupported() {
+        let error = write_xml(true).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        let data = [&MAGIC[..], b"\0\0\0\0"].concat();
+        let error = read(&data[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
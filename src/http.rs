@@ -0,0 +1,218 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Retrieval of port metadata from a remote service.
+//!
+//! For a plugin URI, the service is queried with `GET <base>/plugin?uri=<uri>`
+//! and is expected to respond with a JSON object of the form
+//! `{"num_ports": 12, "ports": {"gain": 3, ...}}`, or with status 404 if the
+//! plugin isn't installed.
+//!
+//! Successful responses are cached on disk for [`CACHE_LIFETIME`].
+
+use super::diag::{self, Kind, Warning};
+use super::dirs::hash_name;
+use super::json;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const TIMEOUT: Duration = Duration::from_secs(30);
+const CACHE_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+pub struct PortTable {
+    num_ports: u32,
+    ports: HashMap<String, u32>,
+}
+
+impl PortTable {
+    pub fn num_ports(&self) -> u32 {
+        self.num_ports
+    }
+
+    pub fn port_index(&self, symbol: &str) -> Option<u32> {
+        self.ports.get(symbol).copied()
+    }
+
+    fn parse(s: &str) -> Result<Self, Error> {
+        let value = json::parse(s).map_err(Error::Json)?;
+        let Some(num_ports) = value.get("num_ports").and_then(|v| v.as_u32())
+        else {
+            return Err(Error::Format("missing `num_ports`"));
+        };
+        let Some(ports) = value.get("ports").and_then(|v| v.as_object())
+        else {
+            return Err(Error::Format("missing `ports`"));
+        };
+        let ports = ports
+            .iter()
+            .map(|(symbol, index)| match index.as_u32() {
+                Some(i) => Ok((symbol.clone(), i)),
+                None => Err(Error::Format("port index is not an integer")),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            num_ports,
+            ports,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Error {
+    Http(Box<ureq::Error>),
+    Io(std::io::Error),
+    Json(json::Error),
+    Format(&'static str),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "could not read response: {e}"),
+            Self::Json(e) => write!(f, "invalid json in response: {e}"),
+            Self::Format(s) => write!(f, "invalid response: {s}"),
+        }
+    }
+}
+
+fn read_cached(path: &Path) -> Option<String> {
+    let age = std::fs::metadata(path).ok()?.modified().ok()?.elapsed().ok()?;
+    if age > CACHE_LIFETIME {
+        return None;
+    }
+    std::fs::read_to_string(path).ok()
+}
+
+fn write_cached(path: &Path, body: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, body)
+}
+
+pub struct Client {
+    base: String,
+    agent: ureq::Agent,
+    cache: HashMap<String, Option<PortTable>>,
+    cache_dir: Option<PathBuf>,
+    /// Whether responses cached on disk are ignored, so that they're
+    /// replaced.
+    refresh_all: bool,
+}
+
+impl Client {
+    /// Creates a client for the service at `base`. If `cache_dir` is
+    /// provided, responses are cached in that directory. If `refresh_all`
+    /// is true, existing responses there are ignored and replaced.
+    pub fn new(
+        base: &str,
+        cache_dir: Option<PathBuf>,
+        refresh_all: bool,
+    ) -> Self {
+        Self {
+            base: base.trim_end_matches('/').to_owned(),
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(CONNECT_TIMEOUT)
+                .timeout(TIMEOUT)
+                .build(),
+            cache: HashMap::new(),
+            cache_dir,
+            refresh_all,
+        }
+    }
+
+    fn cache_path(&self, uri: &str) -> Option<PathBuf> {
+        self.cache_dir.as_ref().map(|dir| {
+            dir.join(hash_name([self.base.as_bytes(), uri.as_bytes()]))
+        })
+    }
+
+    fn fetch(&self, uri: &str) -> Result<Option<PortTable>, Error> {
+        let cache_path = self.cache_path(uri);
+        let cached = cache_path.as_deref().filter(|_| !self.refresh_all);
+        if let Some(body) = cached.and_then(read_cached) {
+            if let Ok(table) = PortTable::parse(&body) {
+                return Ok(Some(table));
+            }
+        }
+        let response = match self
+            .agent
+            .get(&format!("{}/plugin", self.base))
+            .query("uri", uri)
+            .call()
+        {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(Error::Http(Box::new(e))),
+        };
+        let body = response.into_string().map_err(Error::Io)?;
+        let table = PortTable::parse(&body)?;
+        if let Some(path) = &cache_path {
+            if let Err(e) = write_cached(path, &body) {
+                diag::warn(Warning::new(
+                    Kind::Cache,
+                    format!("could not write to cache: {e}"),
+                ));
+            }
+        }
+        Ok(Some(table))
+    }
+
+    /// Discards the cached response for the plugin `uri`, so that it is
+    /// requested again.
+    pub fn refresh(&mut self, uri: &str) {
+        self.cache.remove(uri);
+        let Some(path) = self.cache_path(uri) else {
+            return;
+        };
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                diag::warn(Warning::new(
+                    Kind::Cache,
+                    format!("could not remove cached response: {e}"),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    /// Gets the port table for the plugin `uri`. Responses (including
+    /// failures) are cached, so each plugin is requested at most once.
+    pub fn get(&mut self, uri: &str) -> Option<&PortTable> {
+        if !self.cache.contains_key(uri) {
+            let table = self.fetch(uri).unwrap_or_else(|e| {
+                diag::warn(
+                    Warning::new(
+                        Kind::Backend,
+                        format!("could not query backend for {uri}: {e}"),
+                    )
+                    .uri(uri),
+                );
+                None
+            });
+            self.cache.insert(uri.to_owned(), table);
+        }
+        self.cache[uri].as_ref()
+    }
+}
@@ -0,0 +1,172 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::diag::{self, Kind, Warning};
+use super::json::{self, Str};
+use std::collections::btree_map::{self, BTreeMap};
+use std::fmt::{self, Display};
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mapping {
+    pub symbol: String,
+    pub new_index: u32,
+}
+
+/// The concrete old→new parameter index mapping applied to a session,
+/// organized per plugin URI.
+#[derive(Debug, Default)]
+pub struct IndexMap {
+    plugins: BTreeMap<String, BTreeMap<u32, Mapping>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(json::Error),
+    Format(&'static str),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Json(e) => write!(f, "invalid json: {e}"),
+            Self::Format(s) => write!(f, "invalid index map: {s}"),
+        }
+    }
+}
+
+impl IndexMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, uri: &str, old_index: u32) -> Option<&Mapping> {
+        self.plugins.get(uri)?.get(&old_index)
+    }
+
+    /// Records a mapping. If a different mapping for the same plugin and
+    /// old index already exists, it is kept and returned as an error.
+    pub fn insert(
+        &mut self,
+        uri: &str,
+        old_index: u32,
+        mapping: Mapping,
+    ) -> Result<(), &Mapping> {
+        if !self.plugins.contains_key(uri) {
+            self.plugins.insert(uri.to_owned(), BTreeMap::new());
+        }
+        let plugin = self.plugins.get_mut(uri).unwrap();
+        match plugin.entry(old_index) {
+            btree_map::Entry::Vacant(ent) => {
+                ent.insert(mapping);
+                Ok(())
+            }
+            btree_map::Entry::Occupied(ent) if *ent.get() == mapping => Ok(()),
+            btree_map::Entry::Occupied(ent) => Err(ent.into_mut()),
+        }
+    }
+
+    /// Merges `other` into `self`, warning about conflicting mappings.
+    pub fn merge(&mut self, other: Self) {
+        for (uri, plugin) in other.plugins {
+            for (old_index, mapping) in plugin {
+                let new_index = mapping.new_index;
+                if let Err(existing) = self.insert(&uri, old_index, mapping) {
+                    diag::warn(
+                        Warning::new(
+                            Kind::ConflictingMapping,
+                            format!(
+                                "conflicting index map entries for index \
+                                 {old_index} of {uri}: {} and {new_index}",
+                                existing.new_index,
+                            ),
+                        )
+                        .uri(&uri),
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let value = json::parse(s).map_err(Error::Json)?;
+        let Some(plugins) = value.as_object() else {
+            return Err(Error::Format("expected object"));
+        };
+        let mut this = Self::new();
+        for (uri, entries) in plugins {
+            let Some(entries) = entries.as_array() else {
+                return Err(Error::Format("expected array of mappings"));
+            };
+            for entry in entries {
+                let field = |name| entry.get(name);
+                let (Some(symbol), Some(old_index), Some(new_index)) = (
+                    field("symbol").and_then(|v| v.as_str()),
+                    field("old").and_then(|v| v.as_u32()),
+                    field("new").and_then(|v| v.as_u32()),
+                ) else {
+                    return Err(Error::Format(
+                        "mapping must have `symbol`, `old`, and `new`",
+                    ));
+                };
+                let mapping = Mapping {
+                    symbol: symbol.to_owned(),
+                    new_index,
+                };
+                if this.insert(uri, old_index, mapping).is_err() {
+                    return Err(Error::Format("conflicting mappings"));
+                }
+            }
+        }
+        Ok(this)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path).map_err(Error::Io)?)
+    }
+}
+
+impl Display for IndexMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (uri, plugin)) in self.plugins.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "\n  {}: [", Str(uri))?;
+            for (j, (old_index, mapping)) in plugin.iter().enumerate() {
+                if j > 0 {
+                    write!(f, ",")?;
+                }
+                write!(
+                    f,
+                    "\n    {{\"symbol\": {}, \"old\": {old_index}, \
+                     \"new\": {}}}",
+                    Str(&mapping.symbol),
+                    mapping.new_index,
+                )?;
+            }
+            write!(f, "\n  ]")?;
+        }
+        writeln!(f, "\n}}")
+    }
+}
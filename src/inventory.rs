@@ -0,0 +1,264 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Records of the port tables of installed plugins.
+//!
+//! Saving an inventory and comparing it with a later one shows which plugin
+//! updates changed port indices, and therefore which sessions need fixing.
+//! An inventory can also be used as a port map, to fix sessions on a machine
+//! that doesn't have the plugins installed.
+
+use super::color::Color;
+use super::json::{self, Str};
+use super::lv2::{Plugin, Plugins};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::Path;
+
+/// The version of the inventory format.
+const VERSION: u32 = 1;
+
+#[derive(Debug, Eq, PartialEq)]
+struct PluginPorts {
+    num_ports: u32,
+    /// Port indices by symbol.
+    ports: BTreeMap<String, u32>,
+}
+
+impl PluginPorts {
+    fn new(plugin: &Plugin<'_>) -> Self {
+        let num_ports = plugin.num_ports();
+        let ports = (0..num_ports)
+            .filter_map(|i| Some((plugin.symbol(i)?, i)))
+            .collect();
+        Self {
+            num_ports,
+            ports,
+        }
+    }
+}
+
+/// The ports of every installed plugin.
+#[derive(Debug, Default)]
+pub struct Inventory {
+    plugins: BTreeMap<String, PluginPorts>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(json::Error),
+    Format(&'static str),
+    Version(u32),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Json(e) => write!(f, "invalid json: {e}"),
+            Self::Format(s) => write!(f, "invalid inventory: {s}"),
+            Self::Version(v) => {
+                write!(f, "unsupported inventory version: {v}")
+            }
+        }
+    }
+}
+
+impl Inventory {
+    pub fn new(plugins: &Plugins) -> Self {
+        let plugins = plugins
+            .all()
+            .into_iter()
+            .map(|plugin| (plugin.uri(), PluginPorts::new(&plugin)))
+            .collect();
+        Self {
+            plugins,
+        }
+    }
+
+    /// Adds the ports of `plugin` under `uri`, which may differ from the
+    /// plugin's own URI if it was looked up by an unnormalized form.
+    pub fn insert(&mut self, uri: &str, plugin: &Plugin<'_>) {
+        self.plugins.insert(uri.to_owned(), PluginPorts::new(plugin));
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let value = json::parse(s).map_err(Error::Json)?;
+        match value.get("version").and_then(|v| v.as_u32()) {
+            Some(VERSION) => {}
+            Some(v) => return Err(Error::Version(v)),
+            None => return Err(Error::Format("missing `version`")),
+        }
+        let Some(plugins) = value.get("plugins").and_then(|v| v.as_object())
+        else {
+            return Err(Error::Format("missing `plugins`"));
+        };
+        let mut this = Self::default();
+        for (uri, plugin) in plugins {
+            let num_ports = plugin.get("num_ports").and_then(|v| v.as_u32());
+            let ports = plugin.get("ports").and_then(|v| v.as_object());
+            let (Some(num_ports), Some(ports)) = (num_ports, ports) else {
+                return Err(Error::Format(
+                    "plugin must have `num_ports` and `ports`",
+                ));
+            };
+            let ports = ports
+                .iter()
+                .map(|(symbol, index)| match index.as_u32() {
+                    Some(i) => Ok((symbol.clone(), i)),
+                    None => Err(Error::Format("port index is not an integer")),
+                })
+                .collect::<Result<_, _>>()?;
+            this.plugins.insert(uri.clone(), PluginPorts {
+                num_ports,
+                ports,
+            });
+        }
+        Ok(this)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path).map_err(Error::Io)?)
+    }
+
+    /// Returns the number of ports of the plugin `uri`, or [`None`] if it
+    /// isn't in the inventory.
+    pub fn num_ports(&self, uri: &str) -> Option<u32> {
+        Some(self.plugins.get(uri)?.num_ports)
+    }
+
+    pub fn port_index(&self, uri: &str, symbol: &str) -> Option<u32> {
+        self.plugins.get(uri)?.ports.get(symbol).copied()
+    }
+
+    /// Returns the URIs of the plugins in the inventory.
+    pub fn uris(&self) -> impl Iterator<Item = &str> {
+        self.plugins.keys().map(String::as_str)
+    }
+}
+
+impl Display for Inventory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"version\": {VERSION}, \"plugins\": {{")?;
+        for (i, (uri, plugin)) in self.plugins.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(
+                f,
+                "\n  {}: {{\"num_ports\": {}, \"ports\": {{",
+                Str(uri),
+                plugin.num_ports,
+            )?;
+            for (j, (symbol, index)) in plugin.ports.iter().enumerate() {
+                if j > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: {index}", Str(symbol))?;
+            }
+            write!(f, "}}}}")?;
+        }
+        if !self.plugins.is_empty() {
+            writeln!(f)?;
+        }
+        writeln!(f, "}}}}")
+    }
+}
+
+/// The differences between two inventories.
+pub struct Diff<'a> {
+    old: &'a Inventory,
+    new: &'a Inventory,
+    color: bool,
+}
+
+impl<'a> Diff<'a> {
+    pub fn new(old: &'a Inventory, new: &'a Inventory) -> Self {
+        Self {
+            old,
+            new,
+            color: false,
+        }
+    }
+
+    /// Colors added, removed, and changed plugins and ports when displayed,
+    /// if `color` is true.
+    pub fn colored(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Returns the plugins in both inventories whose ports differ.
+    fn changed(
+        &self,
+    ) -> impl Iterator<Item = (&'a str, [&'a PluginPorts; 2])> {
+        self.old.plugins.iter().filter_map(|(uri, old)| {
+            let new = self.new.plugins.get(uri).filter(|new| *new != old)?;
+            Some((uri.as_str(), [old, new]))
+        })
+    }
+
+    /// The number of plugins whose ports changed.
+    pub fn num_changed(&self) -> usize {
+        self.changed().count()
+    }
+}
+
+impl Display for Diff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let color = self.color;
+        let changed = Color::Yellow.paint("changed:", color);
+        let added = Color::Green.paint("added", color);
+        let removed = Color::Red.paint("removed", color);
+        for (uri, [old, new]) in self.changed() {
+            writeln!(
+                f,
+                "{changed} {uri} ({} -> {} ports)",
+                old.num_ports, new.num_ports,
+            )?;
+            for (symbol, &i) in &old.ports {
+                let symbol_str = symbol.escape_default();
+                match new.ports.get(symbol) {
+                    Some(&j) if i == j => {}
+                    Some(&j) => writeln!(f, "  {symbol_str}: {i} -> {j}")?,
+                    None => writeln!(f, "  {symbol_str}: {i} -> {removed}")?,
+                }
+            }
+            for (symbol, &j) in &new.ports {
+                if !old.ports.contains_key(symbol) {
+                    let symbol = symbol.escape_default();
+                    writeln!(f, "  {symbol}: {added} at {j}")?;
+                }
+            }
+        }
+        for uri in self.new.plugins.keys() {
+            if !self.old.plugins.contains_key(uri) {
+                writeln!(f, "{added}: {uri}")?;
+            }
+        }
+        for uri in self.old.plugins.keys() {
+            if !self.new.plugins.contains_key(uri) {
+                writeln!(f, "{removed}: {uri}")?;
+            }
+        }
+        Ok(())
+    }
+}
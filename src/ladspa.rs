@@ -0,0 +1,192 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Port names of the installed LADSPA plugins, read from their descriptors.
+//!
+//! LADSPA plugins have no port symbols, so Ardour identifies their
+//! parameters by port name instead. Each library in the search path is
+//! loaded with `dlopen` and its `ladspa_descriptor` function is called to
+//! enumerate its plugins.
+
+#![allow(clippy::undocumented_unsafe_blocks)]
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, OsString, c_char, c_int, c_ulong, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+mod dl {
+    use std::ffi::{c_char, c_int, c_void};
+
+    pub const RTLD_NOW: c_int = 2;
+
+    #[link(name = "dl")]
+    extern "C" {
+        pub fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        pub fn dlsym(
+            handle: *mut c_void,
+            symbol: *const c_char,
+        ) -> *mut c_void;
+    }
+}
+
+/// The beginning of `LADSPA_Descriptor`, up to the fields read here.
+#[repr(C)]
+struct Descriptor {
+    unique_id: c_ulong,
+    label: *const c_char,
+    properties: c_int,
+    name: *const c_char,
+    maker: *const c_char,
+    copyright: *const c_char,
+    port_count: c_ulong,
+    port_descriptors: *const c_int,
+    port_names: *const *const c_char,
+}
+
+type DescriptorFn = unsafe extern "C" fn(c_ulong) -> *const Descriptor;
+
+/// The search path Ardour uses when `LADSPA_PATH` is unset.
+const DEFAULT_PATH: &str = "~/.ladspa:/usr/local/lib/ladspa:/usr/lib/ladspa";
+
+/// Returns the directories searched for LADSPA libraries: those in
+/// `LADSPA_PATH`, or else the default search path.
+pub fn search_path() -> Vec<PathBuf> {
+    super::lv2::split_path(
+        &std::env::var_os("LADSPA_PATH")
+            .unwrap_or_else(|| OsString::from(DEFAULT_PATH)),
+    )
+}
+
+/// A LADSPA plugin's name and port names.
+#[derive(Debug)]
+pub struct Plugin {
+    pub name: Option<String>,
+    /// Port names, in port order.
+    pub ports: Vec<String>,
+}
+
+impl Plugin {
+    pub fn num_ports(&self) -> u32 {
+        self.ports.len() as u32
+    }
+
+    /// Returns the index of the port `name`, if exactly one port has that
+    /// name.
+    pub fn port_index(&self, name: &str) -> Option<u32> {
+        let mut found =
+            self.ports.iter().enumerate().filter(|(_, n)| *n == name);
+        match (found.next(), found.next()) {
+            (Some((i, _)), None) => Some(i as u32),
+            _ => None,
+        }
+    }
+}
+
+fn string(s: *const c_char) -> Option<String> {
+    (!s.is_null())
+        .then(|| unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned())
+}
+
+/// Loads the library at `path` and reads the plugins it provides into
+/// `plugins`, keeping any already there. The library is never unloaded,
+/// since some plugins don't survive it.
+fn load(path: &Path, plugins: &mut HashMap<c_ulong, Plugin>) {
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return;
+    };
+    let handle = unsafe { dl::dlopen(c_path.as_ptr(), dl::RTLD_NOW) };
+    if handle.is_null() {
+        return;
+    }
+    let symbol =
+        unsafe { dl::dlsym(handle, b"ladspa_descriptor\0".as_ptr().cast()) };
+    if symbol.is_null() {
+        return;
+    }
+    let descriptor_fn: DescriptorFn =
+        unsafe { std::mem::transmute::<*mut c_void, DescriptorFn>(symbol) };
+    for i in 0.. {
+        let descriptor = unsafe { descriptor_fn(i) };
+        let Some(descriptor) = (unsafe { descriptor.as_ref() }) else {
+            break;
+        };
+        if plugins.contains_key(&descriptor.unique_id) {
+            continue;
+        }
+        let count = descriptor.port_count as usize;
+        let ports = if descriptor.port_names.is_null() {
+            Vec::new()
+        } else {
+            unsafe { std::slice::from_raw_parts(descriptor.port_names, count) }
+                .iter()
+                .map(|&name| string(name).unwrap_or_default())
+                .collect()
+        };
+        let plugin = Plugin {
+            name: string(descriptor.name),
+            ports,
+        };
+        plugins.insert(descriptor.unique_id, plugin);
+    }
+}
+
+/// The installed LADSPA plugins, by unique ID. The search path is scanned
+/// the first time a plugin is looked up.
+pub struct Plugins {
+    search_path: Vec<PathBuf>,
+    plugins: Option<HashMap<c_ulong, Plugin>>,
+}
+
+impl Plugins {
+    /// Finds plugins in the directories in `search_path`.
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            plugins: None,
+        }
+    }
+
+    fn scan(&self) -> HashMap<c_ulong, Plugin> {
+        let mut plugins = HashMap::new();
+        for dir in &self.search_path {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            let mut paths: Vec<_> = entries
+                .filter_map(|e| Some(e.ok()?.path()))
+                .filter(|p| p.extension().is_some_and(|e| e == "so"))
+                .collect();
+            paths.sort();
+            for path in paths {
+                load(&path, &mut plugins);
+            }
+        }
+        plugins
+    }
+
+    /// Looks up the plugin with the unique ID `id`, as written in a session.
+    pub fn get(&mut self, id: &str) -> Option<&Plugin> {
+        let id = id.parse().ok()?;
+        if self.plugins.is_none() {
+            self.plugins = Some(self.scan());
+        }
+        self.plugins.as_ref()?.get(&id)
+    }
+}
@@ -0,0 +1,79 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Fixes the parameter indices of LV2 plugins in Ardour sessions.
+//!
+//! Ardour identifies each automatable plugin parameter in a session by its
+//! port index, which changes when a plugin adds or reorders ports. This
+//! crate rewrites those indices to match the installed plugins, looking up
+//! each parameter by its port symbol, which is also saved in the session.
+//! LADSPA plugins are fixed the same way, by port name ([`ladspa`]).
+//!
+//! The main entry point is [`patch::patch`], which fixes a session given as
+//! a string and returns a [`patch::PatchedSession`] that can be written out
+//! and summarized:
+//!
+//! ```no_run
+//! use fix_ardour_lv2_index::lv2::Plugins;
+//! use fix_ardour_lv2_index::missing::MissingCache;
+//! use fix_ardour_lv2_index::patch::{self, Resolver, Source};
+//!
+//! let xml = std::fs::read_to_string("session.ardour").unwrap();
+//! let plugins = Plugins::new(MissingCache::default(), &[]).unwrap();
+//! let mut resolver = Resolver::new(Source::Lv2(Box::new(plugins)));
+//! let options = patch::Options::default();
+//! let patched = patch::patch(&xml, &mut resolver, options).unwrap();
+//! println!("{} indices changed", patched.summary().changes.len());
+//! let file = std::fs::File::create("session.ardour").unwrap();
+//! patched.write_to(file).unwrap();
+//! ```
+//!
+//! Port metadata comes from a [`patch::Source`]: the installed plugins via
+//! lilv ([`lv2`]), an index map exported earlier ([`index_map`]), with the
+//! `http` feature, a remote service, or any implementation of
+//! [`patch::PortResolver`]. Sessions are read with [`session`], and warnings
+//! are reported through [`diag`].
+
+#![warn(clippy::undocumented_unsafe_blocks)]
+
+mod annotate;
+pub mod backup;
+pub mod color;
+pub mod config;
+pub mod consistency;
+pub mod diag;
+pub mod dirs;
+pub mod filter;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod index_map;
+pub mod inventory;
+pub mod json;
+pub mod ladspa;
+pub mod lines;
+pub mod lv2;
+pub mod merge;
+pub mod missing;
+pub mod patch;
+pub mod port_cache;
+pub mod report;
+pub mod session;
+pub mod spanedit;
+mod time;
+pub mod toml;
@@ -17,11 +17,23 @@
  * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
  */
 
+//! Port metadata of the installed LV2 plugins, retrieved through lilv.
+
 #![allow(clippy::undocumented_unsafe_blocks)]
 
-use std::ffi::CString;
-use std::fmt::{self, Display};
+use super::diag::{self, Kind, Warning};
+use super::missing::MissingCache;
+use super::port_cache::{PortCache, Ports};
+use std::cell::{OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString, OsStr, OsString, c_char, c_void};
+use std::fmt::{self, Display, Write};
+use std::fs::File;
+use std::io;
 use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::ptr::NonNull;
 
 mod lilv {
@@ -29,6 +41,7 @@ mod lilv {
     use std::marker::{PhantomData, PhantomPinned};
 
     type Phantom = PhantomData<(*mut u8, PhantomPinned)>;
+    pub type LilvIter = c_void;
     #[repr(C)]
     pub struct LilvNode([u8; 0], Phantom);
     #[repr(C)]
@@ -41,6 +54,16 @@ mod lilv {
 
     #[link(name = "lilv-0")]
     extern "C" {
+        pub fn lilv_file_uri_parse(
+            uri: *const c_char,
+            hostname: *mut *mut c_char,
+        ) -> *mut c_char;
+        pub fn lilv_free(ptr: *mut c_void);
+        pub fn lilv_new_file_uri(
+            world: *mut LilvWorld,
+            host: *const c_char,
+            path: *const c_char,
+        ) -> *mut LilvNode;
         pub fn lilv_new_string(
             world: *mut LilvWorld,
             r#str: *const c_char,
@@ -49,71 +72,542 @@ mod lilv {
             world: *mut LilvWorld,
             uri: *const c_char,
         ) -> *mut LilvNode;
+        pub fn lilv_node_as_string(value: *const LilvNode) -> *const c_char;
+        pub fn lilv_node_as_uri(value: *const LilvNode) -> *const c_char;
         pub fn lilv_node_free(val: *mut LilvNode);
+        pub fn lilv_plugin_get_bundle_uri(
+            plugin: *const LilvPlugin,
+        ) -> *const LilvNode;
+        pub fn lilv_plugin_get_name(
+            plugin: *const LilvPlugin,
+        ) -> *mut LilvNode;
         pub fn lilv_plugin_get_num_ports(plugin: *const LilvPlugin) -> u32;
+        pub fn lilv_plugin_get_port_by_index(
+            plugin: *const LilvPlugin,
+            index: u32,
+        ) -> *const LilvPort;
         pub fn lilv_plugin_get_port_by_symbol(
             plugin: *const LilvPlugin,
             symbol: *const LilvNode,
         ) -> *const LilvPort;
+        pub fn lilv_plugin_get_uri(
+            plugin: *const LilvPlugin,
+        ) -> *const LilvNode;
+        pub fn lilv_plugins_begin(
+            plugins: *const LilvPlugins,
+        ) -> *mut LilvIter;
+        pub fn lilv_plugins_get(
+            plugins: *const LilvPlugins,
+            i: *mut LilvIter,
+        ) -> *const LilvPlugin;
         pub fn lilv_plugins_get_by_uri(
             plugins: *const LilvPlugins,
             uri: *const LilvNode,
         ) -> *const LilvPlugin;
+        pub fn lilv_plugins_is_end(
+            plugins: *const LilvPlugins,
+            i: *mut LilvIter,
+        ) -> bool;
+        pub fn lilv_plugins_next(
+            plugins: *const LilvPlugins,
+            i: *mut LilvIter,
+        ) -> *mut LilvIter;
+        pub fn lilv_port_get_name(
+            plugin: *const LilvPlugin,
+            port: *const LilvPort,
+        ) -> *mut LilvNode;
         pub fn lilv_port_get_index(
             plugin: *const LilvPlugin,
             port: *const LilvPort,
         ) -> u32;
+        pub fn lilv_port_get_symbol(
+            plugin: *const LilvPlugin,
+            port: *const LilvPort,
+        ) -> *const LilvNode;
+        pub fn lilv_port_is_a(
+            plugin: *const LilvPlugin,
+            port: *const LilvPort,
+            port_class: *const LilvNode,
+        ) -> bool;
         pub fn lilv_world_free(world: *mut LilvWorld);
         pub fn lilv_world_get_all_plugins(
             world: *const LilvWorld,
         ) -> *const LilvPlugins;
         pub fn lilv_world_load_all(world: *mut LilvWorld);
+        pub fn lilv_world_load_bundle(
+            world: *mut LilvWorld,
+            bundle_uri: *const LilvNode,
+        );
         pub fn lilv_world_new() -> *mut LilvWorld;
+        pub fn lilv_world_set_option(
+            world: *mut LilvWorld,
+            uri: *const c_char,
+            value: *const LilvNode,
+        );
+    }
+}
+
+mod dl {
+    use std::ffi::{c_char, c_int, c_void};
+
+    #[repr(C)]
+    pub struct DlInfo {
+        pub dli_fname: *const c_char,
+        pub dli_fbase: *mut c_void,
+        pub dli_sname: *const c_char,
+        pub dli_saddr: *mut c_void,
+    }
+
+    extern "C" {
+        pub fn dladdr(addr: *const c_void, info: *mut DlInfo) -> c_int;
     }
 }
 
 use lilv as lv;
 
+/// Returns the path of the lilv library this program is linked with, with
+/// symlinks resolved so that its name includes the full version (e.g.,
+/// `liblilv-0.so.0.24.22`), since lilv doesn't report its version itself.
+pub fn library_path() -> Option<PathBuf> {
+    let mut info = MaybeUninit::<dl::DlInfo>::uninit();
+    let addr = lv::lilv_world_new as *const c_void;
+    if unsafe { dl::dladdr(addr, info.as_mut_ptr()) } == 0 {
+        return None;
+    }
+    let info = unsafe { info.assume_init() };
+    if info.dli_fname.is_null() {
+        return None;
+    }
+    let path = unsafe { CStr::from_ptr(info.dli_fname) };
+    std::fs::canonicalize(OsStr::from_bytes(path.to_bytes())).ok()
+}
+
+/// An error returned by [`Plugins::new`] and [`Plugins::for_uris`].
 #[derive(Debug)]
 pub enum Error {
     LilvWorldNew,
+    InvalidPath,
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::LilvWorldNew => write!(f, "lilv_world_new failed"),
+            Self::InvalidPath => write!(f, "\\0 in lv2 search path"),
         }
     }
 }
 
-pub struct Plugins {
+/// The search path lilv uses when `LV2_PATH` is unset.
+const DEFAULT_PATH: &str = "~/.lv2:/usr/local/lib/lv2:/usr/lib/lv2";
+
+/// The lilv option that overrides `LV2_PATH`.
+const LV2_PATH_OPTION: &[u8] = b"http://drobilla.net/ns/lilv#lv2-path\0";
+
+/// Splits the search path `path` into directories, expanding `~` like lilv
+/// does.
+pub fn split_path(path: &OsStr) -> Vec<PathBuf> {
+    let home = std::env::var_os("HOME");
+    std::env::split_paths(path)
+        .map(|dir| match (dir.strip_prefix("~"), &home) {
+            (Ok(rest), Some(home)) => Path::new(home).join(rest),
+            _ => dir,
+        })
+        .collect()
+}
+
+/// Returns the directories lilv searches for bundles: `trees` if not empty,
+/// or else those in `LV2_PATH` or lilv's default search path.
+pub fn search_path(trees: &[PathBuf]) -> Vec<PathBuf> {
+    if !trees.is_empty() {
+        return trees.to_vec();
+    }
+    split_path(
+        &std::env::var_os("LV2_PATH")
+            .unwrap_or_else(|| OsString::from(DEFAULT_PATH)),
+    )
+}
+
+/// Returns the directories in `search_path`, and the bundles in them, that
+/// lilv would be denied access to. lilv skips these silently, so the plugins
+/// in them appear to be missing.
+pub fn unreadable(search_path: &[PathBuf]) -> Vec<(PathBuf, io::Error)> {
+    let denied = |e: &io::Error| e.kind() == io::ErrorKind::PermissionDenied;
+    let mut unreadable = Vec::new();
+    for dir in search_path {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if denied(&e) {
+                    unreadable.push((dir.clone(), e));
+                }
+                continue;
+            }
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let bundle = entry.path();
+            if !bundle.is_dir() {
+                continue;
+            }
+            if let Err(e) = File::open(bundle.join("manifest.ttl")) {
+                if denied(&e) {
+                    unreadable.push((bundle, e));
+                }
+            }
+        }
+    }
+    unreadable
+}
+
+/// Returns the prefixes declared in the Turtle document `ttl`, as pairs of
+/// the prefix name and namespace.
+fn prefixes(ttl: &str) -> Vec<(&str, &str)> {
+    ttl.lines()
+        .filter_map(|line| {
+            let line = line.trim_start();
+            let rest = line
+                .strip_prefix("@prefix")
+                .or_else(|| line.strip_prefix("PREFIX"))?;
+            let (name, rest) = rest.split_once(':')?;
+            let ns = rest.trim_start().strip_prefix('<')?.split('>').next()?;
+            Some((name.trim(), ns))
+        })
+        .collect()
+}
+
+/// Whether the Turtle document `ttl` mentions `uri`, either in full or as a
+/// prefixed name using one of `prefixes`.
+fn mentions(ttl: &str, prefixes: &[(&str, &str)], uri: &str) -> bool {
+    if ttl.contains(&format!("<{uri}>")) {
+        return true;
+    }
+    let name_char = |c: char| c.is_alphanumeric() || "_-:%".contains(c);
+    prefixes.iter().any(|(prefix, ns)| {
+        let Some(local) = uri.strip_prefix(ns).filter(|l| !l.is_empty())
+        else {
+            return false;
+        };
+        let name = format!("{prefix}:{local}");
+        ttl.match_indices(&name).any(|(i, _)| {
+            let before = ttl[..i].chars().next_back();
+            let mut after = ttl[i + name.len()..].chars();
+            let end = match after.next() {
+                // A trailing `.` ends the statement unless more of the name
+                // follows.
+                Some('.') => after.next(),
+                c => c,
+            };
+            !before.is_some_and(name_char) && !end.is_some_and(name_char)
+        })
+    })
+}
+
+/// Returns the bundles in `search_path` whose manifests mention any of
+/// `uris`, and the URIs that were found.
+fn find_bundles<'a>(
+    search_path: &[PathBuf],
+    uris: &[&'a str],
+) -> (Vec<PathBuf>, HashSet<&'a str>) {
+    let mut bundles = Vec::new();
+    let mut found = HashSet::new();
+    let entries = search_path
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|e| e.ok());
+    for entry in entries {
+        let bundle = entry.path();
+        let Ok(ttl) = std::fs::read_to_string(bundle.join("manifest.ttl"))
+        else {
+            continue;
+        };
+        let prefixes = prefixes(&ttl);
+        let mut provides = false;
+        for &uri in uris {
+            if mentions(&ttl, &prefixes, uri) {
+                found.insert(uri);
+                provides = true;
+            }
+        }
+        if provides {
+            bundles.push(bundle);
+        }
+    }
+    (bundles, found)
+}
+
+/// The maximum length in bytes of a uri or symbol passed to lilv.
+const MAX_LEN: usize = 4096;
+
+/// Converts `s` to a [`CString`] for lilv, warning if it is too long or
+/// contains a nul byte.
+fn c_string(s: &str, kind: Kind, what: &str) -> Option<CString> {
+    if s.len() > MAX_LEN {
+        diag::warn(Warning::new(
+            kind,
+            format!("{what} is too long ({} bytes)", s.len()),
+        ));
+        return None;
+    }
+    let Ok(c) = CString::new(s) else {
+        diag::warn(Warning::new(
+            kind,
+            format!("\\0 in {what}: \"{}\"", s.escape_default()),
+        ));
+        return None;
+    };
+    Some(c)
+}
+
+/// Percent-encodes non-ASCII bytes in `uri` and uppercases existing
+/// percent-escapes, or returns [`None`] if `uri` is already normalized.
+fn normalize_uri(uri: &str) -> Option<String> {
+    let bytes = uri.as_bytes();
+    let escape = |i: usize| {
+        bytes[i] == b'%'
+            && bytes.get(i + 1..i + 3).is_some_and(|h| {
+                h.iter().all(u8::is_ascii_hexdigit)
+                    && h.iter().any(u8::is_ascii_lowercase)
+            })
+    };
+    if uri.is_ascii() && !(0..bytes.len()).any(escape) {
+        return None;
+    }
+    let mut normalized = String::with_capacity(uri.len() * 3);
+    let mut i = 0;
+    while let Some(&b) = bytes.get(i) {
+        if escape(i) {
+            normalized.push('%');
+            normalized.push(bytes[i + 1].to_ascii_uppercase().into());
+            normalized.push(bytes[i + 2].to_ascii_uppercase().into());
+            i += 3;
+            continue;
+        }
+        if b.is_ascii() {
+            normalized.push(b.into());
+        } else {
+            write!(normalized, "%{b:02X}").unwrap();
+        }
+        i += 1;
+    }
+    Some(normalized)
+}
+
+/// A way in which a plugin URI differs from another that most likely names
+/// the same plugin. See [`uri_variant`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UriDifference {
+    /// One is `http` and the other `https`.
+    Scheme,
+    TrailingSlash,
+    Case,
+}
+
+impl Display for UriDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scheme => write!(f, "http vs https"),
+            Self::TrailingSlash => write!(f, "trailing slash"),
+            Self::Case => write!(f, "case"),
+        }
+    }
+}
+
+/// Splits an `http` or `https` URI into its scheme and the rest.
+fn split_http(uri: &str) -> (Option<&str>, &str) {
+    for (scheme, prefix) in [("https", "https://"), ("http", "http://")] {
+        let len = prefix.len();
+        if uri.get(..len).is_some_and(|p| p.eq_ignore_ascii_case(prefix)) {
+            return (Some(scheme), &uri[len..]);
+        }
+    }
+    (None, uri)
+}
+
+/// If `other` differs from `uri` only in case, a trailing slash, or `http`
+/// vs `https`, returns how it differs. Returns [`None`] if the URIs are
+/// equal or differ in any other way.
+pub fn uri_variant(uri: &str, other: &str) -> Option<Vec<UriDifference>> {
+    if uri == other {
+        return None;
+    }
+    let mut differences = Vec::new();
+    let (scheme, rest) = split_http(uri);
+    let (other_scheme, other_rest) = split_http(other);
+    if scheme != other_scheme {
+        if scheme.is_none() || other_scheme.is_none() {
+            return None;
+        }
+        differences.push(UriDifference::Scheme);
+    }
+    let (rest, other_rest) =
+        match (rest.strip_suffix('/'), other_rest.strip_suffix('/')) {
+            (Some(rest), None) => {
+                differences.push(UriDifference::TrailingSlash);
+                (rest, other_rest)
+            }
+            (None, Some(other_rest)) => {
+                differences.push(UriDifference::TrailingSlash);
+                (rest, other_rest)
+            }
+            _ => (rest, other_rest),
+        };
+    if rest != other_rest {
+        if !rest.eq_ignore_ascii_case(other_rest) {
+            return None;
+        }
+        differences.push(UriDifference::Case);
+    }
+    if differences.is_empty() {
+        // Only the case of the scheme differs.
+        differences.push(UriDifference::Case);
+    }
+    Some(differences)
+}
+
+/// Returns the path of `uri` after its host, without a trailing slash, or
+/// [`None`] if it has no path.
+fn uri_path(uri: &str) -> Option<&str> {
+    let (_, rest) = uri.split_once("://")?;
+    let path = &rest[rest.find('/')?..];
+    Some(path.trim_end_matches('/')).filter(|p| !p.is_empty())
+}
+
+/// The Levenshtein distance between `a` and `b`, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Returns the URI in `candidates` that `uri` most likely refers to: one that
+/// differs only slightly ([`uri_variant`]), one with the same path on a
+/// different host, or else the closest within a small edit distance.
+pub fn similar_uri<'a, I>(uri: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max_distance = (uri.chars().count() / 10).max(2);
+    let path = uri_path(uri);
+    candidates
+        .into_iter()
+        .filter(|&other| other != uri)
+        .filter_map(|other| {
+            if uri_variant(uri, other).is_some() {
+                return Some(((0, 0), other));
+            }
+            let other_path = uri_path(other);
+            if path.is_some_and(|p| {
+                other_path.is_some_and(|o| p.eq_ignore_ascii_case(o))
+            }) {
+                return Some(((1, 0), other));
+            }
+            let distance = edit_distance(uri, other);
+            (distance <= max_distance).then_some(((2, distance), other))
+        })
+        .min()
+        .map(|(_, other)| other)
+}
+
+/// Whether `symbol` is a valid LV2 symbol (a C identifier).
+fn valid_symbol(symbol: &str) -> bool {
+    let mut bytes = symbol.bytes();
+    bytes.next().is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// A lilv world and the plugins loaded in it.
+struct World {
     world: NonNull<lv::LilvWorld>,
     plugins: NonNull<lv::LilvPlugins>,
 }
 
-impl Plugins {
-    pub fn new() -> Result<Self, Error> {
+fn path_c_string(path: &Path) -> Result<CString, Error> {
+    CString::new(path.as_os_str().as_encoded_bytes())
+        .map_err(|_| Error::InvalidPath)
+}
+
+impl World {
+    /// Loads the plugins in `tree`, or in `LV2_PATH` if `tree` is [`None`].
+    fn new(tree: Option<&Path>) -> Result<Self, Error> {
+        let tree = tree.map(path_c_string).transpose()?;
         let world = NonNull::new(unsafe { lv::lilv_world_new() })
             .ok_or(Error::LilvWorldNew)?;
+        if let Some(tree) = tree {
+            let node = NonNull::new(unsafe {
+                lv::lilv_new_string(world.as_ptr(), tree.as_ptr())
+            })
+            .expect("lilv_new_string failed");
+            unsafe {
+                lv::lilv_world_set_option(
+                    world.as_ptr(),
+                    LV2_PATH_OPTION.as_ptr().cast(),
+                    node.as_ptr(),
+                );
+                lv::lilv_node_free(node.as_ptr());
+            }
+        }
         unsafe {
             lv::lilv_world_load_all(world.as_ptr());
         }
+        Ok(Self::with_world(world))
+    }
+
+    /// Loads only the plugins in `bundles`.
+    fn with_bundles(bundles: &[PathBuf]) -> Result<Self, Error> {
+        // Bundle URIs must end with a slash.
+        let bundles = bundles
+            .iter()
+            .map(|b| path_c_string(&b.join("")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let world = NonNull::new(unsafe { lv::lilv_world_new() })
+            .ok_or(Error::LilvWorldNew)?;
+        for bundle in &bundles {
+            let node = NonNull::new(unsafe {
+                lv::lilv_new_file_uri(
+                    world.as_ptr(),
+                    std::ptr::null(),
+                    bundle.as_ptr(),
+                )
+            })
+            .expect("lilv_new_file_uri failed");
+            unsafe {
+                lv::lilv_world_load_bundle(world.as_ptr(), node.as_ptr());
+                lv::lilv_node_free(node.as_ptr());
+            }
+        }
+        Ok(Self::with_world(world))
+    }
+
+    fn with_world(world: NonNull<lv::LilvWorld>) -> Self {
         let plugins = NonNull::new(unsafe {
             lv::lilv_world_get_all_plugins(world.as_ptr())
         } as _)
         .expect("lilv_world_get_all_plugins failed");
-        Ok(Self {
-            plugins,
+        Self {
             world,
+            plugins,
+        }
+    }
+
+    fn plugin(&self, uri: &CString) -> Option<Plugin<'_>> {
+        Some(Plugin {
+            world: self.world,
+            plugin: self.lookup(uri)?,
+            _phantom: PhantomData,
         })
     }
 
-    pub fn get(&mut self, uri: &str) -> Option<Plugin<'_>> {
-        let Ok(uri) = CString::new(uri) else {
-            eprintln!("warning: \\0 in uri: \"{}\"", uri.escape_default());
-            return None;
-        };
+    fn lookup(&self, uri: &CString) -> Option<NonNull<lv::LilvPlugin>> {
         let node = NonNull::new(unsafe {
             lv::lilv_new_uri(self.world.as_ptr(), uri.as_ptr())
         })
@@ -124,15 +618,26 @@ impl Plugins {
         unsafe {
             lv::lilv_node_free(node.as_ptr());
         }
-        plugin.map(|p| Plugin {
-            world: self.world,
-            plugin: p,
-            _phantom: PhantomData,
-        })
+        plugin
+    }
+}
+
+impl World {
+    fn all(&self) -> Vec<NonNull<lv::LilvPlugin>> {
+        let plugins = self.plugins.as_ptr();
+        let mut all = Vec::new();
+        let mut iter = unsafe { lv::lilv_plugins_begin(plugins) };
+        while !unsafe { lv::lilv_plugins_is_end(plugins, iter) } {
+            all.extend(NonNull::new(unsafe {
+                lv::lilv_plugins_get(plugins, iter)
+            } as _));
+            iter = unsafe { lv::lilv_plugins_next(plugins, iter) };
+        }
+        all
     }
 }
 
-impl Drop for Plugins {
+impl Drop for World {
     fn drop(&mut self) {
         unsafe {
             lv::lilv_world_free(self.world.as_ptr());
@@ -140,25 +645,435 @@ impl Drop for Plugins {
     }
 }
 
+/// The plugins in one tree of the search path.
+struct Tree {
+    /// The tree's directory, or [`None`] for `LV2_PATH`.
+    path: Option<PathBuf>,
+    /// The bundles whose manifests mention the plugins passed to
+    /// [`Plugins::for_uris`], and the plugins that were mentioned. Loaded on
+    /// first use.
+    partial: OnceCell<Option<(World, HashSet<String>)>>,
+    /// Every bundle in the tree, loaded on first use.
+    full: OnceCell<Option<World>>,
+}
+
+impl Tree {
+    fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            partial: OnceCell::new(),
+            full: OnceCell::new(),
+        }
+    }
+
+    /// Returns the bundles that mention `wanted`, except plugins known to be
+    /// `missing`.
+    fn partial(
+        &self,
+        wanted: &[String],
+        missing: &RefCell<MissingCache>,
+    ) -> Option<&(World, HashSet<String>)> {
+        self.partial
+            .get_or_init(|| {
+                let missing = missing.borrow();
+                let uris: Vec<_> = wanted
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|uri| !missing.contains(uri))
+                    .collect();
+                if uris.is_empty() {
+                    return None;
+                }
+                let tree =
+                    self.path.as_ref().map_or(&[][..], std::slice::from_ref);
+                let (bundles, found) = find_bundles(&search_path(tree), &uris);
+                if bundles.is_empty() {
+                    return None;
+                }
+                let world = World::with_bundles(&bundles).ok()?;
+                Some((world, found.into_iter().map(str::to_owned).collect()))
+            })
+            .as_ref()
+    }
+
+    fn full(&self) -> Option<&World> {
+        self.full
+            .get_or_init(|| World::new(self.path.as_deref()).ok())
+            .as_ref()
+    }
+}
+
+/// The installed LV2 plugins, as found by lilv.
+pub struct Plugins {
+    /// Bundles given explicitly, which take priority over the search path.
+    extra: Option<World>,
+    /// Searched in order, so plugins in earlier trees take priority.
+    trees: Vec<Tree>,
+    /// The plugins passed to [`Self::for_uris`].
+    wanted: Vec<String>,
+    /// Plugins known to be missing, which aren't looked up again.
+    missing: RefCell<MissingCache>,
+    /// The ports of the plugins looked up so far, or [`None`] for missing
+    /// plugins.
+    ports: HashMap<String, Option<Ports>>,
+    cache: Option<PortCache>,
+}
+
+/// Returns `trees`, or a single [`None`] for `LV2_PATH` if it is empty.
+fn tree_paths(trees: &[PathBuf]) -> Result<Vec<Option<PathBuf>>, Error> {
+    if trees.is_empty() {
+        return Ok(vec![None]);
+    }
+    for tree in trees {
+        path_c_string(tree)?;
+    }
+    Ok(trees.iter().cloned().map(Some).collect())
+}
+
+impl Plugins {
+    /// Loads all plugins. If `trees` is not empty, each tree is loaded
+    /// separately instead of `LV2_PATH`, and a plugin found in more than one
+    /// tree is taken from the first.
+    pub fn new(
+        missing: MissingCache,
+        trees: &[PathBuf],
+    ) -> Result<Self, Error> {
+        let trees = tree_paths(trees)?
+            .into_iter()
+            .map(|path| {
+                let world = World::new(path.as_deref())?;
+                let tree = Tree::new(path);
+                let _ = tree.full.set(Some(world));
+                Ok(tree)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            extra: None,
+            trees,
+            wanted: Vec::new(),
+            missing: RefCell::new(missing),
+            ports: HashMap::new(),
+            cache: None,
+        })
+    }
+
+    /// Like [`Self::new`], but loads only the bundles whose manifests
+    /// mention one of `uris`, which is much faster with large plugin
+    /// collections. Nothing is loaded until a plugin is looked up, and every
+    /// bundle is still loaded the first time a plugin can't be found this
+    /// way.
+    pub fn for_uris<'a>(
+        missing: MissingCache,
+        trees: &[PathBuf],
+        uris: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            extra: None,
+            trees: tree_paths(trees)?.into_iter().map(Tree::new).collect(),
+            wanted: uris.into_iter().map(str::to_owned).collect(),
+            missing: RefCell::new(missing),
+            ports: HashMap::new(),
+            cache: None,
+        })
+    }
+
+    /// Also loads the plugins in `bundles`, such as development builds that
+    /// aren't installed. They take priority over plugins in the search path
+    /// and are never cached.
+    pub fn with_bundles(mut self, bundles: &[PathBuf]) -> Result<Self, Error> {
+        self.extra = Some(World::with_bundles(bundles)?);
+        Ok(self)
+    }
+
+    /// Stores the ports of plugins in `cache`, and takes them from there
+    /// when possible instead of loading the plugins.
+    pub fn with_cache(mut self, cache: PortCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Looks up the plugin `uri`. Since this takes `&self`, several plugin
+    /// handles can be held at once.
+    ///
+    /// If `uri` isn't found and contains non-ASCII characters or lowercase
+    /// percent-escapes, its percent-encoded form is tried as well.
+    pub fn get(&self, uri: &str) -> Option<Plugin<'_>> {
+        // Explicitly loaded bundles aren't covered by the missing cache.
+        let extra = self.extra(uri);
+        if extra.is_some() {
+            return extra;
+        }
+        if self.missing.borrow().contains(uri) {
+            return None;
+        }
+        let plugin = self
+            .lookup(uri)
+            .or_else(|| normalize_uri(uri).and_then(|uri| self.lookup(&uri)));
+        if plugin.is_none() {
+            self.missing.borrow_mut().insert(uri);
+        }
+        plugin
+    }
+
+    /// Returns the ports of the plugin `uri`, from the cache if possible.
+    pub fn ports(&mut self, uri: &str) -> Option<&Ports> {
+        if !self.ports.contains_key(uri) {
+            let extra = self.extra(uri).map(|plugin| plugin.ports());
+            let cached =
+                || self.cache.as_ref().and_then(|cache| cache.get(uri));
+            let ports = extra.or_else(cached).or_else(|| {
+                let plugin = self.get(uri)?;
+                let ports = plugin.ports();
+                if let (Some(cache), Some(bundle)) =
+                    (&self.cache, plugin.bundle())
+                {
+                    cache.insert(uri, &bundle, &ports);
+                }
+                Some(ports)
+            });
+            self.ports.insert(uri.to_owned(), ports);
+        }
+        self.ports[uri].as_ref()
+    }
+
+    /// Forgets everything known about the plugin `uri`, including whether
+    /// it was missing, so that it is looked up again.
+    pub fn refresh(&mut self, uri: &str) {
+        self.missing.borrow_mut().remove(uri);
+        self.ports.remove(uri);
+        if let Some(cache) = &self.cache {
+            cache.remove(uri);
+        }
+    }
+
+    /// Returns every installed plugin. Plugins in more than one tree are
+    /// returned only once, from the tree that takes priority.
+    pub fn all(&self) -> Vec<Plugin<'_>> {
+        let trees = self.trees.iter().filter_map(Tree::full);
+        Self::collect(self.extra.iter().chain(trees))
+    }
+
+    /// Like [`Self::all`], but returns only the plugins loaded so far,
+    /// without loading any more bundles.
+    pub fn loaded(&self) -> Vec<Plugin<'_>> {
+        let trees = self.trees.iter().flat_map(|tree| {
+            let full = tree.full.get().and_then(Option::as_ref);
+            let partial = tree.partial.get().and_then(Option::as_ref);
+            full.into_iter().chain(partial.map(|(world, _)| world))
+        });
+        Self::collect(self.extra.iter().chain(trees))
+    }
+
+    /// Returns the plugins in `worlds`, skipping those already returned from
+    /// an earlier world.
+    fn collect<'a>(
+        worlds: impl Iterator<Item = &'a World>,
+    ) -> Vec<Plugin<'a>> {
+        let mut seen = HashSet::new();
+        let mut all = Vec::new();
+        for world in worlds {
+            for plugin in world.all() {
+                let plugin = Plugin {
+                    world: world.world,
+                    plugin,
+                    _phantom: PhantomData,
+                };
+                if seen.insert(plugin.uri()) {
+                    all.push(plugin);
+                }
+            }
+        }
+        all
+    }
+}
+
+impl Plugins {
+    /// Looks up the plugin `uri` in the bundles given to
+    /// [`Self::with_bundles`].
+    fn extra(&self, uri: &str) -> Option<Plugin<'_>> {
+        let world = self.extra.as_ref()?;
+        let find =
+            |uri: &str| world.plugin(&c_string(uri, Kind::InvalidUri, "uri")?);
+        find(uri).or_else(|| find(&normalize_uri(uri)?))
+    }
+
+    fn lookup(&self, uri: &str) -> Option<Plugin<'_>> {
+        let c_uri = c_string(uri, Kind::InvalidUri, "uri")?;
+        let partial = self
+            .trees
+            .iter()
+            .filter_map(|tree| tree.partial(&self.wanted, &self.missing))
+            .filter(|(_, found)| found.contains(uri))
+            .find_map(|(world, _)| world.plugin(&c_uri));
+        // The manifests may not mention the plugin in a form we recognize,
+        // so it must be looked for in every bundle before it's missing.
+        partial.or_else(|| {
+            self.trees.iter().find_map(|tree| tree.full()?.plugin(&c_uri))
+        })
+    }
+}
+
+/// The port classes reported in [`PortInfo::classes`], and their URIs.
+const PORT_CLASSES: &[(&str, &[u8])] = &[
+    ("input", b"http://lv2plug.in/ns/lv2core#InputPort\0"),
+    ("output", b"http://lv2plug.in/ns/lv2core#OutputPort\0"),
+    ("control", b"http://lv2plug.in/ns/lv2core#ControlPort\0"),
+    ("audio", b"http://lv2plug.in/ns/lv2core#AudioPort\0"),
+    ("cv", b"http://lv2plug.in/ns/lv2core#CVPort\0"),
+    ("atom", b"http://lv2plug.in/ns/ext/atom#AtomPort\0"),
+    ("event", b"http://lv2plug.in/ns/ext/event#EventPort\0"),
+];
+
+/// A description of one port of a plugin.
+#[derive(Debug)]
+pub struct PortInfo {
+    pub index: u32,
+    pub symbol: Option<String>,
+    pub name: Option<String>,
+    /// The port's direction and type (such as `input` and `control`), in
+    /// the order of [`PORT_CLASSES`].
+    pub classes: Vec<&'static str>,
+}
+
+/// An installed plugin, borrowed from [`Plugins`].
+#[derive(Clone, Copy)]
 pub struct Plugin<'a> {
     world: NonNull<lv::LilvWorld>,
     plugin: NonNull<lv::LilvPlugin>,
-    _phantom: PhantomData<&'a mut lv::LilvWorld>,
+    _phantom: PhantomData<&'a lv::LilvWorld>,
+}
+
+/// Copies the string value of `node`, which may be null.
+fn node_string(
+    node: *const lv::LilvNode,
+    get: unsafe extern "C" fn(*const lv::LilvNode) -> *const c_char,
+) -> Option<String> {
+    if node.is_null() {
+        return None;
+    }
+    let s = unsafe { get(node) };
+    if s.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned())
 }
 
 impl Plugin<'_> {
+    pub fn uri(&self) -> String {
+        let node = unsafe { lv::lilv_plugin_get_uri(self.plugin.as_ptr()) };
+        node_string(node, lv::lilv_node_as_uri).unwrap_or_default()
+    }
+
+    /// Returns the plugin's human-readable name.
+    pub fn name(&self) -> Option<String> {
+        let node = unsafe { lv::lilv_plugin_get_name(self.plugin.as_ptr()) };
+        let name = node_string(node, lv::lilv_node_as_string);
+        if !node.is_null() {
+            unsafe {
+                lv::lilv_node_free(node);
+            }
+        }
+        name
+    }
+
+    /// Returns the symbol of the port at `index`.
+    pub fn symbol(&self, index: u32) -> Option<String> {
+        let plugin = self.plugin.as_ptr();
+        let port = unsafe { lv::lilv_plugin_get_port_by_index(plugin, index) };
+        if port.is_null() {
+            return None;
+        }
+        let node = unsafe { lv::lilv_port_get_symbol(plugin, port) };
+        node_string(node, lv::lilv_node_as_string)
+    }
+
+    /// Describes the port at `index`.
+    pub fn port_info(&self, index: u32) -> Option<PortInfo> {
+        let plugin = self.plugin.as_ptr();
+        let port = unsafe { lv::lilv_plugin_get_port_by_index(plugin, index) };
+        if port.is_null() {
+            return None;
+        }
+        let symbol = unsafe { lv::lilv_port_get_symbol(plugin, port) };
+        let name = unsafe { lv::lilv_port_get_name(plugin, port) };
+        let info = PortInfo {
+            index,
+            symbol: node_string(symbol, lv::lilv_node_as_string),
+            name: node_string(name, lv::lilv_node_as_string),
+            classes: PORT_CLASSES
+                .iter()
+                .filter(|(_, uri)| {
+                    let node = NonNull::new(unsafe {
+                        lv::lilv_new_uri(
+                            self.world.as_ptr(),
+                            uri.as_ptr().cast(),
+                        )
+                    })
+                    .expect("lilv_new_uri failed");
+                    let is_a = unsafe {
+                        lv::lilv_port_is_a(plugin, port, node.as_ptr())
+                    };
+                    unsafe {
+                        lv::lilv_node_free(node.as_ptr());
+                    }
+                    is_a
+                })
+                .map(|&(class, _)| class)
+                .collect(),
+        };
+        if !name.is_null() {
+            unsafe {
+                lv::lilv_node_free(name);
+            }
+        }
+        Some(info)
+    }
+
     pub fn num_ports(&self) -> u32 {
         unsafe { lv::lilv_plugin_get_num_ports(self.plugin.as_ptr()) }
     }
 
-    pub fn port_index(&mut self, symbol: &str) -> Option<u32> {
-        let Ok(symbol) = CString::new(symbol) else {
-            eprintln!(
-                "warning: \\0 in symbol: \"{}\"",
-                symbol.escape_default(),
-            );
+    /// Returns the path of the bundle the plugin was loaded from, or
+    /// [`None`] if it isn't valid UTF-8.
+    pub fn bundle(&self) -> Option<PathBuf> {
+        let node =
+            unsafe { lv::lilv_plugin_get_bundle_uri(self.plugin.as_ptr()) };
+        if node.is_null() {
             return None;
-        };
+        }
+        let path = NonNull::new(unsafe {
+            lv::lilv_file_uri_parse(
+                lv::lilv_node_as_uri(node),
+                std::ptr::null_mut(),
+            )
+        })?;
+        let bundle = unsafe { CStr::from_ptr(path.as_ptr()) }
+            .to_str()
+            .ok()
+            .map(PathBuf::from);
+        unsafe {
+            lv::lilv_free(path.as_ptr().cast());
+        }
+        bundle
+    }
+
+    /// Returns the plugin's name and the indices of all of its ports.
+    pub fn ports(&self) -> Ports {
+        let num_ports = self.num_ports();
+        Ports {
+            name: self.name(),
+            num_ports,
+            ports: (0..num_ports)
+                .filter_map(|i| Some((self.symbol(i)?, i)))
+                .collect(),
+        }
+    }
+
+    pub fn port_index(&self, symbol: &str) -> Option<u32> {
+        if !valid_symbol(symbol) {
+            return None;
+        }
+        let symbol = c_string(symbol, Kind::InvalidSymbol, "symbol")?;
         let node = NonNull::new(unsafe {
             lv::lilv_new_string(self.world.as_ptr(), symbol.as_ptr())
         })
@@ -178,3 +1093,75 @@ impl Plugin<'_> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_ascii_uri() {
+        assert_eq!(normalize_uri("http://example.org/plug"), None);
+        assert_eq!(normalize_uri("http://example.org/a%2Fb"), None);
+        assert_eq!(
+            normalize_uri("http://example.org/a%2fb%c3"),
+            Some("http://example.org/a%2Fb%C3".to_owned()),
+        );
+    }
+
+    #[test]
+    fn normalize_malformed_escape() {
+        assert_eq!(normalize_uri("http://example.org/%zz"), None);
+        assert_eq!(normalize_uri("http://example.org/%a"), None);
+        assert_eq!(
+            normalize_uri("http://example.org/%zz%ab%"),
+            Some("http://example.org/%zz%AB%".to_owned()),
+        );
+    }
+
+    #[test]
+    fn normalize_emoji_uri() {
+        assert_eq!(
+            normalize_uri("urn:plug:\u{1f39b}"),
+            Some("urn:plug:%F0%9F%8E%9B".to_owned()),
+        );
+    }
+
+    #[test]
+    fn normalize_idn_uri() {
+        // Hosts are percent-encoded like the rest of the uri, not converted
+        // to punycode.
+        assert_eq!(
+            normalize_uri("http://b\u{fc}cher.example/plug#m\u{fc}"),
+            Some("http://b%C3%BCcher.example/plug#m%C3%BC".to_owned()),
+        );
+        assert_eq!(
+            normalize_uri("http://\u{4f8b}\u{3048}.jp/%e2"),
+            Some("http://%E4%BE%8B%E3%81%88.jp/%E2".to_owned()),
+        );
+    }
+
+    #[test]
+    fn c_string_max_len() {
+        let uri = "x".repeat(MAX_LEN);
+        assert!(c_string(&uri, Kind::InvalidUri, "uri").is_some());
+        let uri = "x".repeat(MAX_LEN + 1);
+        assert!(c_string(&uri, Kind::InvalidUri, "uri").is_none());
+    }
+
+    #[test]
+    fn c_string_max_len_counts_bytes() {
+        let emoji = "\u{1f39b}";
+        let uri = "x".repeat(MAX_LEN - emoji.len()) + emoji;
+        assert!(c_string(&uri, Kind::InvalidUri, "uri").is_some());
+        let uri = "x".repeat(MAX_LEN - emoji.len() + 1) + emoji;
+        assert!(c_string(&uri, Kind::InvalidUri, "uri").is_none());
+        let uri = emoji.repeat(MAX_LEN / emoji.len());
+        let c_uri = c_string(&uri, Kind::InvalidUri, "uri").unwrap();
+        assert_eq!(c_uri.as_bytes(), uri.as_bytes());
+    }
+
+    #[test]
+    fn c_string_nul() {
+        assert!(c_string("urn:a\0b", Kind::InvalidUri, "uri").is_none());
+    }
+}
@@ -19,19 +19,77 @@
 
 #![warn(clippy::undocumented_unsafe_blocks)]
 
-use std::ffi::OsString;
-use std::fmt::{Display, Write as _};
-use std::fs::File;
-use std::io::{self, BufWriter, Write};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
+use std::fs::{File, Metadata};
+use std::io::{self, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use fix_ardour_lv2_index::color::{self, Color, Stream};
+use fix_ardour_lv2_index::config::{self, Config};
+use fix_ardour_lv2_index::consistency::Consistency;
+use fix_ardour_lv2_index::diag::{self, Kind, Warning};
+use fix_ardour_lv2_index::dirs::Dirs;
+#[cfg(feature = "http")]
+use fix_ardour_lv2_index::http;
+use fix_ardour_lv2_index::index_map::IndexMap;
+use fix_ardour_lv2_index::inventory::{self, Inventory};
+use fix_ardour_lv2_index::lv2::{self, Plugins};
+use fix_ardour_lv2_index::missing::MissingCache;
+use fix_ardour_lv2_index::patch::Summary;
+use fix_ardour_lv2_index::patch::{self, PatchedSession, Resolver, Source};
+use fix_ardour_lv2_index::port_cache::PortCache;
+use fix_ardour_lv2_index::report::{self, FileReport, Report};
+use fix_ardour_lv2_index::session;
+use fix_ardour_lv2_index::{backup, filter, json, merge};
+
+/// Prints an error with [`diag::emit`].
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::diag::emit(
+            $crate::diag::Level::Error,
+            format_args!($($arg)*),
+        )
+    };
+}
+
+/// Prints a warning with [`diag::emit`], unless `-q` was given. Warnings
+/// about plugins should be reported with [`diag::warn`] instead.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::diag::emit(
+            $crate::diag::Level::Warning,
+            format_args!($($arg)*),
+        )
+    };
+}
+
+/// Prints an informational message with [`diag::emit`], unless `-q` was
+/// given.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::diag::emit(
+            $crate::diag::Level::Info,
+            format_args!($($arg)*),
+        )
+    };
+}
 
 mod args;
 use args::{Args, USAGE};
 
-mod lv2;
-mod patch;
-mod session;
+mod capabilities;
+mod completions;
+mod flock;
+mod gzip;
+mod pager;
+mod preflight;
+mod preserve;
+#[cfg(feature = "zip")]
+mod zip;
 
 fn write_display<P, T>(path: P, contents: &T) -> io::Result<()>
 where
@@ -43,35 +101,1597 @@ where
     writer.flush()
 }
 
-fn create_backup(path: &Path) -> io::Result<()> {
-    const BACKUP_EXT: &str = "orig";
-    let mut backup: PathBuf = OsString::from_iter([
-        path.as_os_str(),
-        ".".as_ref(),
-        BACKUP_EXT.as_ref(),
-    ])
-    .into();
-    let mut ext = String::new();
-    let mut i = 0;
-    loop {
-        match File::options().write(true).create_new(true).open(&backup) {
-            Ok(f) => {
-                drop(f);
-                std::fs::rename(path, &backup)?;
-                return Ok(());
+/// Whether backups of snapshots, named with a suffix, are treated as
+/// snapshots.
+#[derive(Clone, Copy, Debug)]
+enum Backups<'a> {
+    Exclude(&'a str),
+    Include(&'a str),
+}
+
+impl<'a> Backups<'a> {
+    /// The backups given with `--backup-suffix`, which are included with
+    /// `--include-backups`.
+    fn of(args: &'a args::RunArgs) -> Self {
+        let suffix = &args.backup.suffix;
+        if args.include_backups {
+            Self::Include(suffix)
+        } else {
+            Self::Exclude(suffix)
+        }
+    }
+}
+
+/// Whether `path` is named like a snapshot. Backups of snapshots are
+/// skipped unless included by `backups`, even if the suffix they are named
+/// with ends with the snapshot extension.
+fn is_snapshot(path: &Path, backups: Backups<'_>) -> bool {
+    const SESSION_EXT: &str = "ardour";
+    let is_session =
+        |path: &Path| path.extension() == Some(SESSION_EXT.as_ref());
+    let (Backups::Exclude(suffix) | Backups::Include(suffix)) = backups;
+    match backup::original(path, suffix).filter(|p| is_session(p)) {
+        Some(_) => matches!(backups, Backups::Include(_)),
+        None => is_session(path),
+    }
+}
+
+/// Returns the snapshots in `dir` named in `names`, or all of them if
+/// `names` is empty. Backups of snapshots are skipped unless included by
+/// `backups`.
+fn snapshots(
+    dir: &Path,
+    names: &[OsString],
+    backups: Backups<'_>,
+) -> Result<Vec<PathBuf>, ()> {
+    let read_error = |e| {
+        error!("could not read session directory: {e}");
+    };
+    let mut all = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        if is_snapshot(&path, backups) && path.is_file() {
+            all.push(path);
+        }
+    }
+    all.sort_unstable();
+    if all.is_empty() {
+        error!("no snapshots found in {}", dir.display());
+        return Err(());
+    }
+    select_snapshots(all, names)
+}
+
+/// Returns the snapshots in `all` named in `names`, or all of them if
+/// `names` is empty.
+fn select_snapshots(
+    all: Vec<PathBuf>,
+    names: &[OsString],
+) -> Result<Vec<PathBuf>, ()> {
+    if names.is_empty() {
+        return Ok(all);
+    }
+    let mut selected = Vec::new();
+    let mut missing = false;
+    for name in names {
+        let Some(path) = all.iter().find(|p| {
+            p.file_stem() == Some(name) || p.file_name() == Some(name)
+        }) else {
+            error!("no snapshot named {}", name.to_string_lossy());
+            missing = true;
+            continue;
+        };
+        if !selected.contains(path) {
+            selected.push(path.clone());
+        }
+    }
+    if missing {
+        let mut available = String::from("available snapshots:");
+        for path in &all {
+            let stem = path.file_stem().unwrap_or_default();
+            available += &format!("\n  {}", stem.to_string_lossy());
+        }
+        info!("{available}");
+        return Err(());
+    }
+    Ok(selected)
+}
+
+#[cfg(feature = "http")]
+fn http_source(
+    url: &str,
+    dirs: &Dirs,
+    caching: Caching,
+) -> Result<Source, ()> {
+    let cache = caching.dir(dirs).map(|dir| dir.join("http"));
+    let refresh = caching == Caching::Refresh;
+    Ok(Source::Http(http::Client::new(url, cache, refresh)))
+}
+
+#[cfg(not(feature = "http"))]
+fn http_source(_: &str, _: &Dirs, _: Caching) -> Result<Source, ()> {
+    error!("the http backend requires the `http` feature");
+    Err(())
+}
+
+/// How cached plugin information is used.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Caching {
+    Enabled,
+    /// Ignore cached information, replacing it.
+    Refresh,
+    Disabled,
+}
+
+impl Caching {
+    fn of(args: &args::RunArgs) -> Result<Self, ()> {
+        match (args.no_cache, args.refresh_cache) {
+            (true, true) => {
+                error!("--no-cache cannot be used with --refresh-cache");
+                Err(())
+            }
+            (true, false) => Ok(Self::Disabled),
+            (false, true) => Ok(Self::Refresh),
+            (false, false) => Ok(Self::Enabled),
+        }
+    }
+
+    /// The cache directory, unless caching is disabled.
+    fn dir(self, dirs: &Dirs) -> Option<&Path> {
+        dirs.cache().filter(|_| self != Self::Disabled)
+    }
+}
+
+/// Adds the plugins used by the session `xml` to `uris`.
+fn add_plugin_uris(
+    xml: &str,
+    uris: &mut BTreeSet<String>,
+) -> Result<(), roxmltree::Error> {
+    let doc = roxmltree::Document::parse(xml)?;
+    uris.extend(session::plugin_uris(&doc).map(str::to_owned));
+    Ok(())
+}
+
+/// Returns the plugins used by the snapshots `paths`, so that only their
+/// bundles need to be loaded. Snapshots that can't be read are skipped;
+/// they're reported when they're fixed.
+fn used_plugins<P: AsRef<Path>>(paths: &[P]) -> BTreeSet<String> {
+    let mut uris = BTreeSet::new();
+    for path in paths {
+        if let Ok((xml, _)) = gzip::read_file(path.as_ref()) {
+            let _ = add_plugin_uris(&xml, &mut uris);
+        }
+    }
+    uris
+}
+
+/// Loads plugins with lilv. If `trees` is not empty, plugins are loaded from
+/// those directories, in order of priority, instead of from `LV2_PATH`.
+/// Plugins in `bundles` take priority over both. Only the bundles providing
+/// `uris` are loaded unless a plugin can't be found in them.
+fn lilv_source(
+    dirs: &Dirs,
+    trees: &[PathBuf],
+    bundles: &[PathBuf],
+    uris: &BTreeSet<String>,
+    caching: Caching,
+) -> Result<Source, ()> {
+    let search_path = lv2::search_path(trees);
+    let unreadable = lv2::unreadable(&search_path);
+    for (path, e) in &unreadable {
+        diag::warn(Warning::new(
+            Kind::UnreadableBundle,
+            format!(
+                "could not read {}: {e}; plugins in it will appear to be \
+                 missing",
+                path.display(),
+            ),
+        ));
+    }
+    // Plugins in unreadable bundles would be recorded as missing, and fixing
+    // the permissions doesn't change the search path's fingerprint.
+    let dir = caching.dir(dirs).filter(|_| unreadable.is_empty());
+    let dir = dir.map(|d| d.join("missing"));
+    let mut missing = MissingCache::load(dir.as_deref(), &search_path);
+    if caching == Caching::Refresh {
+        missing.clear();
+    }
+    if let Some(bundle) = bundles.iter().find(|b| !b.is_dir()) {
+        error!("{} is not a bundle directory", bundle.display());
+        return Err(());
+    }
+    let uris = uris.iter().map(String::as_str);
+    diag::progress("loading lv2 plugins...");
+    let plugins = Plugins::for_uris(missing, trees, uris)
+        .and_then(|plugins| match bundles {
+            [] => Ok(plugins),
+            _ => plugins.with_bundles(bundles),
+        })
+        .map_err(|e| {
+            error!("could not retrieve lv2 metadata: {e}");
+        })?;
+    diag::clear_progress();
+    Ok(Source::Lv2(Box::new(match caching.dir(dirs) {
+        Some(dir) => plugins.with_cache(PortCache::new(
+            dir.join("ports"),
+            search_path,
+            caching == Caching::Refresh,
+        )),
+        None => plugins,
+    })))
+}
+
+fn index_map_source(path: &Path) -> Result<Source, ()> {
+    IndexMap::load(path).map(Source::IndexMap).map_err(|e| {
+        error!("could not load index map: {e}");
+    })
+}
+
+fn port_map_source(path: &Path) -> Result<Source, ()> {
+    Inventory::load(path).map(Source::PortMap).map_err(|e| {
+        error!("could not load port map: {e}");
+    })
+}
+
+/// Returns the plugin trees given with `--lv2-path` or `--plugins-as-of`.
+fn lv2_trees(args: &args::RunArgs) -> Result<Vec<PathBuf>, ()> {
+    match (&args.lv2_path, &args.plugins_as_of) {
+        (Some(_), Some(_)) => {
+            error!("--lv2-path cannot be used with --plugins-as-of");
+            Err(())
+        }
+        (Some(path), None) => Ok(lv2::split_path(path)),
+        (None, dir) => Ok(dir.iter().cloned().collect()),
+    }
+}
+
+fn load_source(
+    args: &args::RunArgs,
+    dirs: &Dirs,
+    trees: &[PathBuf],
+    uris: &BTreeSet<String>,
+    caching: Caching,
+) -> Result<Source, ()> {
+    let backends = [
+        ("--index-map", args.index_map.is_some()),
+        ("--port-map", args.port_map.is_some()),
+        ("--backend", args.backend.is_some()),
+    ];
+    let mut given = backends.iter().filter(|(_, given)| *given);
+    if let (Some((a, _)), Some((b, _))) = (given.next(), given.next()) {
+        error!("{a} cannot be used with {b}");
+        return Err(());
+    }
+    if let Some(url) = &args.backend {
+        let Some(url) = url.to_str() else {
+            error!("invalid backend url: {}", url.to_string_lossy());
+            return Err(());
+        };
+        return http_source(url, dirs, caching);
+    }
+    if let Some(path) = &args.index_map {
+        return index_map_source(path);
+    }
+    if let Some(path) = &args.port_map {
+        return port_map_source(path);
+    }
+    lilv_source(dirs, trees, &args.bundles, uris, caching)
+}
+
+/// Loads the configuration file given with `--config`, or the default one
+/// if it exists.
+fn load_config(args: &args::RunArgs, dirs: &Dirs) -> Result<Config, ()> {
+    let path = match (&args.config, dirs.config()) {
+        (Some(path), _) => path.clone(),
+        (None, Some(dir)) => dir.join(config::FILE_NAME),
+        (None, None) => return Ok(Config::default()),
+    };
+    match Config::load(&path) {
+        Ok(config) => Ok(config),
+        Err(config::Error::Io(e))
+            if args.config.is_none()
+                && e.kind() == io::ErrorKind::NotFound =>
+        {
+            Ok(Config::default())
+        }
+        Err(e) => {
+            error!("could not load {}: {e}", path.display());
+            Err(())
+        }
+    }
+}
+
+/// Loads the resolver for fixing sessions that use the plugins `uris`.
+fn load_resolver(
+    args: &args::RunArgs,
+    dirs: &Dirs,
+    uris: &BTreeSet<String>,
+) -> Result<Resolver, ()> {
+    let warnings = diag::counts();
+    let config = load_config(args, dirs)?;
+    let default_is_lilv = args.index_map.is_none()
+        && args.port_map.is_none()
+        && args.backend.is_none();
+    let trees = lv2_trees(args)?;
+    let caching = Caching::of(args)?;
+    let source = load_source(args, dirs, &trees, uris, caching)?;
+    let mut resolver = Resolver::new(source);
+    let mut added = Vec::<(&config::Backend, usize)>::new();
+    for pin in &config.pins {
+        let source = match &pin.backend {
+            config::Backend::Lilv if default_is_lilv => 0,
+            backend => match added.iter().find(|(b, _)| *b == backend) {
+                Some(&(_, i)) => i,
+                None => {
+                    let i = resolver.add(match backend {
+                        config::Backend::Lilv => {
+                            let bundles = &args.bundles;
+                            lilv_source(dirs, &trees, bundles, uris, caching)?
+                        }
+                        config::Backend::IndexMap(path) => {
+                            index_map_source(path)?
+                        }
+                        config::Backend::PortMap(path) => {
+                            port_map_source(path)?
+                        }
+                        config::Backend::Http(url) => {
+                            http_source(url, dirs, caching)?
+                        }
+                    });
+                    added.push((backend, i));
+                    i
+                }
+            },
+        };
+        resolver.pin(pin.uri.clone(), source);
+    }
+    for uri in &args.refresh_plugins {
+        resolver.refresh(uri);
+    }
+    for uri in &args.simulate_missing {
+        resolver.simulate_missing(uri.clone());
+    }
+    check_strict(args, warnings)?;
+    Ok(resolver)
+}
+
+/// Fails if any warnings denied with `--deny` (or under `--strict`, any
+/// warnings at all) were emitted since [`diag::counts`] returned `since`.
+fn check_strict(args: &args::RunArgs, since: diag::Counts) -> Result<(), ()> {
+    let counts = diag::counts();
+    if counts.denied > since.denied {
+        error!("warnings denied with --deny occurred");
+        return Err(());
+    }
+    if args.strict && counts.warnings > since.warnings {
+        error!("warnings occurred and --strict was given");
+        return Err(());
+    }
+    Ok(())
+}
+
+fn check_verify(
+    args: &args::RunArgs,
+    patched: &PatchedSession<'_>,
+) -> Result<(), ()> {
+    if !args.verify {
+        return Ok(());
+    }
+    patched.verify().map_err(|e| {
+        error!("verification failed: {e}");
+    })
+}
+
+fn export_index_map(args: &args::RunArgs, map: &IndexMap) -> Result<(), ()> {
+    let Some(path) = &args.export_index_map else {
+        return Ok(());
+    };
+    write_display(path, map).map_err(|e| {
+        error!("could not write index map: {e}");
+    })
+}
+
+/// Writes `patched` to `path`, gzip-compressed if `compress` is true.
+fn write_output(
+    path: &Path,
+    patched: &PatchedSession<'_>,
+    compress: bool,
+) -> Result<(), ()> {
+    let write =
+        || gzip::write(patched, BufWriter::new(File::create(path)?), compress);
+    write().map_err(|e| {
+        error!("could not write output: {e}");
+    })
+}
+
+/// Returns the path that the session at `path` is written to with
+/// `--output-dir` or `--suffix`, creating the output directory if needed.
+/// Fails if that's the session itself, which would be overwritten without a
+/// backup.
+fn output_path(output: &args::Output, path: &Path) -> Result<PathBuf, ()> {
+    let output = match output {
+        args::Output::Dir(dir) => {
+            std::fs::create_dir_all(dir).map_err(|e| {
+                error!("could not create {}: {e}", dir.display());
+            })?;
+            dir.join(file_name(path))
+        }
+        args::Output::Suffix(suffix) => {
+            let mut output = path.as_os_str().to_owned();
+            output.push(suffix);
+            output.into()
+        }
+        _ => unreachable!(),
+    };
+    let canonical = |p: &Path| std::fs::canonicalize(p).ok();
+    if canonical(&output).is_some_and(|o| Some(o) == canonical(path)) {
+        error!(
+            "{} would overwrite the session itself\nUse a different \
+             --output-dir, or omit it to fix the session in-place with a \
+             backup.",
+            output.display(),
+        );
+        return Err(());
+    }
+    Ok(output)
+}
+
+/// Writes the index map and reports requested in `args`, given the summary
+/// of each fixed session. If `list_changes` is true, a dry run prints a text
+/// report when no other report is written to stdout.
+fn write_results<I>(
+    args: &args::RunArgs,
+    summaries: I,
+    list_changes: bool,
+) -> Result<(), ()>
+where
+    I: IntoIterator<Item = (String, Summary)>,
+{
+    let mut index_map = IndexMap::new();
+    let mut report = Report::new();
+    for (path, summary) in summaries {
+        index_map.merge(summary.index_map);
+        report.files.push(FileReport {
+            path,
+            changes: summary.changes,
+            skipped: summary.skipped,
+            bypassed: summary.bypassed,
+            verified: summary.verified,
+            unchecked: summary.unchecked,
+            plugins: summary.plugins,
+            missing_plugins: summary.missing_plugins,
+        });
+    }
+    let mut result = export_index_map(args, &index_map);
+    let dry_run_report = args::ReportArg {
+        format: report::Format::Text,
+        path: None,
+    };
+    let dry_run_report = Some(&dry_run_report)
+        .filter(|_| args.dry_run && list_changes && !stdout_report(args));
+    for arg in args.reports.iter().chain(dry_run_report) {
+        let Some(path) = &arg.path else {
+            let color = color::enabled(Stream::Stdout);
+            let rendered = report.render_colored(arg.format, color);
+            match arg.format {
+                report::Format::Text if !args.no_pager => {
+                    pager::print(&rendered.to_string());
+                }
+                _ => print!("{rendered}"),
+            }
+            continue;
+        };
+        let rendered = report.render(arg.format);
+        if let Err(e) = write_display(path, &rendered) {
+            error!("could not write report: {e}");
+            result = Err(());
+        }
+    }
+    result
+}
+
+fn patch_options(args: &args::RunArgs) -> patch::Options {
+    patch::Options {
+        conservative: args.conservative,
+        annotate: !args.no_annotation,
+        skip_bypassed: args.skip_bypassed,
+        plugin_name: args.plugin_name.as_deref().map(filter::NameFilter::new),
+    }
+}
+
+/// Checks that no more than `--max-change-ratio` of the session's parameters
+/// were changed, unless `--force` was given.
+fn check_change_ratio(
+    args: &args::RunArgs,
+    patched: &PatchedSession<'_>,
+) -> Result<(), ()> {
+    const MAX_PLUGINS: usize = 5;
+    let Some(max) = args.max_change_ratio else {
+        return Ok(());
+    };
+    let summary = patched.summary();
+    let changed = summary.changes.len();
+    let total = summary.parameters;
+    let ratio = changed as f64 / total.max(1) as f64;
+    if ratio <= max {
+        return Ok(());
+    }
+    let mut message = format!(
+        "{changed} of {total} parameters ({:.0}%) would be changed, more \
+         than --max-change-ratio allows",
+        ratio * 100.0,
+    );
+    let mut counts = HashMap::<_, usize>::new();
+    for change in &summary.changes {
+        *counts.entry(change.uri.as_str()).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    message += "\nmost changes are in:";
+    for (uri, n) in counts.iter().take(MAX_PLUGINS) {
+        message += &format!("\n  {uri}: {n}");
+    }
+    if args.force || args.dry_run {
+        warning!("{message}");
+        return Ok(());
+    }
+    error!(
+        "{message}\nThe installed plugins may be the wrong versions.\nUse \
+         --force to save the session anyway."
+    );
+    Err(())
+}
+
+/// Whether `path` is named like a backup made by this program.
+fn is_backup(path: &Path, args: &args::RunArgs) -> bool {
+    backup::original(path, &args.backup.suffix).is_some()
+}
+
+/// Returns the lock file Ardour keeps next to the session at `path` while
+/// the session is open, if there is one. Its name is the session's name,
+/// which is that of one of the snapshots in the directory.
+fn ardour_lock(path: &Path) -> Option<PathBuf> {
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+    let dir = dir.unwrap_or(Path::new("."));
+    std::fs::read_dir(dir).ok()?.find_map(|entry| {
+        let lock = entry.ok()?.path();
+        (lock.extension() == Some("lock".as_ref())
+            && lock.with_extension("ardour").is_file())
+        .then_some(lock)
+    })
+}
+
+/// Whether the snapshot at `path` was modified after Ardour created `lock`,
+/// so Ardour has saved it while the session was open. A snapshot that is
+/// older than the lock doesn't mean the lock is stale, as a session that
+/// was just opened hasn't been saved yet, so this only changes how the
+/// lock is described.
+fn saved_since_locked(path: &Path, lock: &Path) -> bool {
+    let modified = |p| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    modified(path).zip(modified(lock)).is_some_and(|(s, l)| s > l)
+}
+
+/// Locks the session at `path` against other runs of this program fixing
+/// it at the same time, unless this is a dry run.
+fn lock_session(
+    path: &Path,
+    args: &args::RunArgs,
+) -> Result<Option<flock::SessionLock>, ()> {
+    if args.dry_run {
+        return Ok(None);
+    }
+    let wait = || {
+        info!("waiting for another process fixing {}", path.display());
+    };
+    flock::SessionLock::acquire(path, wait).map(Some).map_err(|e| {
+        error!("could not lock {}: {e}", path.display());
+    })
+}
+
+fn create_backup(path: &Path, args: &args::RunArgs) -> Result<PathBuf, ()> {
+    let backup = backup::create(path, &args.backup).map_err(|e| {
+        error!("could not create backup: {e}");
+    })?;
+    if let Err(e) = backup::prune(path, &args.backup) {
+        warning!("could not remove old backups: {e}");
+    }
+    Ok(backup)
+}
+
+/// Backs up the file at `path` and replaces its contents with those written
+/// by `write`, which is given the path of a file with the original contents.
+///
+/// With [`backup::Strategy::Rename`], the original is moved to the backup,
+/// so the new contents are written to a temporary file next to it first and
+/// moved into place once complete. Otherwise, the original is copied to the
+/// backup and then overwritten, so it keeps its inode: hard links to it and
+/// programs watching it see the new contents. If overwriting it fails, the
+/// backup is moved back. Returns the path of the backup.
+fn replace_file<F>(
+    path: &Path,
+    args: &args::RunArgs,
+    write: F,
+) -> Result<PathBuf, ()>
+where
+    F: FnOnce(&Path, &File) -> io::Result<()>,
+{
+    let write_error = |e| {
+        error!("could not write {}: {e}", path.display());
+    };
+    if args.backup.strategy != backup::Strategy::Rename {
+        let backup = create_backup(path, args)?;
+        let file = File::options()
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .map_err(write_error)?;
+        if let Err(e) = write(&backup, &file).and_then(|()| file.sync_all()) {
+            write_error(e);
+            restore_backup(&backup, path);
+            return Err(());
+        }
+        return Ok(backup);
+    }
+    let mut tmp_name = path.file_name().unwrap().to_owned();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let file =
+        File::options().write(true).create_new(true).open(&tmp).map_err(
+            |e| {
+                error!("could not create {}: {e}", tmp.display());
+            },
+        )?;
+    let backup = write(path, &file)
+        .and_then(|()| file.sync_all())
+        .map_err(write_error)
+        .and_then(|()| create_backup(path, args));
+    drop(file);
+    let Ok(backup) = backup else {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(());
+    };
+    if let Err(e) = std::fs::rename(&tmp, path) {
+        error!("could not move {} into place: {e}", tmp.display());
+        restore_backup(&backup, path);
+        return Err(());
+    }
+    Ok(backup)
+}
+
+/// Moves `backup` back to `path` after the file at `path` couldn't be
+/// replaced.
+fn restore_backup(backup: &Path, path: &Path) {
+    match backup::restore(backup, path) {
+        Ok(()) => info!("restored {} from its backup", path.display()),
+        Err(e) => error!(
+            "could not restore {} from {}: {e}",
+            path.display(),
+            backup.display(),
+        ),
+    }
+}
+
+/// Backs up the session at `path` and overwrites it with `patched`,
+/// gzip-compressed if `compress` is true, with [`replace_file`]. If `path`
+/// is a symlink, the file it points to is backed up and patched instead, so
+/// the link is kept. Returns the path of the backup.
+fn save_in_place(
+    path: &Path,
+    args: &args::RunArgs,
+    patched: &PatchedSession<'_>,
+    compress: bool,
+) -> Result<PathBuf, ()> {
+    let is_link = std::fs::symlink_metadata(path)
+        .is_ok_and(|m| m.file_type().is_symlink());
+    let target;
+    let path = if is_link {
+        target = std::fs::canonicalize(path).map_err(|e| {
+            error!("could not resolve symlink: {e}");
+        })?;
+        &target
+    } else {
+        path
+    };
+    if let Some(lock) = ardour_lock(path).filter(|_| !args.ignore_lock) {
+        error!(
+            "{} is open in Ardour ({} exists)\n{}\nClose the session \
+             first, or use --ignore-lock if Ardour isn't running.",
+            path.display(),
+            Path::new(lock.file_name().unwrap()).display(),
+            if saved_since_locked(path, &lock) {
+                "Ardour has saved it since opening the session."
+            } else {
+                "It hasn't been saved since the session was opened, so if \
+                 Ardour crashed, the lock file may have been left behind."
+            },
+        );
+        return Err(());
+    }
+    let metadata = std::fs::metadata(path).map_err(|e| {
+        error!("could not read session file: {e}");
+    })?;
+    let backup = replace_file(path, args, |_, file| {
+        gzip::write(patched, BufWriter::new(file), compress)
+    })?;
+    restore_metadata(path, &metadata, args);
+    Ok(backup)
+}
+
+/// Gives the file at `path`, which replaced a file with `metadata`, the
+/// original's permissions and ownership (and modification time, with
+/// `--preserve-mtime`).
+fn restore_metadata(path: &Path, metadata: &Metadata, args: &args::RunArgs) {
+    if let Err(e) = preserve::restore(path, metadata, args.preserve_mtime) {
+        warning!("could not preserve metadata of {}: {e}", path.display(),);
+    }
+}
+
+/// Fixes the snapshot at `path`, in place or, with `--output-dir` or
+/// `--suffix`, writing it to a new file.
+fn fix_snapshot(
+    path: &Path,
+    args: &args::RunArgs,
+    resolver: &mut Resolver,
+) -> Result<Summary, ()> {
+    let _lock = match args.output {
+        args::Output::InPlace => lock_session(path, args)?,
+        _ => None,
+    };
+    let (xml, compressed) = gzip::read_file(path).map_err(|e| {
+        error!("could not read session file: {e}");
+    })?;
+    let warnings = diag::counts();
+    let patched =
+        patch::patch(&xml, resolver, patch_options(args)).map_err(|e| {
+            error!("{e}");
+        })?;
+    check_strict(args, warnings)?;
+    check_change_ratio(args, &patched)?;
+    check_verify(args, &patched)?;
+    let compress = compressed || args.compress;
+    match &args.output {
+        _ if args.dry_run => {}
+        args::Output::Dir(_) | args::Output::Suffix(_) => {
+            let output = output_path(&args.output, path)?;
+            write_output(&output, &patched, compress)?;
+        }
+        _ => {
+            save_in_place(path, args, &patched, compress)?;
+        }
+    }
+    Ok(patched.into_summary())
+}
+
+/// How many times deferred snapshots are retried before giving up.
+const SETTLE_RETRIES: u32 = 3;
+
+/// Returns how much longer to wait before the file at `path` can be
+/// considered idle (not modified within `window`), or [`None`] if it
+/// already is.
+fn time_until_idle(path: &Path, window: Duration) -> Option<Duration> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    // A modification time in the future counts as just modified.
+    let age = modified.elapsed().unwrap_or_default();
+    window.checked_sub(age).filter(|d| !d.is_zero())
+}
+
+/// Summarizes which missing plugins kept snapshots from being fully fixed.
+fn report_missing(summaries: &[(String, Summary)]) {
+    let mut plugins = BTreeMap::<&str, usize>::new();
+    let mut blocked = 0;
+    for (_, summary) in summaries {
+        blocked += usize::from(!summary.missing_plugins.is_empty());
+        for uri in &summary.missing_plugins {
+            *plugins.entry(uri).or_default() += 1;
+        }
+    }
+    if blocked == 0 {
+        return;
+    }
+    let color = diag::color();
+    let mut message = format!(
+        "{blocked} of {} snapshots use plugins that are not installed \
+         (snapshots affected by each plugin):",
+        summaries.len(),
+    );
+    for (uri, n) in plugins {
+        message += &format!("\n  {}: {n}", Color::Red.paint(uri, color));
+    }
+    warning!("{message}");
+}
+
+/// What fixing sessions did (or would do, in a dry run), which determines
+/// the exit status.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Outcome {
+    /// No indices needed to be changed.
+    Unchanged,
+    /// Some indices were changed. With `--check`, some sessions had
+    /// indices that don't match the installed plugins or couldn't be fully
+    /// checked.
+    Changed,
+}
+
+impl Outcome {
+    /// Determines the outcome from the summary of each session. With
+    /// `--check`, each session that needs fixing is described.
+    fn of(args: &args::RunArgs, summaries: &[(String, Summary)]) -> Self {
+        let mut outcome = Self::Unchanged;
+        for (path, summary) in summaries {
+            let wrong = summary.changes.len();
+            if !args.check {
+                if wrong > 0 {
+                    outcome = Self::Changed;
+                }
+                continue;
+            }
+            let skipped = summary.skipped.len();
+            let missing = summary.missing_plugins.len();
+            if wrong + skipped + missing == 0 {
+                continue;
+            }
+            info!(
+                "{path} needs fixing: {wrong} wrong indices, {skipped} \
+                 unresolved parameters, {missing} missing plugins",
+            );
+            outcome = Self::Changed;
+        }
+        outcome
+    }
+}
+
+/// Whether any report is written to stdout.
+fn stdout_report(args: &args::RunArgs) -> bool {
+    args.reports.iter().any(|r| r.path.is_none())
+}
+
+/// How a snapshot would be affected by fixing it, shown by a dry run of a
+/// session directory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Status {
+    /// No indices need to be changed.
+    Clean,
+    /// Some indices would be changed.
+    NeedsFix,
+    /// Some plugins are missing, so the snapshot can't be fully checked
+    /// (whether or not other indices would be changed).
+    CannotVerify,
+    /// The snapshot couldn't be read or parsed.
+    Error,
+}
+
+impl Status {
+    const ALL: [Self; 4] =
+        [Self::Clean, Self::NeedsFix, Self::CannotVerify, Self::Error];
+
+    fn of(summary: &Summary) -> Self {
+        if !summary.missing_plugins.is_empty() {
+            Self::CannotVerify
+        } else if summary.changes.is_empty() {
+            Self::Clean
+        } else {
+            Self::NeedsFix
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Self::Clean => Color::Green,
+            Self::NeedsFix => Color::Yellow,
+            Self::CannotVerify | Self::Error => Color::Red,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Clean => "clean",
+            Self::NeedsFix => "needs-fix",
+            Self::CannotVerify => "cannot-verify",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Returns the file name of `path`, or `path` itself if it has none, for
+/// listing files concisely.
+fn file_name(path: &Path) -> &Path {
+    path.file_name().map_or(path, Path::new)
+}
+
+/// Prints the status of each snapshot and the totals, to stdout unless a
+/// report is written there.
+fn print_statuses(args: &args::RunArgs, statuses: &[(&PathBuf, Status)]) {
+    let color = color::enabled(if stdout_report(args) {
+        Stream::Stderr
+    } else {
+        Stream::Stdout
+    });
+    let mut table = String::new();
+    for (path, status) in statuses {
+        table += &format!(
+            "{} {}\n",
+            status
+                .color()
+                .paint(format_args!("{:<14}", status.as_str()), color),
+            file_name(path).display(),
+        );
+    }
+    let totals: Vec<_> = Status::ALL
+        .iter()
+        .map(|&s| {
+            let n = statuses.iter().filter(|(_, t)| *t == s).count();
+            format!("{n} {}", s.as_str())
+        })
+        .collect();
+    table += &format!("total: {}\n", totals.join(", "));
+    if stdout_report(args) {
+        eprint!("{table}");
+    } else {
+        print!("{table}");
+    }
+}
+
+fn fix_directory(
+    dir: &Path,
+    args: &args::RunArgs,
+    dirs: &Dirs,
+) -> Result<Outcome, ()> {
+    if matches!(args.output, args::Output::Stdout | args::Output::Path(_)) {
+        error!("-o cannot be used with a session directory");
+        return Err(());
+    }
+    let paths = snapshots(dir, &args.snapshots, Backups::of(args))?;
+    fix_snapshots(&paths, args, dirs)
+}
+
+/// Fixes several session files given on the command line.
+fn fix_files(
+    paths: &[PathBuf],
+    args: &args::RunArgs,
+    dirs: &Dirs,
+) -> Result<Outcome, ()> {
+    if matches!(args.output, args::Output::Stdout | args::Output::Path(_)) {
+        error!("-o cannot be used with multiple sessions");
+        return Err(());
+    }
+    if matches!(args.output, args::Output::Dir(_)) {
+        let mut names = HashSet::new();
+        if let Some(path) = paths.iter().find(|p| !names.insert(file_name(p)))
+        {
+            error!(
+                "more than one session is named {}, so they can't all be \
+                 written to --output-dir",
+                file_name(path).display(),
+            );
+            return Err(());
+        }
+    }
+    for path in paths {
+        if path.is_dir() {
+            error!(
+                "{} is a directory, which must be the only session \
+                 given",
+                path.display(),
+            );
+            return Err(());
+        }
+        if is_zip(path) {
+            error!(
+                "{} is an archive, which must be the only session \
+                 given",
+                path.display(),
+            );
+            return Err(());
+        }
+        if !args.include_backups && is_backup(path, args) {
+            error!(
+                "{} looks like a backup\nUse --include-backups to fix it \
+                 anyway.",
+                path.display(),
+            );
+            return Err(());
+        }
+    }
+    fix_snapshots(paths, args, dirs)
+}
+
+/// Fixes each of the snapshots in `paths` in place, using one resolver for
+/// all of them.
+fn fix_snapshots(
+    paths: &[PathBuf],
+    args: &args::RunArgs,
+    dirs: &Dirs,
+) -> Result<Outcome, ()> {
+    let mut resolver = load_resolver(args, dirs, &used_plugins(paths))?;
+    let mut summaries = Vec::new();
+    let mut statuses = Vec::new();
+    let mut result = Ok(());
+    let mut pending: Vec<_> = paths.iter().collect();
+    let mut done = 0;
+    for attempt in 0..=SETTLE_RETRIES {
+        let mut deferred = Vec::new();
+        let mut wait = Duration::ZERO;
+        for path in pending {
+            if let Some(t) = time_until_idle(path, args.settle_time) {
+                wait = wait.max(t);
+                deferred.push(path);
+                continue;
             }
-            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
-                i += 1;
-                ext.clear();
-                write!(ext, "{BACKUP_EXT}{i}").unwrap();
-                backup.set_extension(&ext);
+            done += 1;
+            diag::progress(format_args!(
+                "fixing {done}/{}: {}",
+                paths.len(),
+                file_name(path).display(),
+            ));
+            match fix_snapshot(path, args, &mut resolver) {
+                Ok(summary) => {
+                    statuses.push((path, Status::of(&summary)));
+                    summaries.push((path.display().to_string(), summary))
+                }
+                Err(()) => {
+                    statuses.push((path, Status::Error));
+                    error!("could not fix snapshot {}", path.display(),);
+                    result = Err(());
+                }
             }
-            Err(e) => return Err(e),
         }
+        pending = deferred;
+        if pending.is_empty() || attempt == SETTLE_RETRIES {
+            break;
+        }
+        diag::progress(format_args!(
+            "waiting for {} snapshots to be saved...",
+            pending.len(),
+        ));
+        std::thread::sleep(wait);
+    }
+    diag::clear_progress();
+    if !pending.is_empty() {
+        let mut message =
+            String::from("snapshots still being modified were not fixed:");
+        for path in &pending {
+            message += &format!("\n  {}", path.display());
+        }
+        error!("{message}");
+        result = Err(());
+    }
+    report_missing(&summaries);
+    if args.dry_run {
+        print_statuses(args, &statuses);
+    }
+    let outcome = Outcome::of(args, &summaries);
+    write_results(args, summaries, false).and(result)?;
+    Ok(outcome)
+}
+
+/// Whether the file at `path` is a zip archive.
+fn is_zip(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    File::open(path).and_then(|mut f| f.read_exact(&mut magic)).is_ok()
+        && magic == *b"PK\x03\x04"
+}
+
+/// Converts a zip error to an I/O error, for [`replace_file`].
+#[cfg(feature = "zip")]
+fn zip_io(e: zip::Error) -> io::Error {
+    match e {
+        zip::Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+/// Writes the archive `archive` to `path`, with the entries in `replaced`
+/// replaced.
+#[cfg(feature = "zip")]
+fn write_archive(
+    archive: &mut zip::Archive,
+    path: &Path,
+    replaced: &HashMap<usize, Vec<u8>>,
+) -> Result<(), zip::Error> {
+    let file = File::create(path)?;
+    archive.write_to(BufWriter::new(file), replaced)
+}
+
+/// Fixes the snapshots in the zip archive at `path` (such as one made with
+/// Ardour's "Archive Session"), writing a repacked archive. Entries other
+/// than the fixed snapshots are copied unchanged.
+#[cfg(feature = "zip")]
+fn fix_archive(
+    path: &Path,
+    args: &args::RunArgs,
+    dirs: &Dirs,
+) -> Result<Outcome, ()> {
+    if args.compress {
+        error!("--compress cannot be used with an archive");
+        return Err(());
+    }
+    let read_error = |e| {
+        error!("could not read {}: {e}", path.display());
+    };
+    let _lock = match args.output {
+        args::Output::InPlace => lock_session(path, args)?,
+        _ => None,
+    };
+    let mut archive = zip::Archive::open(path).map_err(read_error)?;
+    let backups = Backups::of(args);
+    let all: Vec<_> = archive
+        .entries()
+        .iter()
+        .filter(|e| !e.is_dir())
+        .map(|e| PathBuf::from(e.name()))
+        .filter(|p| is_snapshot(p, backups))
+        .collect();
+    if all.is_empty() {
+        error!("no snapshots found in {}", path.display());
+        return Err(());
+    }
+    let mut snapshots = Vec::new();
+    for name in select_snapshots(all, &args.snapshots)? {
+        let i = archive
+            .entries()
+            .iter()
+            .position(|e| Path::new(&e.name()) == name)
+            .unwrap();
+        let xml = archive.read(i).map_err(read_error)?;
+        let xml = String::from_utf8(xml).map_err(|_| {
+            error!("{} is not valid UTF-8", name.display());
+        })?;
+        snapshots.push((i, name, xml));
+    }
+    let mut uris = BTreeSet::new();
+    for (_, _, xml) in &snapshots {
+        let _ = add_plugin_uris(xml, &mut uris);
+    }
+    let mut resolver = load_resolver(args, dirs, &uris)?;
+    let mut replaced = HashMap::new();
+    let mut summaries = Vec::new();
+    let mut statuses = Vec::new();
+    for (n, (i, name, xml)) in snapshots.iter().enumerate() {
+        diag::progress(format_args!(
+            "fixing {}/{}: {}",
+            n + 1,
+            snapshots.len(),
+            file_name(name).display(),
+        ));
+        let warnings = diag::counts();
+        let patched = patch::patch(xml, &mut resolver, patch_options(args))
+            .map_err(|e| {
+                error!("{}: {e}", name.display());
+            })?;
+        check_strict(args, warnings)?;
+        check_change_ratio(args, &patched)?;
+        check_verify(args, &patched)?;
+        if !patched.summary().changes.is_empty() {
+            replaced.insert(*i, patched.to_string().into_bytes());
+        }
+        let summary = patched.into_summary();
+        statuses.push((name, Status::of(&summary)));
+        let name = format!("{}:{}", path.display(), name.display());
+        summaries.push((name, summary));
+    }
+    let write_error = |e| {
+        error!("could not write archive: {e}");
+    };
+    match &args.output {
+        _ if args.dry_run => {}
+        args::Output::InPlace if replaced.is_empty() => {}
+        args::Output::InPlace => {
+            let path = std::fs::canonicalize(path).map_err(|e| {
+                error!("could not resolve path: {e}");
+            })?;
+            let metadata = std::fs::metadata(&path).map_err(|e| {
+                error!("could not read {}: {e}", path.display());
+            })?;
+            drop(archive);
+            // The entries are copied from the original contents, which may
+            // be in the backup by the time they're written.
+            replace_file(&path, args, |original, file| {
+                let mut archive =
+                    zip::Archive::open(original).map_err(zip_io)?;
+                archive
+                    .write_to(BufWriter::new(file), &replaced)
+                    .map_err(zip_io)
+            })?;
+            restore_metadata(&path, &metadata, args);
+        }
+        args::Output::Stdout => archive
+            .write_to(io::stdout().lock(), &replaced)
+            .map_err(write_error)?,
+        args::Output::Path(p) => {
+            write_archive(&mut archive, p, &replaced).map_err(write_error)?;
+        }
+        args::Output::Dir(_) | args::Output::Suffix(_) => {
+            let output = output_path(&args.output, path)?;
+            write_archive(&mut archive, &output, &replaced)
+                .map_err(write_error)?;
+        }
+    }
+    report_missing(&summaries);
+    if args.dry_run {
+        print_statuses(args, &statuses);
+    }
+    let outcome = Outcome::of(args, &summaries);
+    write_results(args, summaries, false)?;
+    Ok(outcome)
+}
+
+#[cfg(not(feature = "zip"))]
+fn fix_archive(_: &Path, _: &args::RunArgs, _: &Dirs) -> Result<Outcome, ()> {
+    error!("fixing zip archives requires the `zip` feature");
+    Err(())
+}
+
+fn merge_reports(paths: &[PathBuf]) -> Result<(), ()> {
+    let mut reports = Vec::new();
+    for path in paths {
+        let report = Report::load(path).map_err(|e| {
+            error!("could not load {}: {e}", path.display());
+        })?;
+        let name = path.file_stem().unwrap_or(path.as_os_str());
+        reports.push((name.to_string_lossy(), report));
+    }
+    let merged = merge::Merged::new(reports.iter().map(|(n, r)| (&**n, r)));
+    print!("{merged}");
+    Ok(())
+}
+
+fn run_preflight(args: &args::RunArgs) -> Result<(), ()> {
+    let args::Input::Path(session) = &args.input else {
+        unreachable!("preflight takes one session");
+    };
+    let paths = if session.is_dir() {
+        snapshots(session, &args.snapshots, Backups::of(args))?
+    } else {
+        vec![session.to_owned()]
+    };
+    let dirs = Dirs::new(args.state_dir.as_deref());
+    let uris = used_plugins(&paths);
+    let mut resolver = load_resolver(args, &dirs, &uris)?;
+    let paths = paths.iter().map(PathBuf::as_path);
+    let preflight = preflight::run(paths, args, &mut resolver);
+    drop(resolver);
+    print!("{preflight}");
+    match preflight.outcome {
+        preflight::Outcome::Busy | preflight::Outcome::Error => Err(()),
+        _ => Ok(()),
+    }
+}
+
+fn plugins_diff(since: Option<&Path>, save: Option<&Path>) -> Result<(), ()> {
+    let old = since.map(|path| {
+        Inventory::load(path).map_err(|e| {
+            error!("could not load {}: {e}", path.display());
+        })
+    });
+    let old = old.transpose()?;
+    let plugins = Plugins::new(MissingCache::default(), &[]).map_err(|e| {
+        error!("could not retrieve lv2 metadata: {e}");
+    })?;
+    let inventory = Inventory::new(&plugins);
+    if let Some(path) = save {
+        write_display(path, &inventory).map_err(|e| {
+            error!("could not write inventory: {e}");
+        })?;
+    }
+    let Some(old) = old else {
+        return Ok(());
+    };
+    let diff = inventory::Diff::new(&old, &inventory)
+        .colored(color::enabled(Stream::Stdout));
+    print!("{diff}");
+    match diff.num_changed() {
+        0 => {
+            println!("no plugins changed their ports");
+            Ok(())
+        }
+        n => {
+            error!(
+                "{n} plugins changed their ports; sessions using them \
+                 may need to be fixed"
+            );
+            Err(())
+        }
+    }
+}
+
+/// Prints a table of the ports of the plugin `uri`.
+fn list_ports(uri: &str) -> Result<(), ()> {
+    let plugins = Plugins::for_uris(MissingCache::default(), &[], [uri]);
+    let plugins = plugins.map_err(|e| {
+        error!("could not retrieve lv2 metadata: {e}");
+    })?;
+    let Some(plugin) = plugins.get(uri) else {
+        error!("could not find plugin: {uri}");
+        return Err(());
+    };
+    let ports: Vec<_> =
+        (0..plugin.num_ports()).filter_map(|i| plugin.port_info(i)).collect();
+    let types: Vec<_> = ports.iter().map(|p| p.classes.join(" ")).collect();
+    let symbol_width = ports
+        .iter()
+        .filter_map(|p| p.symbol.as_ref())
+        .map(|s| s.chars().count())
+        .chain(["symbol".len()])
+        .max()
+        .unwrap_or_default();
+    let type_width = types
+        .iter()
+        .map(String::len)
+        .chain(["type".len()])
+        .max()
+        .unwrap_or_default();
+    match plugin.name() {
+        Some(name) => println!("{uri} ({name})"),
+        None => println!("{uri}"),
+    }
+    println!(
+        "index  {:symbol_width$}  {:type_width$}  name",
+        "symbol", "type"
+    );
+    for (port, types) in ports.iter().zip(&types) {
+        println!(
+            "{:>5}  {:symbol_width$}  {types:type_width$}  {}",
+            port.index,
+            port.symbol.as_deref().unwrap_or("-"),
+            port.name.as_deref().unwrap_or("-"),
+        );
     }
+    Ok(())
 }
 
-fn run() -> Result<(), ()> {
+/// Prints what's needed to find out why plugins can't be found: the lv2
+/// search path, how many plugins lilv loads from it, and for each of `uris`,
+/// whether it's installed or differs slightly from a plugin that is.
+fn doctor(uris: &[String]) -> Result<(), ()> {
+    match lv2::library_path() {
+        Some(path) => println!("lilv: {}", path.display()),
+        None => println!("lilv: unknown"),
+    }
+    let search_path = lv2::search_path(&[]);
+    let unreadable = lv2::unreadable(&search_path);
+    match std::env::var_os("LV2_PATH") {
+        Some(_) => println!("search path (from LV2_PATH):"),
+        None => println!("search path (default):"),
+    }
+    for dir in &search_path {
+        if !dir.is_dir() {
+            println!("  {} (does not exist)", dir.display());
+        } else if unreadable.iter().any(|(path, _)| path == dir) {
+            println!("  {} (unreadable)", dir.display());
+        } else {
+            println!("  {}", dir.display());
+        }
+    }
+    for (path, e) in &unreadable {
+        if !search_path.contains(path) {
+            println!("unreadable bundle: {}: {e}", path.display());
+        }
+    }
+    let start = Instant::now();
+    let plugins = Plugins::new(MissingCache::default(), &[]).map_err(|e| {
+        error!("could not retrieve lv2 metadata: {e}");
+    })?;
+    let all = plugins.all();
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("loaded {} plugins in {elapsed:.2}s", all.len());
+    let mut missing = 0;
+    for uri in uris {
+        if let Some(plugin) = plugins.get(uri) {
+            match plugin.bundle() {
+                Some(bundle) => {
+                    println!("{uri}: found in {}", bundle.display())
+                }
+                None => println!("{uri}: found"),
+            }
+            continue;
+        }
+        missing += 1;
+        println!("{uri}: not found");
+        for plugin in &all {
+            let other = plugin.uri();
+            let Some(differences) = lv2::uri_variant(uri, &other) else {
+                continue;
+            };
+            let differences: Vec<_> =
+                differences.iter().map(ToString::to_string).collect();
+            println!(
+                "  but {other} is installed (differs in {})",
+                differences.join(", "),
+            );
+        }
+    }
+    if missing > 0 {
+        return Err(());
+    }
+    Ok(())
+}
+
+/// Adds the URIs of the plugins used by `session`, a snapshot or session
+/// directory, to `uris`.
+fn session_uris(
+    session: &Path,
+    uris: &mut BTreeSet<String>,
+) -> Result<(), ()> {
+    let paths = if session.is_dir() {
+        snapshots(session, &[], Backups::Exclude(backup::DEFAULT_SUFFIX))?
+    } else {
+        vec![session.to_owned()]
+    };
+    for path in &paths {
+        let (xml, _) = gzip::read_file(path).map_err(|e| {
+            error!("could not read {}: {e}", path.display());
+        })?;
+        add_plugin_uris(&xml, uris).map_err(|e| {
+            error!("could not parse {}: {e}", path.display());
+        })?;
+    }
+    Ok(())
+}
+
+fn export_port_map(
+    uris: &[String],
+    sessions: &[PathBuf],
+    output: Option<&Path>,
+) -> Result<(), ()> {
+    let mut all_uris: BTreeSet<_> = uris.iter().cloned().collect();
+    for session in sessions {
+        session_uris(session, &mut all_uris)?;
+    }
+    let plugins = all_uris.iter().map(String::as_str);
+    let plugins = Plugins::for_uris(MissingCache::default(), &[], plugins);
+    let plugins = plugins.map_err(|e| {
+        error!("could not retrieve lv2 metadata: {e}");
+    })?;
+    let mut inventory = Inventory::default();
+    // Plugins used by a session may legitimately be missing, as when
+    // fixing, but explicitly requested plugins must be installed.
+    let mut missing = 0;
+    for uri in &all_uris {
+        match plugins.get(uri) {
+            Some(plugin) => inventory.insert(uri, &plugin),
+            None if uris.contains(uri) => {
+                error!("could not find plugin: {uri}");
+                missing += 1;
+            }
+            None => warning!("could not find plugin: {uri}"),
+        }
+    }
+    match output {
+        Some(path) => write_display(path, &inventory).map_err(|e| {
+            error!("could not write port map: {e}");
+        })?,
+        None => print!("{inventory}"),
+    }
+    if missing > 0 {
+        return Err(());
+    }
+    Ok(())
+}
+
+/// Moves the newest backup of `session` back into place, first moving the
+/// current session to `keep_patched` if given.
+fn restore(
+    session: &Path,
+    keep_patched: Option<&Path>,
+    options: &backup::Options,
+) -> Result<(), ()> {
+    // Backups of a symlinked session are made of the file it points to.
+    let is_link = std::fs::symlink_metadata(session)
+        .is_ok_and(|m| m.file_type().is_symlink());
+    let target;
+    let session = if is_link {
+        target = std::fs::canonicalize(session).map_err(|e| {
+            error!("could not resolve symlink: {e}");
+        })?;
+        &target
+    } else {
+        session
+    };
+    let backups = backup::list(session, options).map_err(|e| {
+        error!("could not list backups: {e}");
+    })?;
+    let Some(newest) = backups.last() else {
+        error!("no backups of {} found", session.display());
+        return Err(());
+    };
+    if let Some(keep) = keep_patched {
+        if keep.exists() {
+            error!("{} already exists", keep.display());
+            return Err(());
+        }
+        std::fs::rename(session, keep).map_err(|e| {
+            error!("could not move {}: {e}", session.display());
+        })?;
+    }
+    if let Err(e) = backup::restore(&newest.path, session) {
+        error!("could not restore backup: {e}");
+        if let Some(keep) = keep_patched {
+            let _ = std::fs::rename(keep, session);
+        }
+        return Err(());
+    }
+    let from = newest.path.display();
+    info!("restored {} from {from}", session.display());
+    Ok(())
+}
+
+fn check_consistency(dir: &Path) -> Result<(), ()> {
+    let paths = snapshots(dir, &[], Backups::Exclude(backup::DEFAULT_SUFFIX))?;
+    let mut snapshots = Vec::new();
+    for path in &paths {
+        let (xml, _) = gzip::read_file(path).map_err(|e| {
+            error!("could not read {}: {e}", path.display());
+        })?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        snapshots.push((name, xml));
+    }
+    let mut docs = Vec::new();
+    for (name, xml) in &snapshots {
+        let doc = roxmltree::Document::parse(xml).map_err(|e| {
+            error!("could not parse snapshot {name}: {e}");
+        })?;
+        docs.push((name, doc));
+    }
+    let mut consistency = Consistency::new();
+    for (name, doc) in &docs {
+        consistency.add(name, doc);
+    }
+    print!("{consistency}");
+    match consistency.divergences() {
+        0 => {
+            println!("all {} snapshots are consistent", docs.len());
+            Ok(())
+        }
+        n => {
+            error!("{n} ports have different indices in snapshots");
+            Err(())
+        }
+    }
+}
+
+/// Patches `xml` with the default options, except that no annotation is
+/// added, writing the patched XML to stdout and the warnings and errors as
+/// a JSON array to stderr.
+fn patch_string(xml: &OsStr) -> Result<(), ()> {
+    // Standard error holds only the JSON array, so progress messages (such
+    // as from loading plugins) must not be printed. Warnings are still kept,
+    // and errors are kept to be added to the array.
+    diag::set_verbosity(diag::Verbosity::Quiet);
+    diag::capture_errors();
+    let result = patch_xml_string(xml);
+    let warnings = diag::take();
+    let errors = diag::take_errors();
+    eprint!("{}", diag::Json {
+        warnings: &warnings,
+        errors: &errors,
+    },);
+    result
+}
+
+/// Does the work of [`patch_string`].
+fn patch_xml_string(xml: &OsStr) -> Result<(), ()> {
+    let Some(xml) = xml.to_str() else {
+        error!("session xml is not valid UTF-8");
+        return Err(());
+    };
+    let mut uris = BTreeSet::new();
+    let _ = add_plugin_uris(xml, &mut uris);
+    let source =
+        lilv_source(&Dirs::new(None), &[], &[], &uris, Caching::Enabled)?;
+    let mut resolver = Resolver::new(source);
+    let options = patch::Options {
+        annotate: false,
+        ..patch::Options::default()
+    };
+    let patched = patch::patch(xml, &mut resolver, options).map_err(|e| {
+        error!("{e}");
+    })?;
+    patched.write_to(io::stdout().lock()).map_err(|e| {
+        error!("could not write output: {e}");
+    })
+}
+
+fn run() -> Result<Outcome, ()> {
     let mut args = std::env::args_os();
     let arg0 = args.next();
     let bin = arg0
@@ -80,52 +1700,200 @@ fn run() -> Result<(), ()> {
         .unwrap_or("fix-ardour-lv2-index");
     let args = match args::parse(args) {
         Ok(Args::Run(args)) => args,
-        Ok(Args::Help) => {
-            print!("Usage: {bin} {USAGE}");
-            return Ok(());
+        Ok(Args::Preflight(args)) => {
+            set_diag_options(&args);
+            let result = run_preflight(&args);
+            diag::flush();
+            return result.map(|()| Outcome::Unchanged);
+        }
+        Ok(args) => {
+            return run_subcommand(args, bin).map(|()| Outcome::Unchanged);
         }
         Err(e) => {
-            eprintln!("error: {e}");
-            eprintln!("See `{bin} --help`.");
+            error!("{e}\nSee `{bin} --help`.");
             return Err(());
         }
     };
-    let xml = match &args.input {
-        args::Input::Stdin => std::io::read_to_string(io::stdin().lock())
-            .map_err(|e| {
-                eprintln!("error: could not read from stdin: {e}");
-            })?,
-        args::Input::Path(p) => std::fs::read_to_string(p).map_err(|e| {
-            eprintln!("error: could not read session file: {e}");
+    set_diag_options(&args);
+    let result = fix(&args);
+    diag::flush();
+    result
+}
+
+/// Sets how warnings and errors are printed.
+fn set_diag_options(args: &args::RunArgs) {
+    if args.warn_immediately {
+        diag::set_mode(diag::Mode::Immediate);
+    }
+    color::set_choice(args.color);
+    diag::set_verbosity(args.verbosity);
+    diag::set_format(args.diagnostics);
+    for &(kind, lint) in &args.lints {
+        diag::set_lint(kind, lint);
+    }
+    if let Some(max) = args.max_warnings {
+        diag::set_max_warnings(max);
+    }
+}
+
+fn run_subcommand(args: Args, bin: &str) -> Result<(), ()> {
+    match args {
+        Args::Help => {
+            print!("Usage: {bin} {USAGE}");
+            Ok(())
+        }
+        Args::Version => {
+            let version = env!("CARGO_PKG_VERSION");
+            println!("{} {version}", env!("CARGO_PKG_NAME"));
+            match lv2::library_path() {
+                Some(path) => println!("lilv: {}", path.display()),
+                None => println!("lilv: unknown"),
+            }
+            Ok(())
+        }
+        Args::Capabilities => {
+            print!("{}", capabilities::Capabilities);
+            Ok(())
+        }
+        Args::Completions(shell) => {
+            print!("{}", completions::Script(shell));
+            Ok(())
+        }
+        Args::ListPorts(uri) => list_ports(&uri),
+        Args::Doctor(uris) => doctor(&uris),
+        Args::MergeReports(paths) => merge_reports(&paths),
+        Args::CheckConsistency(dir) => check_consistency(&dir),
+        Args::PluginsDiff {
+            since,
+            save,
+        } => plugins_diff(since.as_deref(), save.as_deref()),
+        Args::ExportPortMap {
+            uris,
+            sessions,
+            output,
+        } => export_port_map(&uris, &sessions, output.as_deref()),
+        Args::Restore {
+            session,
+            keep_patched,
+            backup,
+        } => restore(&session, keep_patched.as_deref(), &backup),
+        Args::PatchString(xml) => patch_string(&xml),
+        Args::Preflight(_) | Args::Run(_) => unreachable!(),
+    }
+}
+
+fn fix(args: &args::RunArgs) -> Result<Outcome, ()> {
+    let stdout_reports =
+        args.reports.iter().filter(|r| r.path.is_none()).count();
+    if stdout_reports > 1 {
+        error!("only one report can be written to stdout");
+        return Err(());
+    }
+    let session_to_stdout =
+        !args.dry_run && matches!(args.output, args::Output::Stdout);
+    if stdout_reports > 0 && session_to_stdout {
+        error!("cannot write both a report and the session to stdout");
+        return Err(());
+    }
+    if matches!(args.input, args::Input::Stdin)
+        && matches!(
+            args.output,
+            args::Output::Dir(_) | args::Output::Suffix(_)
+        )
+    {
+        error!("--output-dir and --suffix cannot be used with standard input");
+        return Err(());
+    }
+    if args.compress && !cfg!(feature = "gzip") {
+        error!("--compress requires the `gzip` feature");
+        return Err(());
+    }
+    let dirs = Dirs::new(args.state_dir.as_deref());
+    match &args.input {
+        args::Input::Path(p) if p.is_dir() => {
+            return fix_directory(p, args, &dirs);
+        }
+        args::Input::Path(p) if is_zip(p) => {
+            return fix_archive(p, args, &dirs);
+        }
+        _ if !args.snapshots.is_empty() => {
+            error!("--snapshot requires a session directory");
+            return Err(());
+        }
+        args::Input::Paths(paths) => return fix_files(paths, args, &dirs),
+        args::Input::Path(p)
+            if !args.include_backups && is_backup(p, args) =>
+        {
+            error!(
+                "{} looks like a backup\nUse --include-backups to fix it \
+                 anyway.",
+                p.display(),
+            );
+            return Err(());
+        }
+        _ => {}
+    }
+    // The session file, or `None` for stdin.
+    let path = match &args.input {
+        args::Input::Stdin => None,
+        args::Input::Path(p) => Some(p),
+        args::Input::Paths(_) => unreachable!(),
+    };
+    let _lock = match (path, &args.output) {
+        (Some(p), args::Output::InPlace) => lock_session(p, args)?,
+        _ => None,
+    };
+    let (xml, compressed) = match path {
+        None => gzip::read(io::stdin().lock()).map_err(|e| {
+            error!("could not read from stdin: {e}");
+        })?,
+        Some(p) => gzip::read_file(p).map_err(|e| {
+            error!("could not read session file: {e}");
         })?,
     };
-    let patched = patch::patch(&xml).map_err(|e| {
-        eprintln!("error: {e}");
+    let compress = compressed || args.compress;
+    let mut uris = BTreeSet::new();
+    // Parse errors are reported when the session is fixed.
+    let _ = add_plugin_uris(&xml, &mut uris);
+    let mut resolver = load_resolver(args, &dirs, &uris)?;
+    let options = patch_options(args);
+    let warnings = diag::counts();
+    let patched = patch::patch(&xml, &mut resolver, options).map_err(|e| {
+        error!("{e}");
     })?;
-    let write_output = |path| {
-        write_display(path, &patched).map_err(|e| {
-            eprintln!("error: could not write output: {e}");
-        })
-    };
+    check_strict(args, warnings)?;
+    check_change_ratio(args, &patched)?;
+    check_verify(args, &patched)?;
     match &args.output {
+        _ if args.dry_run => {}
         args::Output::InPlace => {
-            let args::Input::Path(path) = &args.input else {
-                unreachable!();
-            };
-            create_backup(path).map_err(|e| {
-                eprintln!("error: could not create backup: {e}");
-            })?;
-            write_output(path)?;
+            save_in_place(path.unwrap(), args, &patched, compress)?;
+        }
+        args::Output::Stdout => {
+            gzip::write(&patched, io::stdout().lock(), compress).map_err(
+                |e| {
+                    error!("could not write output: {e}");
+                },
+            )?;
+        }
+        args::Output::Path(p) => write_output(p, &patched, compress)?,
+        args::Output::Dir(_) | args::Output::Suffix(_) => {
+            let output = output_path(&args.output, path.unwrap())?;
+            write_output(&output, &patched, compress)?;
         }
-        args::Output::Stdout => print!("{patched}"),
-        args::Output::Path(p) => write_output(p)?,
     }
-    Ok(())
+    let name =
+        path.map_or_else(|| "-".to_owned(), |p| p.display().to_string());
+    let summaries = [(name, patched.into_summary())];
+    let outcome = Outcome::of(args, &summaries);
+    write_results(args, summaries, true)?;
+    Ok(outcome)
 }
 
 fn main() -> ExitCode {
     match run() {
-        Ok(()) => ExitCode::SUCCESS,
-        Err(()) => ExitCode::FAILURE,
+        Ok(Outcome::Unchanged) => ExitCode::SUCCESS,
+        Ok(Outcome::Changed) => ExitCode::from(1),
+        Err(()) => ExitCode::from(2),
     }
 }
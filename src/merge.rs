@@ -0,0 +1,194 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Combining the JSON reports of several machines, printed by the
+//! `merge-reports` subcommand.
+
+use super::patch::SkipReason;
+use super::report::Report;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+
+#[derive(Debug, Default)]
+struct PluginUsage<'a> {
+    /// The sessions that use the plugin, with the machines that reported
+    /// them.
+    sessions: BTreeSet<(&'a str, &'a str)>,
+    /// Machines on which every port of the plugin was resolved.
+    available: BTreeSet<&'a str>,
+    /// Machines on which some ports of the plugin couldn't be resolved.
+    unresolved: BTreeSet<&'a str>,
+    /// Machines on which the plugin couldn't be found.
+    missing: BTreeSet<&'a str>,
+}
+
+/// The reports of several machines, cross-referenced by plugin. Each machine
+/// is identified by the name of its report.
+pub struct Merged<'a> {
+    plugins: BTreeMap<&'a str, PluginUsage<'a>>,
+}
+
+impl<'a> Merged<'a> {
+    pub fn new<I>(reports: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, &'a Report)>,
+    {
+        let mut plugins = BTreeMap::<_, PluginUsage<'_>>::new();
+        for (machine, report) in reports {
+            let mut used = BTreeSet::new();
+            let mut unresolved = BTreeSet::new();
+            let mut missing = BTreeSet::new();
+            for file in &report.files {
+                for uri in &file.plugins {
+                    let usage = plugins.entry(uri.as_str()).or_default();
+                    usage.sessions.insert((file.path.as_str(), machine));
+                    used.insert(uri.as_str());
+                }
+                missing
+                    .extend(file.missing_plugins.iter().map(String::as_str));
+                unresolved.extend(
+                    file.skipped
+                        .iter()
+                        .filter(|s| s.reason == SkipReason::Unresolved)
+                        .map(|s| s.uri.as_str()),
+                );
+            }
+            for uri in used {
+                let usage = plugins.get_mut(uri).unwrap();
+                if missing.contains(uri) {
+                    usage.missing.insert(machine);
+                } else if unresolved.contains(uri) {
+                    usage.unresolved.insert(machine);
+                } else {
+                    usage.available.insert(machine);
+                }
+            }
+        }
+        Self {
+            plugins,
+        }
+    }
+}
+
+struct Machines<'a, 'b>(&'b BTreeSet<&'a str>);
+
+impl Display for Machines<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "(none)");
+        }
+        for (i, machine) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{machine}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Merged<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (uri, usage) in &self.plugins {
+            writeln!(f, "{uri}")?;
+            writeln!(f, "  available on: {}", Machines(&usage.available))?;
+            if !usage.missing.is_empty() {
+                writeln!(f, "  missing on: {}", Machines(&usage.missing))?;
+            }
+            if !usage.unresolved.is_empty() {
+                writeln!(
+                    f,
+                    "  missing ports on: {}",
+                    Machines(&usage.unresolved),
+                )?;
+            }
+            writeln!(f, "  needed by:")?;
+            for (session, machine) in &usage.sessions {
+                writeln!(f, "    {session} ({machine})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::Skipped;
+    use crate::report::FileReport;
+
+    fn uris(uris: &[&str]) -> BTreeSet<String> {
+        uris.iter().map(|&uri| uri.to_owned()).collect()
+    }
+
+    fn file(path: &str, plugins: &[&str], missing: &[&str]) -> FileReport {
+        FileReport {
+            path: path.to_owned(),
+            changes: Vec::new(),
+            skipped: Vec::new(),
+            bypassed: 0,
+            verified: 0,
+            unchecked: 0,
+            plugins: uris(plugins),
+            missing_plugins: uris(missing),
+        }
+    }
+
+    #[test]
+    fn availability() {
+        let mut studio = file("A.ardour", &["urn:comp", "urn:verb"], &[]);
+        studio.skipped.push(Skipped {
+            uri: "urn:verb".to_owned(),
+            instance: Default::default(),
+            owner: None,
+            symbol: "size".to_owned(),
+            old_index: 4,
+            offset: 100,
+            reason: SkipReason::Unresolved,
+        });
+        let studio = Report {
+            files: vec![studio],
+        };
+        let laptop = Report {
+            files: vec![
+                file("A.ardour", &["urn:comp", "urn:verb"], &["urn:comp"]),
+                file("B.ardour", &["urn:eq"], &[]),
+            ],
+        };
+        let merged = Merged::new([("studio", &studio), ("laptop", &laptop)]);
+        let usage = |uri| &merged.plugins[uri];
+        let machines =
+            |names: &[&'static str]| BTreeSet::from_iter(names.to_vec());
+
+        // Available on the studio, though none of its indices were changed.
+        assert_eq!(usage("urn:comp").available, machines(&["studio"]));
+        assert_eq!(usage("urn:comp").missing, machines(&["laptop"]));
+        assert_eq!(usage("urn:verb").available, machines(&["laptop"]));
+        assert_eq!(usage("urn:verb").unresolved, machines(&["studio"]));
+        assert_eq!(usage("urn:eq").available, machines(&["laptop"]));
+        assert_eq!(
+            usage("urn:comp").sessions,
+            BTreeSet::from([("A.ardour", "laptop"), ("A.ardour", "studio")]),
+        );
+        let text = merged.to_string();
+        assert!(text.contains(
+            "urn:comp\n  available on: studio\n  missing on: laptop\n"
+        ));
+    }
+}
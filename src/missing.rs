@@ -0,0 +1,138 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A persistent cache of plugins that lilv couldn't find.
+//!
+//! The cache is keyed by a fingerprint of the LV2 search path, which covers
+//! the path itself and the modification times of the bundles in it, so
+//! installing, removing, or updating a plugin invalidates it.
+
+use super::diag::{self, Kind, Warning};
+use super::dirs::hash_name;
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Describes the contents of the LV2 search path `search_path`.
+fn fingerprint(search_path: &[PathBuf]) -> String {
+    let mut parts = Vec::new();
+    let mtime = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos())
+    };
+    for dir in search_path {
+        parts.push(dir.as_os_str().as_encoded_bytes().to_vec());
+        parts.push(mtime(dir).to_le_bytes().to_vec());
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut bundles: Vec<_> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| (e.file_name(), mtime(&e.path())))
+            .collect();
+        bundles.sort_unstable();
+        for (name, time) in bundles {
+            parts.push(name.as_encoded_bytes().to_vec());
+            parts.push(time.to_le_bytes().to_vec());
+        }
+    }
+    hash_name(parts.iter().map(Vec::as_slice))
+}
+
+#[derive(Debug, Default)]
+pub struct MissingCache {
+    path: Option<PathBuf>,
+    uris: BTreeSet<String>,
+    /// Whether `uris` has changed since it was loaded.
+    dirty: bool,
+}
+
+impl MissingCache {
+    /// Loads the cache for the LV2 search path `search_path` from `dir`. If
+    /// `dir` is [`None`], nothing is persisted.
+    pub fn load(dir: Option<&Path>, search_path: &[PathBuf]) -> Self {
+        let Some(dir) = dir else {
+            return Self::default();
+        };
+        let path = dir.join(fingerprint(search_path));
+        let uris = std::fs::read_to_string(&path)
+            .map(|s| s.lines().map(str::to_owned).collect())
+            .unwrap_or_default();
+        Self {
+            path: Some(path),
+            uris,
+            dirty: false,
+        }
+    }
+
+    pub fn contains(&self, uri: &str) -> bool {
+        self.uris.contains(uri)
+    }
+
+    pub fn insert(&mut self, uri: &str) {
+        // URIs are stored one per line.
+        if !uri.contains('\n') && self.uris.insert(uri.to_owned()) {
+            self.dirty = true;
+        }
+    }
+
+    /// Forgets every missing plugin.
+    pub fn clear(&mut self) {
+        if !self.uris.is_empty() {
+            self.uris.clear();
+            self.dirty = true;
+        }
+    }
+
+    pub fn remove(&mut self, uri: &str) {
+        if self.uris.remove(uri) {
+            self.dirty = true;
+        }
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut contents = String::new();
+        for uri in &self.uris {
+            contents.push_str(uri);
+            contents.push('\n');
+        }
+        std::fs::write(path, contents)
+    }
+}
+
+impl Drop for MissingCache {
+    fn drop(&mut self) {
+        let Some(path) = self.path.as_deref().filter(|_| self.dirty) else {
+            return;
+        };
+        if let Err(e) = self.save(path) {
+            diag::warn(Warning::new(
+                Kind::Cache,
+                format!("could not write to cache: {e}"),
+            ));
+        }
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Display of long output through a pager.
+
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// The pager used when `PAGER` is unset.
+const DEFAULT_PAGER: &str = "less";
+
+/// The number of rows in the terminal on standard output.
+#[cfg(target_os = "linux")]
+fn rows() -> Option<usize> {
+    use std::ffi::{c_int, c_ulong, c_ushort};
+    use std::os::fd::AsRawFd;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Winsize {
+        ws_row: c_ushort,
+        ws_col: c_ushort,
+        ws_xpixel: c_ushort,
+        ws_ypixel: c_ushort,
+    }
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    const TIOCGWINSZ: c_ulong = 0x5413;
+    let mut size = Winsize::default();
+    let fd = io::stdout().as_raw_fd();
+    // SAFETY: `TIOCGWINSZ` takes a pointer to a `struct winsize` as its only
+    // argument, and `size` is valid for the duration of the call.
+    if unsafe { ioctl(fd, TIOCGWINSZ, &mut size as *mut Winsize) } == -1 {
+        return None;
+    }
+    Some(size.ws_row.into()).filter(|&rows| rows > 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rows() -> Option<usize> {
+    std::env::var("LINES").ok()?.parse().ok()
+}
+
+/// Runs `pager` with `text` as its input.
+fn page(pager: &str, text: &str) -> io::Result<()> {
+    let mut words = pager.split_whitespace();
+    let Some(program) = words.next() else {
+        return Err(io::ErrorKind::NotFound.into());
+    };
+    let mut command = Command::new(program);
+    command.args(words).stdin(Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        // Like git: show raw escapes, don't clear the screen on exit, and
+        // exit immediately if the text fits on one screen.
+        command.env("LESS", "FRX");
+    }
+    let mut child = command.spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    // Quitting the pager early closes the pipe, which isn't an error.
+    match stdin.write_all(text.as_bytes()) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+        _ => {}
+    }
+    drop(stdin);
+    child.wait()?;
+    Ok(())
+}
+
+/// Prints `text` to standard output, through `PAGER` if standard output is a
+/// terminal and `text` doesn't fit on the screen.
+pub fn print(text: &str) {
+    let fits = || rows().is_some_and(|rows| text.lines().count() < rows);
+    if !io::stdout().is_terminal() || fits() {
+        print!("{text}");
+        return;
+    }
+    let pager = std::env::var("PAGER");
+    let pager = pager.as_deref().unwrap_or(DEFAULT_PAGER);
+    if pager.is_empty() || pager == "cat" {
+        print!("{text}");
+        return;
+    }
+    if let Err(e) = page(pager, text) {
+        warning!("could not run pager: {e}");
+        print!("{text}");
+    }
+}
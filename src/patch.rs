@@ -17,57 +17,357 @@
  * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
  */
 
-use super::lv2::{Plugin, Plugins};
-use super::session::Processor;
-use std::collections::hash_map::{self, HashMap};
+//! Fixing the parameter indices of a session.
+//!
+//! [`patch`] finds each LV2 and LADSPA processor in a session, resolves the
+//! new index of each of its parameters through a [`Resolver`], and returns the
+//! [`PatchedSession`] with the changed indices replaced.
+//!
+//! Repairs are [`Fixer`]s, which are shown each element of the session in
+//! turn and record their edits as [`Splices`]. Index fixing is currently the
+//! only one; [`PostProcessor`]s then add edits that depend on the outcome,
+//! such as the annotation comment.
+
+use super::annotate::Annotate;
+use super::diag::{self, Kind, Level};
+use super::filter::NameFilter;
+#[cfg(feature = "http")]
+use super::http;
+use super::index_map::{IndexMap, Mapping};
+use super::inventory::Inventory;
+use super::ladspa;
+use super::lines::{LineIndex, Position};
+use super::lv2::{self, Plugin, Plugins};
+use super::session::{self, OwnerKind, Parameter, PluginKind, Processor};
+use super::spanedit::{Spliced, Splices};
+use roxmltree::Node;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::{self, Display};
+use std::io::{self, Write};
 use std::ops::Range;
 
-#[derive(Debug)]
-struct Replacement {
-    pub location: Range<usize>,
-    pub value: u32,
+/// The edits made to a session, passed to each [`PostProcessor`].
+pub struct Edits<'e, 'xml> {
+    /// The original session.
+    pub document: &'e roxmltree::Document<'xml>,
+    pub summary: &'e Summary,
+    splices: &'e mut Splices,
+}
+
+impl Edits<'_, '_> {
+    /// Replaces `location` in the original session with `text`. The range
+    /// must not overlap any other replacement; empty ranges insert text.
+    pub fn replace(&mut self, location: Range<usize>, text: String) {
+        self.splices.replace(location, text);
+    }
+}
+
+/// A pass that adds edits to a session after its indices are fixed and
+/// before it is written.
+pub trait PostProcessor {
+    fn process(&mut self, edits: &mut Edits<'_, '_>);
+}
+
+/// The post-processors enabled by `options`, in the order they run.
+fn post_processors(options: &Options) -> Vec<Box<dyn PostProcessor>> {
+    let mut passes: Vec<Box<dyn PostProcessor>> = Vec::new();
+    if options.annotate {
+        passes.push(Box::new(Annotate));
+    }
+    passes
+}
+
+/// The route or region a processor belongs to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Owner {
+    pub kind: OwnerKind,
+    pub name: String,
+    /// For a route, its 1-based position among the session's routes.
+    pub index: Option<usize>,
+}
+
+impl Owner {
+    fn of(processor: &Processor<'_>) -> Option<Self> {
+        processor.owner().map(|o| Self {
+            kind: o.kind,
+            name: o.name.to_owned(),
+            index: o.index,
+        })
+    }
+}
+
+/// The processor (plugin instance) a parameter belongs to, which
+/// distinguishes instances of the same plugin.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Instance {
+    /// The processor's name as shown in Ardour.
+    pub name: Option<String>,
+    /// The processor's `id` attribute.
+    pub id: Option<String>,
+}
+
+impl Instance {
+    fn of(processor: &Processor<'_>) -> Self {
+        Self {
+            name: processor.name().map(str::to_owned),
+            id: processor.id().map(str::to_owned),
+        }
+    }
 }
 
+/// The text surrounding a changed index, for showing it in context.
+#[derive(Clone, Debug)]
+pub struct Context {
+    /// The 1-based line number of the index in the session file.
+    pub line: usize,
+    /// The text on the same line before the index.
+    pub before: String,
+    /// The text on the same line after the index.
+    pub after: String,
+}
+
+impl Context {
+    /// The maximum number of characters kept on each side of the index.
+    const MAX_LEN: usize = 40;
+
+    fn new(xml: &str, location: &Range<usize>) -> Self {
+        let line_start =
+            xml[..location.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = xml[location.end..]
+            .find('\n')
+            .map_or(xml.len(), |i| location.end + i);
+        let before = &xml[line_start..location.start];
+        let skip = before.chars().count().saturating_sub(Self::MAX_LEN);
+        Self {
+            line: 0,
+            before: before.chars().skip(skip).collect(),
+            after: xml[location.end..line_end]
+                .chars()
+                .take(Self::MAX_LEN)
+                .collect(),
+        }
+    }
+}
+
+/// A parameter index that was changed.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub uri: String,
+    pub instance: Instance,
+    pub owner: Option<Owner>,
+    pub symbol: String,
+    pub old_index: u32,
+    pub new_index: u32,
+    /// The byte offset of the replaced index in the session file.
+    pub offset: usize,
+    /// The byte offset just past the replaced index.
+    pub end: usize,
+    /// Whether the port couldn't be found, so the new index was assigned
+    /// past the end of the plugin's ports.
+    pub synthetic: bool,
+    /// The surrounding text, if the session file is available.
+    pub context: Option<Context>,
+}
+
+/// Why a parameter was left unchanged.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SkipReason {
+    /// The port's index couldn't be determined.
+    Unresolved,
+    /// The processor's symbol for the index is ambiguous.
+    Ambiguous,
+}
+
+impl SkipReason {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"unresolved" => Self::Unresolved,
+            b"ambiguous" => Self::Ambiguous,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unresolved => "unresolved",
+            Self::Ambiguous => "ambiguous",
+        }
+    }
+}
+
+/// A parameter whose index was left unchanged because it couldn't be
+/// fixed with certainty.
+#[derive(Clone, Debug)]
+pub struct Skipped {
+    pub uri: String,
+    pub instance: Instance,
+    pub owner: Option<Owner>,
+    pub symbol: String,
+    pub old_index: u32,
+    pub offset: usize,
+    pub reason: SkipReason,
+}
+
+/// Information about the fixes applied to a session.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// The old→new index mapping of every parameter that was resolved,
+    /// including those whose index was already correct.
+    pub index_map: IndexMap,
+    /// Every changed index, in order of appearance.
+    pub changes: Vec<Change>,
+    /// Every parameter that was skipped, in order of appearance.
+    pub skipped: Vec<Skipped>,
+    /// The number of parameters of plugins that were found, whether or not
+    /// they were changed.
+    pub parameters: usize,
+    /// The number of bypassed processors that were skipped.
+    pub bypassed: usize,
+    /// The number of parameters whose index was resolved and already
+    /// correct.
+    pub verified: usize,
+    /// The number of parameters that couldn't be checked at all, because
+    /// they have no symbol or their plugin wasn't found.
+    pub unchecked: usize,
+    /// Plugins used by the session whose ports were looked up, including
+    /// those that couldn't be found.
+    pub plugins: BTreeSet<String>,
+    /// Plugins used by the session that couldn't be found.
+    pub missing_plugins: BTreeSet<String>,
+}
+
+/// Options for [`patch`].
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Only change indices of ports that were found, and skip parameters
+    /// whose symbol is ambiguous within their processor, rather than
+    /// assigning new indices to ports that can't be found. Only the
+    /// resolved version of each plugin is consulted.
+    pub conservative: bool,
+    /// Add a comment to the session summarizing the changes, if any.
+    pub annotate: bool,
+    /// Leave the parameters of bypassed processors unchanged.
+    pub skip_bypassed: bool,
+    /// Only fix processors whose plugin name matches this filter.
+    pub plugin_name: Option<NameFilter>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            conservative: false,
+            annotate: true,
+            skip_bypassed: false,
+            plugin_name: None,
+        }
+    }
+}
+
+/// A session with its indices fixed, returned by [`patch`]. It borrows the
+/// original session text and is rendered when written or displayed.
 #[derive(Debug)]
 pub struct PatchedSession<'a> {
-    xml: &'a str,
-    replacements: Vec<Replacement>,
+    session: Spliced<'a>,
+    summary: Summary,
+    /// The offset in the original session of each parameter index that was
+    /// checked, and the index it should have after patching.
+    expected: Vec<(usize, u32)>,
+}
+
+impl PatchedSession<'_> {
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    pub fn into_summary(self) -> Summary {
+        self.summary
+    }
+}
+
+impl PatchedSession<'_> {
+    /// Writes the patched session to `writer` without rendering it in
+    /// memory first.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.session.write_to(writer)
+    }
 }
 
-macro_rules! debug_eprintln {
-    ($($tt:tt)*) => {
-        if cfg!(debug_assertions) {
-            eprintln!($($tt)*);
+impl PatchedSession<'_> {
+    /// Renders the patched session and checks it: it must still be
+    /// well-formed, and every parameter index that was checked must now
+    /// have the index it was given (or keep its index, if it was skipped).
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let output = self.to_string();
+        let document =
+            roxmltree::Document::parse(&output).map_err(VerifyError::Xml)?;
+        let offsets = session::index_offsets(&document);
+        for &(offset, expected) in &self.expected {
+            let offset = self.session.map_offset(offset);
+            let digits = output[offset..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(&output[offset..], |end| &output[offset..][..end]);
+            let found =
+                digits.parse().ok().filter(|_| offsets.contains(&offset));
+            if found != Some(expected) {
+                return Err(VerifyError::Mismatch {
+                    position: LineIndex::new(&output).position(offset),
+                    expected,
+                    found,
+                });
+            }
         }
-    };
+        Ok(())
+    }
 }
 
-impl Display for PatchedSession<'_> {
+/// An error returned by [`PatchedSession::verify`].
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The patched session isn't well-formed.
+    Xml(roxmltree::Error),
+    /// A parameter doesn't have the expected index. `position` is its
+    /// position in the patched session.
+    Mismatch {
+        position: Position,
+        expected: u32,
+        found: Option<u32>,
+    },
+}
+
+impl Display for VerifyError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut pos = 0;
-        for r in self.replacements.iter() {
-            if r.location.start < pos {
-                debug_eprintln!(
-                    "warning: overlapping/out-of-order replacement: \
-                     {}..{} -> {} (currently at {pos})",
-                    r.location.start,
-                    r.location.end,
-                    r.value,
-                );
-                continue;
-            }
-            write!(f, "{}{}", &self.xml[pos..r.location.start], r.value)?;
-            pos = r.location.end;
+        match self {
+            Self::Xml(e) => write!(f, "patched session is malformed: {e}"),
+            Self::Mismatch {
+                position,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "parameter at {position} has index {found}, not {expected}",
+            ),
+            Self::Mismatch {
+                position,
+                expected,
+                found: None,
+            } => write!(
+                f,
+                "no parameter index at {position} (expected {expected})",
+            ),
         }
-        write!(f, "{}", &self.xml[pos..])
     }
 }
 
+impl Display for PatchedSession<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.session.fmt(f)
+    }
+}
+
+/// An error returned by [`patch`].
 #[derive(Debug)]
 pub enum Error {
     Xml(roxmltree::Error),
-    Lv2(super::lv2::Error),
 }
 
 impl From<roxmltree::Error> for Error {
@@ -76,124 +376,756 @@ impl From<roxmltree::Error> for Error {
     }
 }
 
-impl From<super::lv2::Error> for Error {
-    fn from(e: super::lv2::Error) -> Self {
-        Self::Lv2(e)
-    }
-}
-
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Xml(e) => {
                 write!(f, "could not parse session file: {e}")
             }
-            Self::Lv2(e) => {
-                write!(f, "could not retrieve lv2 metadata: {e}")
-            }
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
-struct PortId<'a> {
-    pub uri: &'a str,
-    pub symbol: &'a str,
+/// Port metadata for plugins, by URI. This is how [`Resolver`] looks up
+/// ports, so other sources of metadata can be used by implementing it and
+/// passing it as [`Source::Custom`].
+pub trait PortResolver {
+    /// Returns the number of ports of the plugin `uri`, or [`None`] if the
+    /// plugin is missing.
+    fn num_ports(&mut self, uri: &str) -> Option<u32>;
+
+    /// Returns the index of the port `symbol` of the plugin `uri`, or
+    /// [`None`] if the plugin or port is missing.
+    fn port_index(&mut self, uri: &str, symbol: &str) -> Option<u32>;
+
+    /// Returns the human-readable name of the plugin `uri`, if known.
+    fn plugin_name(&mut self, uri: &str) -> Option<String> {
+        let _ = uri;
+        None
+    }
+
+    /// Discards anything cached about the plugin `uri`.
+    fn refresh(&mut self, uri: &str) {
+        let _ = uri;
+    }
+
+    /// Returns the URIs of the plugins available so far, from which a
+    /// replacement is suggested when a plugin is missing. Resolvers that
+    /// can't list their plugins return an empty list.
+    fn uris(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+impl PortResolver for Plugins {
+    fn num_ports(&mut self, uri: &str) -> Option<u32> {
+        Some(self.ports(uri)?.num_ports)
+    }
+
+    fn port_index(&mut self, uri: &str, symbol: &str) -> Option<u32> {
+        self.ports(uri)?.ports.get(symbol).copied()
+    }
+
+    fn plugin_name(&mut self, uri: &str) -> Option<String> {
+        self.ports(uri)?.name.clone()
+    }
+
+    fn refresh(&mut self, uri: &str) {
+        Plugins::refresh(self, uri);
+    }
+
+    fn uris(&mut self) -> Vec<String> {
+        self.loaded().iter().map(Plugin::uri).collect()
+    }
+}
+
+impl PortResolver for Inventory {
+    fn num_ports(&mut self, uri: &str) -> Option<u32> {
+        Inventory::num_ports(self, uri)
+    }
+
+    fn port_index(&mut self, uri: &str, symbol: &str) -> Option<u32> {
+        Inventory::port_index(self, uri, symbol)
+    }
+
+    fn uris(&mut self) -> Vec<String> {
+        Inventory::uris(self).map(str::to_owned).collect()
+    }
+}
+
+impl PortResolver for ladspa::Plugins {
+    fn num_ports(&mut self, uri: &str) -> Option<u32> {
+        Some(self.get(uri)?.num_ports())
+    }
+
+    fn port_index(&mut self, uri: &str, symbol: &str) -> Option<u32> {
+        self.get(uri)?.port_index(symbol)
+    }
+
+    fn plugin_name(&mut self, uri: &str) -> Option<String> {
+        self.get(uri)?.name.clone()
+    }
+}
+
+#[cfg(feature = "http")]
+impl PortResolver for http::Client {
+    fn num_ports(&mut self, uri: &str) -> Option<u32> {
+        Some(self.get(uri)?.num_ports())
+    }
+
+    fn port_index(&mut self, uri: &str, symbol: &str) -> Option<u32> {
+        self.get(uri)?.port_index(symbol)
+    }
+
+    fn refresh(&mut self, uri: &str) {
+        http::Client::refresh(self, uri);
+    }
+}
+
+/// The ports of a plugin resolved so far.
+#[derive(Debug, Default)]
+struct ResolvedPorts {
+    /// The index to assign to the next port that can't be found.
+    next: Option<u32>,
+    /// Port indices by symbol.
+    index: HashMap<String, Option<u32>>,
+    /// The symbols of ports that couldn't be found and were assigned new
+    /// indices.
+    synthetic: HashSet<String>,
 }
 
+/// Port indices resolved so far. This is shared by every session patched
+/// with the same [`Resolver`], so that the indices assigned to missing ports
+/// are consistent between them (e.g., between the snapshots of a session).
 #[derive(Debug, Default)]
-struct PortMap<'a> {
-    count: HashMap<&'a str, u32>,
-    index: HashMap<PortId<'a>, u32>,
+struct PortMap {
+    plugins: HashMap<String, ResolvedPorts>,
 }
 
-impl<'a> PortMap<'a> {
-    pub fn new() -> Self {
-        Self::default()
+impl PortMap {
+    /// Returns the index of the port referred to by `parameter` of
+    /// `processor`. If `synthesize` is true, ports that can't be found are
+    /// assigned new indices past the end of the plugin's ports.
+    pub fn index(
+        &mut self,
+        plugin: &mut dyn PortResolver,
+        processor: &Processor<'_>,
+        parameter: &Parameter<'_>,
+        synthesize: bool,
+    ) -> Option<u32> {
+        let uri = processor.uri();
+        let symbol = parameter.symbol;
+        if !self.plugins.contains_key(uri) {
+            self.plugins.insert(uri.to_owned(), ResolvedPorts::default());
+        }
+        let ports = self.plugins.get_mut(uri).unwrap();
+        if let Some(&index) = ports.index.get(symbol) {
+            return index;
+        }
+        let index = plugin.port_index(uri, symbol).or_else(|| {
+            diag::warn(
+                processor
+                    .warning(
+                        Kind::MissingPort,
+                        format!(
+                            "could not find port \"{}\" in {uri} (at {})",
+                            symbol.escape_default(),
+                            parameter.position,
+                        ),
+                    )
+                    .symbol(symbol)
+                    .at(parameter.location.start, parameter.position),
+            );
+            if !synthesize {
+                return None;
+            }
+            let next = match ports.next {
+                Some(ref mut next) => next,
+                None => ports.next.insert(plugin.num_ports(uri)?),
+            };
+            ports.synthetic.insert(symbol.to_owned());
+            Some(std::mem::replace(next, *next + 1))
+        });
+        ports.index.insert(symbol.to_owned(), index);
+        index
+    }
+
+    /// Returns whether the port `symbol` of the plugin `uri` couldn't be
+    /// found and was assigned a new index.
+    pub fn is_synthetic(&self, uri: &str, symbol: &str) -> bool {
+        self.plugins.get(uri).is_some_and(|p| p.synthetic.contains(symbol))
+    }
+
+    /// Resolves the index of each of `processor`'s parameters, or returns
+    /// [`None`] if its plugin is missing.
+    pub fn resolve<'a>(
+        &mut self,
+        plugin: &mut dyn PortResolver,
+        processor: &Processor<'a>,
+        synthesize: bool,
+    ) -> Option<Vec<(Parameter<'a>, Option<u32>)>> {
+        let uri = processor.uri();
+        plugin.num_ports(uri)?;
+        let resolved = processor
+            .parameters()
+            .map(|parameter| {
+                let index =
+                    self.index(plugin, processor, &parameter, synthesize);
+                (parameter, index)
+            })
+            .collect();
+        Some(resolved)
     }
+}
 
-    pub fn index(&mut self, plugin: &mut Plugin, id: PortId<'a>) -> u32 {
-        let vacant = match self.index.entry(id) {
-            hash_map::Entry::Occupied(ent) => return *ent.get(),
-            hash_map::Entry::Vacant(ent) => ent,
+fn resolve_mapped<'a>(
+    map: &IndexMap,
+    processor: &Processor<'a>,
+) -> Vec<(Parameter<'a>, Option<u32>)> {
+    let uri = processor.uri();
+    let mut resolved = Vec::new();
+    for parameter in processor.parameters() {
+        let old = parameter.old_index;
+        let warn = |kind, message| {
+            diag::warn(
+                processor
+                    .warning(kind, message)
+                    .symbol(parameter.symbol)
+                    .at(parameter.location.start, parameter.position),
+            );
+            None
+        };
+        let index = match map.get(uri, old) {
+            Some(m) if m.symbol == parameter.symbol => Some(m.new_index),
+            Some(m) => warn(
+                Kind::ConflictingMapping,
+                format!(
+                    "index map has symbol \"{}\" for index {old} of {uri}, \
+                     not \"{}\"",
+                    m.symbol.escape_default(),
+                    parameter.symbol.escape_default(),
+                ),
+            ),
+            None => warn(
+                Kind::MissingMapping,
+                format!(
+                    "no index map entry for \"{}\" (index {old}) of {uri}",
+                    parameter.symbol.escape_default(),
+                ),
+            ),
         };
-        if let Some(i) = plugin.port_index(id.symbol) {
-            return *vacant.insert(i);
+        resolved.push((parameter, index));
+    }
+    resolved
+}
+
+/// Where new parameter indices come from.
+pub enum Source {
+    /// Resolve port symbols with lilv.
+    Lv2(Box<Plugins>),
+    /// Resolve LADSPA port names from the plugins' descriptors.
+    Ladspa(ladspa::Plugins),
+    /// Apply a previously exported index map.
+    IndexMap(IndexMap),
+    /// Resolve port symbols with a saved inventory of plugins' ports.
+    PortMap(Inventory),
+    /// Query a remote metadata service.
+    #[cfg(feature = "http")]
+    Http(http::Client),
+    /// Look up ports with another [`PortResolver`].
+    Custom(Box<dyn PortResolver>),
+}
+
+impl Source {
+    /// The source's port lookup, unless it applies an index map.
+    fn ports(&mut self) -> Option<&mut dyn PortResolver> {
+        match self {
+            Self::Lv2(plugins) => Some(&mut **plugins),
+            Self::Ladspa(plugins) => Some(plugins),
+            Self::IndexMap(_) => None,
+            Self::PortMap(inventory) => Some(inventory),
+            #[cfg(feature = "http")]
+            Self::Http(client) => Some(client),
+            Self::Custom(resolver) => Some(&mut **resolver),
         }
-        eprintln!(
-            "warning: could not find port \"{}\" in {}",
-            id.symbol.escape_default(),
-            id.uri,
-        );
-        let count =
-            self.count.entry(id.uri).or_insert_with(|| plugin.num_ports());
-        *vacant.insert(std::mem::replace(count, *count + 1))
     }
 }
 
-struct Patcher<'a, 'xml> {
-    root: roxmltree::Node<'a, 'xml>,
-    plugins: Plugins,
-    ports: PortMap<'a>,
-    replacements: Vec<Replacement>,
+/// Routes each plugin to the [`Source`] its new indices come from.
+pub struct Resolver {
+    sources: Vec<Source>,
+    /// Plugins pinned to a source other than the default, as indices into
+    /// `sources`.
+    pins: HashMap<String, usize>,
+    /// The source used for LADSPA plugins that aren't pinned, if not the
+    /// default.
+    ladspa: Option<usize>,
+    /// Plugins treated as missing regardless of whether they're available.
+    simulated_missing: HashSet<String>,
+    ports: PortMap,
+    /// The plugin suggested in place of each missing plugin, if any.
+    similar: HashMap<String, Option<String>>,
 }
 
-impl<'a, 'xml> Patcher<'a, 'xml> {
-    fn handle_processor(&mut self, processor: Processor<'a>) {
-        let uri = processor.uri();
-        let Some(mut plugin) = self.plugins.get(uri) else {
-            eprintln!("warning: could not find plugin: {uri}");
-            return;
+impl Resolver {
+    /// Creates a resolver that uses `default` for every plugin that isn't
+    /// pinned. If `default` is [`Source::Lv2`], LADSPA plugins are resolved
+    /// with the installed LADSPA plugins instead.
+    pub fn new(default: Source) -> Self {
+        let lv2 = matches!(default, Source::Lv2(_));
+        let mut this = Self {
+            sources: vec![default],
+            pins: HashMap::new(),
+            ladspa: None,
+            simulated_missing: HashSet::new(),
+            ports: PortMap::default(),
+            similar: HashMap::new(),
         };
-        for parameter in processor.parameters() {
-            let index = self.ports.index(&mut plugin, PortId {
-                uri,
-                symbol: parameter.symbol,
-            });
-            if index == parameter.old_index {
-                continue;
+        if lv2 {
+            let plugins = ladspa::Plugins::new(ladspa::search_path());
+            this.ladspa = Some(this.add(Source::Ladspa(plugins)));
+        }
+        this
+    }
+
+    /// Adds a source that plugins can be pinned to with [`Self::pin`].
+    pub fn add(&mut self, source: Source) -> usize {
+        self.sources.push(source);
+        self.sources.len() - 1
+    }
+
+    /// Pins the plugin `uri` to a source returned by [`Self::add`].
+    pub fn pin(&mut self, uri: String, source: usize) {
+        self.pins.insert(uri, source);
+    }
+
+    /// Makes the plugin `uri` appear to be missing, to preview how sessions
+    /// would be fixed on a machine without it.
+    pub fn simulate_missing(&mut self, uri: String) {
+        self.simulated_missing.insert(uri);
+    }
+
+    /// Discards anything cached about the plugin `uri` by any source.
+    pub fn refresh(&mut self, uri: &str) {
+        self.similar.remove(uri);
+        for source in &mut self.sources {
+            if let Some(ports) = source.ports() {
+                ports.refresh(uri);
             }
-            self.replacements.push(Replacement {
-                location: parameter.location,
-                value: index,
-            })
         }
     }
 
-    fn populate_replacements(&mut self) -> Result<(), Error> {
-        let mut next = Some(self.root);
-        while let Some(node) = next {
-            next = None;
-            if node.has_tag_name("Processor") {
-                if let Some(p) = Processor::parse(node) {
-                    self.handle_processor(p);
-                }
-            } else {
-                next = node.first_child();
+    /// Returns the index of the source for `processor`'s plugin.
+    fn route(&self, processor: &Processor<'_>) -> usize {
+        if let Some(&i) = self.pins.get(processor.uri()) {
+            return i;
+        }
+        match (processor.kind(), self.ladspa) {
+            (PluginKind::Ladspa, Some(i)) => i,
+            _ => 0,
+        }
+    }
+
+    /// Returns the human-readable name of `processor`'s plugin, if its
+    /// source provides one.
+    fn plugin_name(&mut self, processor: &Processor<'_>) -> Option<String> {
+        let i = self.route(processor);
+        self.sources[i].ports()?.plugin_name(processor.uri())
+    }
+
+    /// Returns an available plugin whose URI is similar to that of
+    /// `processor`'s missing plugin, if there is one.
+    fn similar_uri(&mut self, processor: &Processor<'_>) -> Option<String> {
+        let uri = processor.uri();
+        if !self.similar.contains_key(uri) {
+            let i = self.route(processor);
+            let uris = match self.sources[i].ports() {
+                Some(ports) => ports.uris(),
+                None => Vec::new(),
+            };
+            let similar =
+                lv2::similar_uri(uri, uris.iter().map(String::as_str));
+            self.similar.insert(uri.to_owned(), similar.map(str::to_owned));
+        }
+        self.similar[uri].clone()
+    }
+
+    /// Returns whether the port `symbol` of the plugin `uri` couldn't be
+    /// found and was assigned a new index.
+    fn is_synthetic(&self, uri: &str, symbol: &str) -> bool {
+        self.ports.is_synthetic(uri, symbol)
+    }
+
+    /// Resolves the new index of each of `processor`'s parameters, or
+    /// returns [`None`] if its plugin is missing.
+    fn resolve<'a>(
+        &mut self,
+        processor: &Processor<'a>,
+        synthesize: bool,
+    ) -> Option<Vec<(Parameter<'a>, Option<u32>)>> {
+        let uri = processor.uri();
+        if self.simulated_missing.contains(uri) {
+            return None;
+        }
+        let i = self.route(processor);
+        match &mut self.sources[i] {
+            Source::IndexMap(map) => Some(resolve_mapped(map, processor)),
+            source => {
+                let plugin = source.ports().unwrap();
+                self.ports.resolve(plugin, processor, synthesize)
             }
-            next = next.or_else(|| {
-                node.ancestors().filter_map(|a| a.next_sibling()).next()
-            });
         }
-        Ok(())
     }
+}
 
-    fn run(mut self) -> Result<PatchedSession<'xml>, Error> {
-        self.populate_replacements()?;
-        self.replacements.sort_unstable_by_key(|r| r.location.start);
-        Ok(PatchedSession {
-            xml: self.root.document().input_text(),
-            replacements: self.replacements,
-        })
+/// The outcome for a parameter: its new index, or the reason it was skipped.
+type Decision = Result<u32, SkipReason>;
+
+/// A processor id that has already been seen, with the decisions made for its
+/// parameters, so that duplicate copies of the processor are fixed
+/// identically.
+struct SeenProcessor<'a> {
+    uri: &'a str,
+    decisions: HashMap<(u32, &'a str), Decision>,
+}
+
+/// A repair made to a session. [`patch`] passes each element of the session
+/// to every fixer in document order, and the fixers record their edits in a
+/// shared set of [`Splices`].
+pub trait Fixer<'a, 'xml: 'a> {
+    fn visit(&mut self, node: Node<'a, 'xml>, splices: &mut Splices);
+}
+
+/// Passes each element under `root` to every fixer in `fixers`, in document
+/// order.
+fn traverse<'a, 'xml: 'a>(
+    root: Node<'a, 'xml>,
+    fixers: &mut [&mut dyn Fixer<'a, 'xml>],
+    splices: &mut Splices,
+) {
+    for node in root.descendants().filter(Node::is_element) {
+        for fixer in fixers.iter_mut() {
+            fixer.visit(node, splices);
+        }
     }
 }
 
-pub fn patch(xml: &str) -> Result<PatchedSession<'_>, Error> {
-    Patcher {
-        root: roxmltree::Document::parse(xml)?.root(),
-        plugins: Plugins::new()?,
-        ports: PortMap::new(),
-        replacements: Vec::new(),
+/// Fixes the parameter indices of each LV2 and LADSPA processor.
+struct IndexFixer<'a, 'xml, 's> {
+    /// The session text.
+    xml: &'xml str,
+    lines: &'s LineIndex<'xml>,
+    resolver: &'s mut Resolver,
+    processors: HashMap<&'a str, SeenProcessor<'a>>,
+    summary: Summary,
+    /// The offset of each parameter index that was checked, and the index it
+    /// should have.
+    expected: Vec<(usize, u32)>,
+    options: Options,
+}
+
+impl<'a, 'xml: 'a> Fixer<'a, 'xml> for IndexFixer<'a, 'xml, '_> {
+    fn visit(&mut self, node: Node<'a, 'xml>, splices: &mut Splices) {
+        if !node.has_tag_name("Processor") {
+            return;
+        }
+        if let Some(processor) = Processor::parse(node, self.lines) {
+            self.handle_processor(processor, splices);
+        }
+    }
+}
+
+impl<'a> IndexFixer<'a, '_, '_> {
+    fn skip(
+        &mut self,
+        processor: &Processor<'_>,
+        parameter: Parameter<'_>,
+        reason: SkipReason,
+    ) {
+        self.summary.skipped.push(Skipped {
+            uri: processor.uri().to_owned(),
+            instance: Instance::of(processor),
+            owner: Owner::of(processor),
+            symbol: parameter.symbol.to_owned(),
+            old_index: parameter.old_index,
+            offset: parameter.location.start,
+            reason,
+        });
+    }
+
+    fn decide(
+        &self,
+        parameter: &Parameter<'_>,
+        index: Option<u32>,
+    ) -> Decision {
+        let index = index.ok_or(SkipReason::Unresolved)?;
+        if self.options.conservative
+            && parameter.ambiguous
+            && index != parameter.old_index
+        {
+            return Err(SkipReason::Ambiguous);
+        }
+        Ok(index)
+    }
+
+    fn replace(
+        &mut self,
+        processor: &Processor<'_>,
+        parameter: Parameter<'_>,
+        decision: Decision,
+        splices: &mut Splices,
+    ) {
+        let uri = processor.uri();
+        self.summary.parameters += 1;
+        let start = parameter.location.start;
+        let position = self.lines.position(start);
+        let symbol = parameter.symbol.escape_default();
+        let old = parameter.old_index;
+        let index = match decision {
+            Ok(index) => index,
+            Err(reason) => {
+                diag::emit(
+                    Level::Debug,
+                    format_args!(
+                        "{uri}: \"{symbol}\": {old} skipped ({}, at \
+                         {position})",
+                        reason.as_str(),
+                    ),
+                );
+                self.expected.push((start, parameter.old_index));
+                self.skip(processor, parameter, reason);
+                return;
+            }
+        };
+        let mapping = Mapping {
+            symbol: parameter.symbol.to_owned(),
+            new_index: index,
+        };
+        if let Err(existing) =
+            self.summary.index_map.insert(uri, parameter.old_index, mapping)
+        {
+            diag::warn(
+                processor
+                    .warning(
+                        Kind::ConflictingMapping,
+                        format!(
+                            "index {} of {uri} maps to both {} and {index}",
+                            parameter.old_index, existing.new_index,
+                        ),
+                    )
+                    .symbol(parameter.symbol)
+                    .at(start, position),
+            );
+        }
+        self.expected.push((start, index));
+        if index == parameter.old_index {
+            diag::emit(
+                Level::Debug,
+                format_args!(
+                    "{uri}: \"{symbol}\": {old} already correct (at \
+                     {position})",
+                ),
+            );
+            self.summary.verified += 1;
+            return;
+        }
+        let synthetic = self.resolver.is_synthetic(uri, parameter.symbol);
+        diag::emit(
+            Level::Debug,
+            format_args!(
+                "{uri}: \"{symbol}\": {old} -> {index} (at {position}){}",
+                if synthetic {
+                    " (port not found)"
+                } else {
+                    ""
+                },
+            ),
+        );
+        self.summary.changes.push(Change {
+            uri: uri.to_owned(),
+            instance: Instance::of(processor),
+            owner: Owner::of(processor),
+            symbol: parameter.symbol.to_owned(),
+            old_index: parameter.old_index,
+            new_index: index,
+            offset: parameter.location.start,
+            end: parameter.location.end,
+            synthetic,
+            context: Some(Context::new(self.xml, &parameter.location)),
+        });
+        splices.replace(parameter.location, index);
+    }
+
+    fn missing_plugin(&mut self, processor: &Processor<'_>) {
+        let uri = processor.uri();
+        let mut warning = processor
+            .warning(
+                Kind::MissingPlugin,
+                format!(
+                    "could not find plugin: {uri} (at {})",
+                    processor.position(),
+                ),
+            )
+            .at(processor.offset(), processor.position());
+        if let Some(similar) = self.resolver.similar_uri(processor) {
+            warning = warning.hint(format!("did you mean {similar}?"));
+        }
+        diag::warn(warning);
+        self.summary.missing_plugins.insert(uri.to_owned());
+    }
+
+    /// Resolves the new index of each of `processor`'s parameters.
+    fn resolve(
+        &mut self,
+        processor: &Processor<'a>,
+    ) -> Option<Vec<(Parameter<'a>, Option<u32>)>> {
+        let synthesize = !self.options.conservative;
+        let resolved = self.resolver.resolve(processor, synthesize);
+        self.summary.plugins.insert(processor.uri().to_owned());
+        if resolved.is_none() {
+            self.missing_plugin(processor);
+        }
+        resolved
+    }
+
+    /// Returns the decisions made for an earlier copy of `processor` (a
+    /// processor with the same id), warning about the duplication.
+    fn seen(&mut self, processor: &Processor<'a>) -> SeenProcessor<'a> {
+        let uri = processor.uri();
+        let fresh = SeenProcessor {
+            uri,
+            decisions: HashMap::new(),
+        };
+        let Some(id) = processor.id() else {
+            return fresh;
+        };
+        let Some(seen) = self.processors.get(id) else {
+            return fresh;
+        };
+        if seen.uri != uri {
+            diag::warn(
+                processor
+                    .warning(
+                        Kind::DuplicateProcessor,
+                        format!(
+                            "processor id {id} is used by both {} and {uri}",
+                            seen.uri,
+                        ),
+                    )
+                    .at(processor.offset(), processor.position()),
+            );
+            return fresh;
+        }
+        diag::warn(
+            processor
+                .warning(
+                    Kind::DuplicateProcessor,
+                    format!(
+                        "processor id {id} of {uri} appears more than once"
+                    ),
+                )
+                .at(processor.offset(), processor.position()),
+        );
+        self.processors.remove(id).unwrap()
+    }
+
+    /// Whether `processor` matches the plugin name filter, if any. If the
+    /// plugin's source doesn't provide its name, the processor's name in the
+    /// session is used.
+    fn selected(&mut self, processor: &Processor<'_>) -> bool {
+        let Some(filter) = &self.options.plugin_name else {
+            return true;
+        };
+        let name = self.resolver.plugin_name(processor);
+        let name = name.as_deref().or(processor.name());
+        name.is_some_and(|name| filter.matches(name))
+    }
+
+    fn handle_processor(
+        &mut self,
+        processor: Processor<'a>,
+        splices: &mut Splices,
+    ) {
+        if self.options.skip_bypassed && !processor.active() {
+            self.summary.bypassed += 1;
+            return;
+        }
+        if !self.selected(&processor) {
+            return;
+        }
+        let mut seen = self.seen(&processor);
+        self.summary.unchecked += processor.unnamed();
+        let Some(resolved) = self.resolve(&processor) else {
+            self.summary.unchecked += processor.parameters().count();
+            return;
+        };
+        for (parameter, index) in resolved {
+            let key = (parameter.old_index, parameter.symbol);
+            let decision = match seen.decisions.get(&key) {
+                Some(&decision) => decision,
+                None => self.decide(&parameter, index),
+            };
+            seen.decisions.insert(key, decision);
+            self.replace(&processor, parameter, decision, splices);
+        }
+        if let Some(id) = processor.id() {
+            self.processors.entry(id).or_insert(seen);
+        }
+    }
+}
+
+impl Summary {
+    /// Puts the changes and skipped parameters in order of appearance, and
+    /// fills in the line number of each change.
+    fn finish(&mut self, lines: &LineIndex<'_>) {
+        self.changes.sort_by_key(|c| c.offset);
+        self.skipped.sort_by_key(|s| s.offset);
+        for change in &mut self.changes {
+            if let Some(context) = &mut change.context {
+                context.line = lines.position(change.offset).line;
+            }
+        }
+    }
+}
+
+/// Fixes the parameter indices of the session `xml`, resolving new indices
+/// through `resolver`. The resolver can be reused for several sessions so
+/// that ports that can't be found get the same new index in each.
+pub fn patch<'xml>(
+    xml: &'xml str,
+    resolver: &mut Resolver,
+    options: Options,
+) -> Result<PatchedSession<'xml>, Error> {
+    let document = roxmltree::Document::parse(xml)?;
+    let lines = LineIndex::new(xml);
+    let mut splices = Splices::new();
+    let mut indices = IndexFixer {
+        xml,
+        lines: &lines,
+        resolver,
+        processors: HashMap::new(),
+        summary: Summary::default(),
+        expected: Vec::new(),
+        options,
+    };
+    traverse(document.root(), &mut [&mut indices], &mut splices);
+    let IndexFixer {
+        mut summary,
+        expected,
+        options,
+        ..
+    } = indices;
+    for mut pass in post_processors(&options) {
+        pass.process(&mut Edits {
+            document: &document,
+            summary: &summary,
+            splices: &mut splices,
+        });
     }
-    .run()
+    summary.finish(&lines);
+    Ok(PatchedSession {
+        session: splices.apply(xml),
+        summary,
+        expected,
+    })
 }
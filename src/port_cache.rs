@@ -0,0 +1,200 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A persistent cache of the ports of installed plugins.
+//!
+//! Each plugin's ports are stored along with the path and modification time
+//! of the bundle they were read from, and are discarded when that bundle
+//! changes or is no longer in the LV2 search path. This lets runs over many
+//! sessions skip loading plugins with lilv.
+
+use super::diag::{self, Kind, Warning};
+use super::dirs::hash_name;
+use super::json::{self, Str};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// The version of the cache entry format.
+const VERSION: u32 = 1;
+
+/// The ports of a plugin.
+#[derive(Clone, Debug, Default)]
+pub struct Ports {
+    /// The plugin's human-readable name.
+    pub name: Option<String>,
+    pub num_ports: u32,
+    /// Port indices by symbol.
+    pub ports: BTreeMap<String, u32>,
+}
+
+/// Returns the latest modification time, in nanoseconds, of `bundle` and
+/// the files in it.
+fn bundle_time(bundle: &Path) -> Option<u128> {
+    let mtime = |m: std::fs::Metadata| {
+        let time = m.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(time.as_nanos())
+    };
+    let mut time = mtime(std::fs::metadata(bundle).ok()?)?;
+    for entry in std::fs::read_dir(bundle).ok()?.filter_map(|e| e.ok()) {
+        time = time.max(entry.metadata().ok().and_then(mtime).unwrap_or(0));
+    }
+    Some(time)
+}
+
+struct Entry<'a> {
+    uri: &'a str,
+    bundle: &'a str,
+    time: u128,
+    ports: &'a Ports,
+}
+
+impl Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{\"version\": {VERSION}, \"uri\": {}, \"bundle\": {}, ",
+            Str(self.uri),
+            Str(self.bundle),
+        )?;
+        // Stored as a string, since JSON numbers can't hold it exactly.
+        write!(f, "\"mtime\": \"{}\", \"name\": ", self.time)?;
+        match &self.ports.name {
+            Some(name) => write!(f, "{}", Str(name))?,
+            None => write!(f, "null")?,
+        }
+        write!(f, ", \"num_ports\": {}, \"ports\": {{", self.ports.num_ports)?;
+        for (i, (symbol, index)) in self.ports.ports.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {index}", Str(symbol))?;
+        }
+        writeln!(f, "}}}}")
+    }
+}
+
+pub struct PortCache {
+    dir: PathBuf,
+    search_path: Vec<PathBuf>,
+    /// Whether existing entries are ignored, so that they're replaced.
+    refresh: bool,
+}
+
+impl PortCache {
+    /// Creates a cache in `dir` for plugins in the LV2 search path
+    /// `search_path`. If `refresh` is true, existing entries are ignored and
+    /// replaced as plugins are looked up again.
+    pub fn new(
+        dir: PathBuf,
+        search_path: Vec<PathBuf>,
+        refresh: bool,
+    ) -> Self {
+        Self {
+            dir,
+            search_path,
+            refresh,
+        }
+    }
+
+    fn path(&self, uri: &str) -> PathBuf {
+        self.dir.join(hash_name([uri.as_bytes()]))
+    }
+
+    /// Returns the cached ports of the plugin `uri`, if its bundle hasn't
+    /// changed since they were stored.
+    pub fn get(&self, uri: &str) -> Option<Ports> {
+        if self.refresh {
+            return None;
+        }
+        let value =
+            json::parse(&std::fs::read_to_string(self.path(uri)).ok()?)
+                .ok()?;
+        if value.get("version")?.as_u32()? != VERSION
+            || value.get("uri")?.as_str()? != uri
+        {
+            return None;
+        }
+        let bundle = Path::new(value.get("bundle")?.as_str()?);
+        let dir = bundle.parent()?;
+        if !self.search_path.iter().any(|d| d == dir) {
+            return None;
+        }
+        let time = value.get("mtime")?.as_str()?.parse().ok()?;
+        if bundle_time(bundle)? != time {
+            return None;
+        }
+        let ports = value.get("ports")?.as_object()?;
+        Some(Ports {
+            name: value.get("name")?.as_str().map(str::to_owned),
+            num_ports: value.get("num_ports")?.as_u32()?,
+            ports: ports
+                .iter()
+                .map(|(symbol, index)| Some((symbol.clone(), index.as_u32()?)))
+                .collect::<Option<_>>()?,
+        })
+    }
+
+    fn write(
+        &self,
+        uri: &str,
+        bundle: &Path,
+        ports: &Ports,
+    ) -> io::Result<()> {
+        // Plugins whose bundles can't be described aren't cached.
+        let (Some(bundle), Some(time)) =
+            (bundle.to_str(), bundle_time(bundle))
+        else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(&self.dir)?;
+        let entry = Entry {
+            uri,
+            bundle,
+            time,
+            ports,
+        };
+        std::fs::write(self.path(uri), entry.to_string())
+    }
+
+    /// Stores the ports of the plugin `uri`, which was loaded from `bundle`.
+    pub fn insert(&self, uri: &str, bundle: &Path, ports: &Ports) {
+        if let Err(e) = self.write(uri, bundle, ports) {
+            diag::warn(Warning::new(
+                Kind::Cache,
+                format!("could not write to cache: {e}"),
+            ));
+        }
+    }
+
+    /// Discards the cached ports of the plugin `uri`.
+    pub fn remove(&self, uri: &str) {
+        match std::fs::remove_file(self.path(uri)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                diag::warn(Warning::new(
+                    Kind::Cache,
+                    format!("could not remove cached ports: {e}"),
+                ));
+            }
+            _ => {}
+        }
+    }
+}
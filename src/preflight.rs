@@ -0,0 +1,192 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A combined check and fix for use in scripts that launch Ardour.
+//!
+//! Each snapshot is skipped if it may still be in use, fixed conservatively
+//! and verified, saved with a backup, and then checked by fixing the result
+//! again, which must not change anything (otherwise the backup is restored).
+//! The outcome is printed as a single line of JSON.
+
+use super::args::RunArgs;
+use super::backup;
+use super::diag;
+use super::flock::SessionLock;
+use super::gzip;
+use super::json::Str;
+use super::patch::{self, Resolver};
+use super::{ardour_lock, patch_options, save_in_place, time_until_idle};
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+use std::path::Path;
+
+/// The outcome for a session, from least to most severe.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Outcome {
+    /// No indices needed to be changed.
+    #[default]
+    Clean,
+    /// Indices were changed and the result checked.
+    Fixed,
+    /// A snapshot is open in Ardour, or was modified too recently, so it
+    /// may be.
+    Busy,
+    /// A snapshot couldn't be fixed or failed the check.
+    Error,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Clean => "clean",
+            Self::Fixed => "fixed",
+            Self::Busy => "busy",
+            Self::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Preflight {
+    pub outcome: Outcome,
+    changed: usize,
+    skipped: usize,
+    missing_plugins: BTreeSet<String>,
+    /// Describes the first error.
+    message: Option<String>,
+}
+
+impl Preflight {
+    fn fail(&mut self, outcome: Outcome, message: String) {
+        self.outcome = self.outcome.max(outcome);
+        self.message.get_or_insert(message);
+    }
+}
+
+/// Fixes the snapshot at `path`, returning whether anything was changed.
+fn fix(
+    path: &Path,
+    args: &RunArgs,
+    resolver: &mut Resolver,
+    preflight: &mut Preflight,
+) -> Result<bool, String> {
+    let name = path.display();
+    if time_until_idle(path, args.settle_time).is_some() {
+        preflight.fail(Outcome::Busy, format!("{name} was just modified"));
+        return Ok(false);
+    }
+    if ardour_lock(path).is_some() {
+        preflight.fail(Outcome::Busy, format!("{name} is open in Ardour"));
+        return Ok(false);
+    }
+    let read = |path: &Path| {
+        gzip::read_file(path)
+            .map_err(|e| format!("could not read {name}: {e}"))
+    };
+    let options = patch::Options {
+        conservative: true,
+        ..patch_options(args)
+    };
+    let _lock = SessionLock::acquire(path, || {})
+        .map_err(|e| format!("could not lock {name}: {e}"))?;
+    let (xml, compressed) = read(path)?;
+    let patched = patch::patch(&xml, resolver, options.clone())
+        .map_err(|e| format!("{name}: {e}"))?;
+    let summary = patched.summary();
+    preflight.skipped += summary.skipped.len();
+    preflight.missing_plugins.extend(summary.missing_plugins.iter().cloned());
+    let changed = summary.changes.len();
+    if changed == 0 {
+        return Ok(false);
+    }
+    patched.verify().map_err(|e| format!("{name}: {e}"))?;
+    let Ok(backup) = save_in_place(path, args, &patched, compressed) else {
+        let errors = diag::take_errors();
+        return Err(errors
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| format!("could not write {name}")));
+    };
+    let recheck = read(path).and_then(|(xml, _)| {
+        let rechecked = patch::patch(&xml, resolver, options)
+            .map_err(|e| format!("fixed {name} is invalid: {e}"))?;
+        if !rechecked.summary().changes.is_empty() {
+            return Err(format!("fixed {name} failed the check"));
+        }
+        Ok(())
+    });
+    if let Err(message) = recheck {
+        // A symlink was kept by saving the file it points to.
+        let target = std::fs::canonicalize(path);
+        let restored = target.and_then(|t| backup::restore(&backup, &t));
+        return Err(match restored {
+            Ok(()) => format!("{message}; the original was restored"),
+            Err(e) => format!(
+                "{message}, and the original could not be restored from \
+                 {}: {e}",
+                backup.display(),
+            ),
+        });
+    }
+    preflight.changed += changed;
+    Ok(true)
+}
+
+/// Fixes the snapshots in `paths`, saving them as `args` specifies. Errors
+/// are described in the outcome instead of printed.
+pub fn run<'a, I>(
+    paths: I,
+    args: &RunArgs,
+    resolver: &mut Resolver,
+) -> Preflight
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    diag::capture_errors();
+    let mut preflight = Preflight::default();
+    for path in paths {
+        match fix(path, args, resolver, &mut preflight) {
+            Ok(true) => {
+                preflight.outcome = preflight.outcome.max(Outcome::Fixed);
+            }
+            Ok(false) => {}
+            Err(message) => preflight.fail(Outcome::Error, message),
+        }
+    }
+    preflight
+}
+
+impl Display for Preflight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{\"result\": {}, \"changed\": {}, \"skipped\": {}, \
+             \"missing_plugins\": {}, \"message\": ",
+            Str(self.outcome.as_str()),
+            self.changed,
+            self.skipped,
+            self.missing_plugins.len(),
+        )?;
+        match &self.message {
+            Some(message) => write!(f, "{}", Str(message))?,
+            None => write!(f, "null")?,
+        }
+        writeln!(f, "}}")
+    }
+}
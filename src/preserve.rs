@@ -0,0 +1,93 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Preservation of a session's metadata when it is rewritten.
+//!
+//! When a session is backed up by renaming it, or an archive is replaced,
+//! the patched file is new, so it would otherwise get default permissions
+//! and belong to whoever ran the program.
+
+use std::ffi::{c_int, c_long};
+use std::fs::{File, Metadata};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Sets the modification time of the file at `path` to that of `original`.
+fn set_mtime(path: &Path, original: &Metadata) -> io::Result<()> {
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: c_long,
+        tv_nsec: c_long,
+    }
+
+    extern "C" {
+        fn futimens(fd: c_int, times: *const Timespec) -> c_int;
+    }
+
+    let file = File::open(path)?;
+    let current = file.metadata()?;
+    let times = [
+        Timespec {
+            tv_sec: current.atime() as c_long,
+            tv_nsec: current.atime_nsec() as c_long,
+        },
+        Timespec {
+            tv_sec: original.mtime() as c_long,
+            tv_nsec: original.mtime_nsec() as c_long,
+        },
+    ];
+    // SAFETY: `futimens` takes a file descriptor and a pointer to two
+    // `struct timespec`s, which `times` is valid for during the call.
+    if unsafe { futimens(file.as_raw_fd(), times.as_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Gives the file at `path` the permissions and ownership in `original`,
+/// and its modification time if `mtime` is true. Only what differs is
+/// changed, since a file rewritten in place may belong to someone else. If
+/// the owner can't be changed (only root can give files away), just the
+/// group is restored.
+pub fn restore(
+    path: &Path,
+    original: &Metadata,
+    mtime: bool,
+) -> io::Result<()> {
+    if mtime {
+        set_mtime(path, original)?;
+    }
+    let current = std::fs::metadata(path)?;
+    let (uid, gid) = (original.uid(), original.gid());
+    if (current.uid(), current.gid()) != (uid, gid) {
+        let chown = std::os::unix::fs::chown;
+        if chown(path, Some(uid), Some(gid)).is_err() {
+            chown(path, None, Some(gid))?;
+        }
+    }
+    // Checked after changing ownership, which may clear setuid and setgid
+    // bits.
+    let current = std::fs::metadata(path)?;
+    if current.mode() != original.mode() {
+        std::fs::set_permissions(path, original.permissions())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,879 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::color::Color;
+use super::json::{self, Str, Value};
+use super::patch::{Change, Instance, Owner, SkipReason, Skipped};
+use super::session::OwnerKind;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::io;
+use std::path::Path;
+
+/// The version of the JSON report schema.
+pub const JSON_VERSION: u32 = 3;
+
+/// The last report version in which the processor, `end`, `synthetic`, and
+/// the counts of each file may be absent, having been added without a
+/// version change.
+const PARTIAL_VERSION: u32 = 1;
+
+/// The last report version without the plugins used by each file.
+const PLUGINLESS_VERSION: u32 = 2;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+    Html,
+}
+
+impl Format {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"text" => Self::Text,
+            b"json" => Self::Json,
+            b"csv" => Self::Csv,
+            b"html" => Self::Html,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct FileReport {
+    /// The session file, or "-" for standard input.
+    pub path: String,
+    pub changes: Vec<Change>,
+    pub skipped: Vec<Skipped>,
+    /// The number of bypassed processors that were left unchanged.
+    pub bypassed: usize,
+    /// The number of parameters whose index was verified to be correct.
+    pub verified: usize,
+    /// The number of parameters that couldn't be checked.
+    pub unchecked: usize,
+    /// The plugins used by the session whose ports were looked up.
+    pub plugins: BTreeSet<String>,
+    /// The plugins used by the session that couldn't be found.
+    pub missing_plugins: BTreeSet<String>,
+}
+
+/// The changes made to every session processed in a run.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub files: Vec<FileReport>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(json::Error),
+    Format(&'static str),
+    /// A field required in this version of the report is absent.
+    Missing(&'static str),
+    Version(u32),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Json(e) => write!(f, "invalid json: {e}"),
+            Self::Format(s) => write!(f, "invalid report: {s}"),
+            Self::Missing(s) => write!(f, "invalid report: missing `{s}`"),
+            Self::Version(v) => {
+                write!(f, "unsupported report version: {v}")
+            }
+        }
+    }
+}
+
+fn parse_owner(value: Option<&Value>) -> Result<Option<Owner>, Error> {
+    let Some(owner) = value.filter(|v| !matches!(v, Value::Null)) else {
+        return Ok(None);
+    };
+    let kind = owner.get("kind").and_then(Value::as_str);
+    let name = owner.get("name").and_then(Value::as_str);
+    let (Some(kind), Some(name)) = (kind, name) else {
+        return Err(Error::Format("owner must have `kind` and `name`"));
+    };
+    let Some(kind) = OwnerKind::from_name(kind.as_bytes()) else {
+        return Err(Error::Format("unknown owner kind"));
+    };
+    let index = owner.get("index").and_then(Value::as_u32);
+    Ok(Some(Owner {
+        kind,
+        name: name.to_owned(),
+        index: index.map(|i| i as usize),
+    }))
+}
+
+/// Gets the field `name` of `value`, which reports of [`PARTIAL_VERSION`]
+/// or earlier may lack.
+fn later_field<'a>(
+    value: &'a Value,
+    name: &'static str,
+    version: u32,
+) -> Result<Option<&'a Value>, Error> {
+    match value.get(name) {
+        None if version > PARTIAL_VERSION => Err(Error::Missing(name)),
+        field => Ok(field),
+    }
+}
+
+fn parse_instance(value: Option<&Value>) -> Result<Instance, Error> {
+    let Some(processor) = value.filter(|v| !matches!(v, Value::Null)) else {
+        return Ok(Instance::default());
+    };
+    let field = |name| match processor.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v
+            .as_str()
+            .map(|s| Some(s.to_owned()))
+            .ok_or(Error::Format("invalid processor")),
+    };
+    Ok(Instance {
+        name: field("name")?,
+        id: field("id")?,
+    })
+}
+
+/// The fields common to changes and skipped parameters.
+struct Entry {
+    uri: String,
+    instance: Instance,
+    owner: Option<Owner>,
+    symbol: String,
+    old_index: u32,
+    offset: usize,
+}
+
+fn parse_entry(value: &Value, version: u32) -> Result<Entry, Error> {
+    let field = |name| value.get(name);
+    let (Some(uri), Some(symbol), Some(old_index), Some(offset)) = (
+        field("uri").and_then(Value::as_str),
+        field("symbol").and_then(Value::as_str),
+        field("old").and_then(Value::as_u32),
+        field("offset").and_then(Value::as_u32),
+    ) else {
+        return Err(Error::Format(
+            "entry must have `uri`, `symbol`, `old`, and `offset`",
+        ));
+    };
+    Ok(Entry {
+        uri: uri.to_owned(),
+        instance: parse_instance(later_field(value, "processor", version)?)?,
+        owner: parse_owner(field("owner"))?,
+        symbol: symbol.to_owned(),
+        old_index,
+        offset: offset as usize,
+    })
+}
+
+fn parse_file(value: &Value, version: u32) -> Result<FileReport, Error> {
+    let Some(path) = value.get("path").and_then(Value::as_str) else {
+        return Err(Error::Format("file must have a `path`"));
+    };
+    let list = |name| {
+        value
+            .get(name)
+            .and_then(Value::as_array)
+            .ok_or(Error::Format("file must have `changes` and `skipped`"))
+    };
+    let mut changes = Vec::new();
+    for change in list("changes")? {
+        let Some(new_index) = change.get("new").and_then(Value::as_u32) else {
+            return Err(Error::Format("change must have `new`"));
+        };
+        let entry = parse_entry(change, version)?;
+        // Indices are written without padding, so `end` can be recomputed
+        // if absent.
+        let end = match later_field(change, "end", version)? {
+            Some(end) => end
+                .as_u32()
+                .map(|end| end as usize)
+                .ok_or(Error::Format("invalid `end`"))?,
+            None => entry.offset + entry.old_index.to_string().len(),
+        };
+        let synthetic = match later_field(change, "synthetic", version)? {
+            Some(b) => {
+                b.as_bool().ok_or(Error::Format("invalid `synthetic`"))?
+            }
+            None => false,
+        };
+        changes.push(Change {
+            uri: entry.uri,
+            instance: entry.instance,
+            owner: entry.owner,
+            symbol: entry.symbol,
+            old_index: entry.old_index,
+            new_index,
+            offset: entry.offset,
+            end,
+            synthetic,
+            context: None,
+        });
+    }
+    let mut skipped = Vec::new();
+    for skip in list("skipped")? {
+        let Some(reason) = skip
+            .get("reason")
+            .and_then(Value::as_str)
+            .and_then(|r| SkipReason::from_name(r.as_bytes()))
+        else {
+            return Err(Error::Format("skipped entry must have a `reason`"));
+        };
+        let entry = parse_entry(skip, version)?;
+        skipped.push(Skipped {
+            uri: entry.uri,
+            instance: entry.instance,
+            owner: entry.owner,
+            symbol: entry.symbol,
+            old_index: entry.old_index,
+            offset: entry.offset,
+            reason,
+        });
+    }
+    let count = |name| match later_field(value, name, version)? {
+        Some(n) => n
+            .as_u32()
+            .map(|n| n as usize)
+            .ok_or(Error::Format("invalid count")),
+        None => Ok(0),
+    };
+    let uris = |name| -> Result<BTreeSet<String>, Error> {
+        let Some(list) = value.get(name) else {
+            return Err(Error::Missing(name));
+        };
+        let list = list.as_array().ok_or(Error::Format("invalid plugins"))?;
+        list.iter()
+            .map(|uri| {
+                let uri = uri.as_str().ok_or(Error::Format("invalid plugins"));
+                uri.map(str::to_owned)
+            })
+            .collect()
+    };
+    let (plugins, missing_plugins) = if version > PLUGINLESS_VERSION {
+        (uris("plugins")?, uris("missing_plugins")?)
+    } else {
+        // The plugins that were found can be recovered from the parameters.
+        let changed = changes.iter().map(|c| c.uri.clone());
+        let skipped = skipped.iter().map(|s| s.uri.clone());
+        (changed.chain(skipped).collect(), BTreeSet::new())
+    };
+    Ok(FileReport {
+        path: path.to_owned(),
+        changes,
+        skipped,
+        bypassed: count("bypassed")?,
+        verified: count("verified")?,
+        unchecked: count("unchecked")?,
+        plugins,
+        missing_plugins,
+    })
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a report written in the JSON format.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let value = json::parse(s).map_err(Error::Json)?;
+        let Some(version) = value.get("version").and_then(Value::as_u32)
+        else {
+            return Err(Error::Format("missing `version`"));
+        };
+        if !(PARTIAL_VERSION..=JSON_VERSION).contains(&version) {
+            return Err(Error::Version(version));
+        }
+        let Some(files) = value.get("files").and_then(Value::as_array) else {
+            return Err(Error::Format("missing `files`"));
+        };
+        let files = files
+            .iter()
+            .map(|file| parse_file(file, version))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            files,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path).map_err(Error::Io)?)
+    }
+
+    pub fn render(&self, format: Format) -> impl Display + '_ {
+        self.render_colored(format, false)
+    }
+
+    /// Like [`Self::render`], but text reports are colored if `color` is
+    /// true.
+    pub fn render_colored(
+        &self,
+        format: Format,
+        color: bool,
+    ) -> impl Display + '_ {
+        Rendered {
+            report: self,
+            format,
+            color,
+        }
+    }
+}
+
+struct Rendered<'a> {
+    report: &'a Report,
+    format: Format,
+    color: bool,
+}
+
+impl Display for Rendered<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format {
+            Format::Text => text(self.report, self.color, f),
+            Format::Json => json(self.report, f),
+            Format::Csv => csv(self.report, f),
+            Format::Html => html(self.report, f),
+        }
+    }
+}
+
+/// Displays a route's position as, e.g., `[07] `, or nothing if it is
+/// unknown.
+struct RouteNumber(Option<usize>);
+
+impl Display for RouteNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(i) => write!(f, "[{i:02}] "),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Describes a processor by its name and id, as, e.g., ` "Comp" (id 123)`.
+struct InstanceLabel<'a>(&'a Instance);
+
+impl Display for InstanceLabel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.0.name {
+            write!(f, " \"{}\"", name.escape_default())?;
+        }
+        match &self.0.id {
+            Some(id) => write!(f, " (id {id})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Describes the route or region a processor belongs to.
+struct OwnerLabel<'a>(Option<&'a Owner>);
+
+impl Display for OwnerLabel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(owner) = self.0 else {
+            return Ok(());
+        };
+        let name = owner.name.escape_default();
+        match owner.kind {
+            OwnerKind::Route => {
+                let number = RouteNumber(owner.index);
+                write!(f, " on route {number}\"{name}\"")
+            }
+            OwnerKind::Region => {
+                write!(f, " (region effect) on region \"{name}\"")
+            }
+        }
+    }
+}
+
+fn text(
+    report: &Report,
+    color: bool,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    for file in &report.files {
+        let n = file.changes.len();
+        let noun = if n == 1 {
+            "index"
+        } else {
+            "indices"
+        };
+        let clean = n == 0 && file.skipped.is_empty() && file.unchecked == 0;
+        writeln!(
+            f,
+            "{}: {}",
+            file.path,
+            Color::Green.paint(
+                format_args!("{n} parameter {noun} changed"),
+                color && clean
+            ),
+        )?;
+        writeln!(
+            f,
+            "  {} already correct, {} could not be checked",
+            file.verified, file.unchecked,
+        )?;
+        if file.bypassed > 0 {
+            let n = file.bypassed;
+            let noun = if n == 1 {
+                "processor"
+            } else {
+                "processors"
+            };
+            writeln!(f, "  {n} bypassed {noun} skipped")?;
+        }
+        let mut processor = None;
+        for change in &file.changes {
+            let current = Some((&change.uri, &change.instance, &change.owner));
+            if processor != current {
+                let instance = InstanceLabel(&change.instance);
+                let owner = OwnerLabel(change.owner.as_ref());
+                writeln!(f, "  {}{instance}{owner}", change.uri)?;
+                processor = current;
+            }
+            writeln!(
+                f,
+                "    {}: {} -> {} (at byte {})",
+                change.symbol.escape_default(),
+                change.old_index,
+                Color::Yellow
+                    .paint(change.new_index, color && change.synthetic),
+                change.offset,
+            )?;
+        }
+        if file.skipped.is_empty() {
+            continue;
+        }
+        let n = file.skipped.len();
+        let noun = if n == 1 {
+            "parameter"
+        } else {
+            "parameters"
+        };
+        let skipped = format!("{n} {noun} skipped");
+        writeln!(f, "  {}:", Color::Yellow.paint(skipped, color))?;
+        for skipped in &file.skipped {
+            writeln!(
+                f,
+                "    {}{}{}: {} (index {}, at byte {}): {}",
+                skipped.uri,
+                InstanceLabel(&skipped.instance),
+                OwnerLabel(skipped.owner.as_ref()),
+                skipped.symbol.escape_default(),
+                skipped.old_index,
+                skipped.offset,
+                skipped.reason.as_str(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn json_instance(
+    instance: &Instance,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    write!(f, "{{")?;
+    for (i, (key, value)) in [("name", &instance.name), ("id", &instance.id)]
+        .into_iter()
+        .enumerate()
+    {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        match value {
+            Some(v) => write!(f, "\"{key}\": {}", Str(v))?,
+            None => write!(f, "\"{key}\": null")?,
+        }
+    }
+    write!(f, "}}")
+}
+
+fn json_owner(
+    owner: Option<&Owner>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    let Some(owner) = owner else {
+        return write!(f, "null");
+    };
+    write!(
+        f,
+        "{{\"kind\": {}, \"name\": {}, \"index\": ",
+        Str(owner.kind.as_str()),
+        Str(&owner.name),
+    )?;
+    match owner.index {
+        Some(i) => write!(f, "{i}}}"),
+        None => write!(f, "null}}"),
+    }
+}
+
+fn json(report: &Report, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{{\n  \"version\": {JSON_VERSION},\n  \"files\": [")?;
+    for (i, file) in report.files.iter().enumerate() {
+        if i > 0 {
+            write!(f, ",")?;
+        }
+        write!(f, "\n    {{\"path\": {}, \"changes\": [", Str(&file.path))?;
+        for (j, change) in file.changes.iter().enumerate() {
+            if j > 0 {
+                write!(f, ",")?;
+            }
+            write!(
+                f,
+                "\n      {{\"uri\": {}, \"processor\": ",
+                Str(&change.uri),
+            )?;
+            json_instance(&change.instance, f)?;
+            write!(f, ", \"owner\": ")?;
+            json_owner(change.owner.as_ref(), f)?;
+            write!(
+                f,
+                ", \"symbol\": {}, \"old\": {}, \"new\": {}, \
+                 \"offset\": {}, \"end\": {}, \"synthetic\": {}}}",
+                Str(&change.symbol),
+                change.old_index,
+                change.new_index,
+                change.offset,
+                change.end,
+                change.synthetic,
+            )?;
+        }
+        if !file.changes.is_empty() {
+            write!(f, "\n    ")?;
+        }
+        write!(f, "], \"skipped\": [")?;
+        for (j, skipped) in file.skipped.iter().enumerate() {
+            if j > 0 {
+                write!(f, ",")?;
+            }
+            write!(
+                f,
+                "\n      {{\"uri\": {}, \"processor\": ",
+                Str(&skipped.uri),
+            )?;
+            json_instance(&skipped.instance, f)?;
+            write!(f, ", \"owner\": ")?;
+            json_owner(skipped.owner.as_ref(), f)?;
+            write!(
+                f,
+                ", \"symbol\": {}, \"old\": {}, \"offset\": {}, \
+                 \"reason\": {}}}",
+                Str(&skipped.symbol),
+                skipped.old_index,
+                skipped.offset,
+                Str(skipped.reason.as_str()),
+            )?;
+        }
+        if !file.skipped.is_empty() {
+            write!(f, "\n    ")?;
+        }
+        write!(
+            f,
+            "], \"bypassed\": {}, \"verified\": {}, \"unchecked\": {}",
+            file.bypassed, file.verified, file.unchecked,
+        )?;
+        for (key, uris) in [
+            ("plugins", &file.plugins),
+            ("missing_plugins", &file.missing_plugins),
+        ] {
+            write!(f, ", \"{key}\": [")?;
+            for (j, uri) in uris.iter().enumerate() {
+                if j > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", Str(uri))?;
+            }
+            write!(f, "]")?;
+        }
+        write!(f, "}}")?;
+    }
+    if !report.files.is_empty() {
+        write!(f, "\n  ")?;
+    }
+    writeln!(f, "]\n}}")
+}
+
+/// Displays a CSV field, quoting it if necessary.
+struct CsvField<'a>(&'a str);
+
+impl Display for CsvField<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.0.contains([',', '"', '\n', '\r']) {
+            return f.write_str(self.0);
+        }
+        write!(f, "\"{}\"", self.0.replace('"', "\"\""))
+    }
+}
+
+fn csv(report: &Report, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(
+        f,
+        "file,uri,owner_kind,owner_name,symbol,old,new,offset,owner_index,\
+         processor_name,processor_id",
+    )?;
+    for file in &report.files {
+        for change in &file.changes {
+            let owner = change.owner.as_ref();
+            writeln!(
+                f,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                CsvField(&file.path),
+                CsvField(&change.uri),
+                owner.map_or("", |o| o.kind.as_str()),
+                CsvField(owner.map_or("", |o| &o.name)),
+                CsvField(&change.symbol),
+                change.old_index,
+                change.new_index,
+                change.offset,
+                owner
+                    .and_then(|o| o.index)
+                    .map_or(String::new(), |i| { i.to_string() }),
+                CsvField(change.instance.name.as_deref().unwrap_or_default()),
+                CsvField(change.instance.id.as_deref().unwrap_or_default()),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Displays text escaped for HTML.
+struct Html<'a>(&'a str);
+
+impl Display for Html<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = self.0;
+        while let Some(i) = rest.find(['&', '<', '>', '"', '\'']) {
+            f.write_str(&rest[..i])?;
+            f.write_str(match rest.as_bytes()[i] {
+                b'&' => "&amp;",
+                b'<' => "&lt;",
+                b'>' => "&gt;",
+                b'"' => "&quot;",
+                _ => "&#39;",
+            })?;
+            rest = &rest[i + 1..];
+        }
+        f.write_str(rest)
+    }
+}
+
+const HTML_HEAD: &str = "\
+<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Parameter index changes</title>
+<style>
+body { font-family: sans-serif; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; }
+pre { margin: 0; }
+summary { font-weight: bold; margin: 1em 0 0.5em; cursor: pointer; }
+del { background: #fdd; }
+ins { background: #dfd; text-decoration: none; }
+</style>
+</head>
+<body>
+<h1>Parameter index changes</h1>
+";
+
+fn html_changes(
+    changes: &[&Change],
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    writeln!(f, "<table>")?;
+    writeln!(
+        f,
+        "<tr><th>Plugin</th><th>Processor</th><th>Port</th><th>Line</th>\
+         <th>Change</th></tr>",
+    )?;
+    for change in changes {
+        write!(
+            f,
+            "<tr><td>{}</td><td>{}</td><td>{}</td>",
+            Html(&change.uri),
+            Html(InstanceLabel(&change.instance).to_string().trim_start()),
+            Html(&change.symbol),
+        )?;
+        let (old, new) = (change.old_index, change.new_index);
+        let Some(context) = &change.context else {
+            writeln!(f, "<td></td><td>{old} &rarr; {new}</td></tr>")?;
+            continue;
+        };
+        writeln!(
+            f,
+            "<td>{}</td><td><pre>{}<del>{old}</del><ins>{new}</ins>{}</pre>\
+             </td></tr>",
+            context.line,
+            Html(&context.before),
+            Html(&context.after),
+        )?;
+    }
+    writeln!(f, "</table>")
+}
+
+fn html(report: &Report, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(HTML_HEAD)?;
+    for file in &report.files {
+        writeln!(f, "<h2>{}</h2>", Html(&file.path))?;
+        let n = file.changes.len();
+        let noun = if n == 1 {
+            "index"
+        } else {
+            "indices"
+        };
+        writeln!(
+            f,
+            "<p>{n} parameter {noun} changed, {} already correct, {} could \
+             not be checked</p>",
+            file.verified, file.unchecked,
+        )?;
+        let mut owners = BTreeMap::<_, Vec<_>>::new();
+        for change in &file.changes {
+            let owner =
+                change.owner.as_ref().map(|o| (o.kind, o.index, &o.name));
+            owners.entry(owner).or_default().push(change);
+        }
+        // Each route's changes are in a section that can be collapsed.
+        for (owner, changes) in &owners {
+            write!(f, "<details open>\n<summary>")?;
+            match owner {
+                Some((OwnerKind::Route, index, name)) => write!(
+                    f,
+                    "Route {}&ldquo;{}&rdquo;",
+                    RouteNumber(*index),
+                    Html(name),
+                )?,
+                Some((OwnerKind::Region, _, name)) => write!(
+                    f,
+                    "Region &ldquo;{}&rdquo; (region effects)",
+                    Html(name),
+                )?,
+                None => write!(f, "Other processors")?,
+            }
+            let n = changes.len();
+            let noun = if n == 1 {
+                "change"
+            } else {
+                "changes"
+            };
+            writeln!(f, " ({n} {noun})</summary>")?;
+            html_changes(changes, f)?;
+            writeln!(f, "</details>")?;
+        }
+        if file.skipped.is_empty() {
+            continue;
+        }
+        writeln!(
+            f,
+            "<details open>\n<summary>Skipped parameters ({})</summary>\n<ul>",
+            file.skipped.len(),
+        )?;
+        for skipped in &file.skipped {
+            writeln!(
+                f,
+                "<li>{}{}{}: {} (index {}): {}</li>",
+                Html(&skipped.uri),
+                Html(&InstanceLabel(&skipped.instance).to_string()),
+                Html(&OwnerLabel(skipped.owner.as_ref()).to_string()),
+                Html(&skipped.symbol),
+                skipped.old_index,
+                skipped.reason.as_str(),
+            )?;
+        }
+        writeln!(f, "</ul>\n</details>")?;
+    }
+    writeln!(f, "</body>\n</html>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANGE: &str = r#"{"uri": "urn:a", "symbol": "gain", "old": 3,
+        "new": 2, "offset": 40"#;
+
+    fn json_report(version: u32, change: &str, counts: &str) -> String {
+        format!(
+            r#"{{"version": {version}, "files": [{{"path": "S.ardour",
+            "changes": [{change}}}], "skipped": []{counts}}}]}}"#
+        )
+    }
+
+    #[test]
+    fn partial_version_filled_in() {
+        let report = Report::parse(&json_report(1, CHANGE, "")).unwrap();
+        let file = &report.files[0];
+        let change = &file.changes[0];
+        assert_eq!((change.end, change.synthetic), (41, false));
+        assert_eq!(change.instance, Instance::default());
+        assert_eq!((file.bypassed, file.verified, file.unchecked), (0, 0, 0));
+        assert_eq!(file.plugins, BTreeSet::from(["urn:a".to_owned()]));
+        assert!(file.missing_plugins.is_empty());
+    }
+
+    #[test]
+    fn current_version_complete() {
+        let change = format!(
+            r#"{CHANGE}, "processor": {{"name": null, "id": "7"}},
+            "end": 41, "synthetic": true"#
+        );
+        let counts = r#", "bypassed": 1, "verified": 2, "unchecked": 3,
+            "plugins": ["urn:a", "urn:b"], "missing_plugins": ["urn:b"]"#;
+        let report = Report::parse(&json_report(3, &change, counts)).unwrap();
+        let file = &report.files[0];
+        assert!(file.changes[0].synthetic);
+        assert_eq!(file.changes[0].instance.id.as_deref(), Some("7"));
+        assert_eq!((file.bypassed, file.verified, file.unchecked), (1, 2, 3));
+        assert_eq!(file.plugins.len(), 2);
+        assert_eq!(file.missing_plugins, BTreeSet::from(["urn:b".to_owned()]));
+        assert_eq!(
+            Report::parse(&report.render(Format::Json).to_string())
+                .unwrap()
+                .files[0]
+                .missing_plugins,
+            file.missing_plugins
+        );
+        for missing in ["end", "synthetic", "processor"] {
+            let partial = change.replace(&format!("\"{missing}\""), "\"x\"");
+            match Report::parse(&json_report(3, &partial, counts)) {
+                Err(Error::Missing(name)) => assert_eq!(name, missing),
+                r => panic!("{missing}: {r:?}"),
+            }
+        }
+        assert!(matches!(
+            Report::parse(&json_report(2, &change, "")),
+            Err(Error::Missing("bypassed")),
+        ));
+        let counts = counts.replace("\"plugins\"", "\"x\"");
+        assert!(matches!(
+            Report::parse(&json_report(3, &change, &counts)),
+            Err(Error::Missing("plugins")),
+        ));
+        assert!(matches!(
+            Report::parse(&json_report(4, &change, &counts)),
+            Err(Error::Version(4)),
+        ));
+    }
+}
@@ -17,11 +17,19 @@
  * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
  */
 
+//! Reading the processors and parameters of a session.
+
+use super::diag::{self, Kind, Warning};
+use super::lines::{LineIndex, Position};
 use roxmltree::Node;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Range;
 use std::str::FromStr;
 
+/// The prefix of the `automation-id` of an automated parameter, which is
+/// followed by its index.
+const AUTOMATION_PREFIX: &str = "parameter-";
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 struct ParameterIndex(u32);
 
@@ -36,95 +44,342 @@ impl FromStr for ParameterIndex {
     }
 }
 
+/// A parameter of a [`Processor`] that refers to a port by index.
 #[derive(Debug)]
 pub struct Parameter<'a> {
     pub symbol: &'a str,
     pub location: Range<usize>,
+    /// The position of the start of `location`.
+    pub position: Position,
     pub old_index: u32,
+    /// Whether the processor has multiple controllables with different
+    /// symbols for this index, making the symbol uncertain.
+    pub ambiguous: bool,
+}
+
+/// The kind of element a processor belongs to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum OwnerKind {
+    /// A track or bus.
+    Route,
+    /// A region-level effect.
+    Region,
+}
+
+impl OwnerKind {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"route" => Self::Route,
+            b"region" => Self::Region,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Route => "route",
+            Self::Region => "region",
+        }
+    }
+}
+
+/// The element a processor belongs to.
+#[derive(Clone, Copy, Debug)]
+pub struct Owner<'a> {
+    pub kind: OwnerKind,
+    pub name: &'a str,
+    /// For a route, its 1-based position among the session's routes, which
+    /// distinguishes routes with the same name.
+    pub index: Option<usize>,
+}
+
+impl<'a> Owner<'a> {
+    fn find(node: Node<'a, '_>) -> Option<Self> {
+        node.ancestors().find_map(|a| {
+            let kind = match a.tag_name().name() {
+                "Route" => OwnerKind::Route,
+                "Region" => OwnerKind::Region,
+                _ => return None,
+            };
+            // `prev_siblings` includes the node itself.
+            let index = (kind == OwnerKind::Route).then(|| {
+                a.prev_siblings().filter(|s| s.has_tag_name("Route")).count()
+            });
+            Some(Self {
+                kind,
+                name: a.attribute("name").unwrap_or_default(),
+                index,
+            })
+        })
+    }
+}
+
+/// The plugin standard of a processor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PluginKind {
+    Lv2,
+    Ladspa,
+}
+
+impl PluginKind {
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Some(match name {
+            b"lv2" => Self::Lv2,
+            b"ladspa" => Self::Ladspa,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lv2 => "lv2",
+            Self::Ladspa => "ladspa",
+        }
+    }
+
+    /// The controllable attribute that names a parameter's port: LADSPA
+    /// ports have no symbols, so Ardour saves their names instead.
+    fn symbol_attribute(self) -> &'static str {
+        match self {
+            Self::Lv2 => "symbol",
+            Self::Ladspa => "name",
+        }
+    }
+}
+
+/// Whether the attribute value `s` is false, as read by Ardour (with
+/// `PBD::string_to_bool`).
+fn is_false(s: &str) -> bool {
+    ["0", "n", "no", "false"].iter().any(|f| s.eq_ignore_ascii_case(f))
 }
 
+/// An LV2 or LADSPA plugin instance in a session.
 #[derive(Debug)]
 pub struct Processor<'a> {
+    id: Option<&'a str>,
+    offset: usize,
+    position: Position,
+    kind: PluginKind,
     uri: &'a str,
+    name: Option<&'a str>,
+    active: bool,
+    owner: Option<Owner<'a>>,
     symbols: HashMap<ParameterIndex, &'a str>,
-    parameters: Vec<(ParameterIndex, Range<usize>)>,
+    ambiguous: HashSet<ParameterIndex>,
+    parameters: Vec<(ParameterIndex, Range<usize>, Position)>,
 }
 
 impl<'a> Processor<'a> {
+    /// The processor's `id` attribute.
+    pub fn id(&self) -> Option<&'a str> {
+        self.id
+    }
+
+    /// The byte offset of the processor's element in the session.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The position of the processor's element in the session.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub fn kind(&self) -> PluginKind {
+        self.kind
+    }
+
+    /// The plugin's URI, or for a LADSPA plugin, its numeric unique ID.
     pub fn uri(&self) -> &'a str {
         self.uri
     }
 
+    /// The processor's name as shown in Ardour, which is the plugin's name
+    /// unless the user renamed it.
+    pub fn name(&self) -> Option<&'a str> {
+        self.name
+    }
+
+    /// Whether the processor is enabled (not bypassed).
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// The route or region containing this processor.
+    pub fn owner(&self) -> Option<Owner<'a>> {
+        self.owner
+    }
+
+    /// Creates a warning about this processor, which notes its plugin, its
+    /// name and id, and the route containing it.
+    pub fn warning(&self, kind: Kind, message: impl Into<String>) -> Warning {
+        let mut warning = Warning::new(kind, message).uri(self.uri);
+        if let Some(name) = self.name {
+            warning = warning.processor_name(name);
+        }
+        if let Some(id) = self.id {
+            warning = warning.processor_id(id);
+        }
+        match self.owner {
+            Some(owner) if owner.kind == OwnerKind::Route => {
+                warning.route(owner.name)
+            }
+            _ => warning,
+        }
+    }
+
     pub fn parameters(&self) -> impl Iterator<Item = Parameter<'a>> + '_ {
-        self.parameters.iter().filter_map(|(i, range)| {
+        self.parameters.iter().filter_map(|(i, range, position)| {
             self.symbols.get(i).map(|&s| Parameter {
                 symbol: s,
                 location: range.clone(),
+                position: *position,
                 old_index: i.0,
+                ambiguous: self.ambiguous.contains(i),
             })
         })
     }
 
-    fn on_automation_list(&mut self, node: Node<'a, '_>) {
+    /// The number of parameter references whose index has no known symbol,
+    /// and so can't be checked.
+    pub fn unnamed(&self) -> usize {
+        self.parameters
+            .iter()
+            .filter(|(i, _, _)| !self.symbols.contains_key(i))
+            .count()
+    }
+
+    fn on_automation_list(
+        &mut self,
+        node: Node<'a, '_>,
+        lines: &LineIndex<'_>,
+    ) {
         let Some(attr) = node.attribute_node("automation-id") else {
             return;
         };
-        const PREFIX: &str = "parameter-";
-        let Some(index) = attr.value().strip_prefix(PREFIX) else {
+        let Some(index) = attr.value().strip_prefix(AUTOMATION_PREFIX) else {
             return;
         };
+        let mut range = attr.range_value();
         let Ok(parsed_index) = index.parse() else {
-            eprintln!("warning: could not parse parameter index: {index}");
+            diag::warn(
+                self.warning(
+                    Kind::UnparsableIndex,
+                    format!("could not parse parameter index: {index}"),
+                )
+                .at(range.start, lines.position(range.start)),
+            );
             return;
         };
-        let mut range = attr.range_value();
-        range.start += PREFIX.len();
-        self.parameters.push((parsed_index, range));
+        range.start += AUTOMATION_PREFIX.len();
+        let position = lines.position(range.start);
+        self.parameters.push((parsed_index, range, position));
     }
 
-    fn on_controllable(&mut self, node: Node<'a, '_>) {
+    fn on_controllable(&mut self, node: Node<'a, '_>, lines: &LineIndex<'_>) {
         let Some(index_attr) = node.attribute_node("parameter") else {
             return;
         };
         let index = index_attr.value();
+        let range = index_attr.range_value();
+        let position = lines.position(range.start);
         let Ok(parsed_index) = index.parse() else {
-            eprintln!("warning: could not parse parameter index: {index}");
+            diag::warn(
+                self.warning(
+                    Kind::UnparsableIndex,
+                    format!("could not parse parameter index: {index}"),
+                )
+                .at(range.start, position),
+            );
             return;
         };
-        let Some(symbol) = node.attribute("symbol") else {
-            eprintln!(
-                "warning: missing `symbol` in controllable at {}",
-                node.range().start,
+        let attr = self.kind.symbol_attribute();
+        let Some(symbol) = node.attribute(attr) else {
+            let start = node.range().start;
+            let position = lines.position(start);
+            diag::warn(
+                self.warning(
+                    Kind::MissingSymbol,
+                    format!("missing `{attr}` in controllable at {position}"),
+                )
+                .at(start, position),
             );
             return;
         };
-        self.symbols.insert(parsed_index, symbol);
-        self.parameters.push((parsed_index, index_attr.range_value()));
+        if let Some(old) = self.symbols.insert(parsed_index, symbol) {
+            if old != symbol {
+                self.ambiguous.insert(parsed_index);
+            }
+        }
+        self.parameters.push((parsed_index, range, position));
     }
 
-    pub fn parse(node: Node<'a, '_>) -> Option<Self> {
-        if node.attribute("type") != Some("lv2") {
-            return None;
-        }
+    /// Records the index of a port value saved in a LADSPA plugin's state.
+    fn on_port(&mut self, node: Node<'a, '_>, lines: &LineIndex<'_>) {
+        let Some(index_attr) = node.attribute_node("number") else {
+            return;
+        };
+        let index = index_attr.value();
+        let range = index_attr.range_value();
+        let position = lines.position(range.start);
+        let Ok(parsed_index) = index.parse() else {
+            diag::warn(
+                self.warning(
+                    Kind::UnparsableIndex,
+                    format!("could not parse port number: {index}"),
+                )
+                .at(range.start, position),
+            );
+            return;
+        };
+        self.parameters.push((parsed_index, range, position));
+    }
+
+    /// Parses the processor `node`. `lines` is used to describe the
+    /// locations of problems in warnings.
+    pub fn parse(node: Node<'a, '_>, lines: &LineIndex<'_>) -> Option<Self> {
+        let kind = node
+            .attribute("type")
+            .and_then(|t| PluginKind::from_name(t.as_bytes()))?;
+        let offset = node.range().start;
+        let position = lines.position(offset);
         let Some(uri) = node.attribute("unique-id") else {
-            eprintln!(
-                "warning: missing uri for processor at {}",
-                node.range().start,
+            diag::warn(
+                Warning::new(
+                    Kind::MissingUri,
+                    format!("missing uri for processor at {position}"),
+                )
+                .at(offset, position),
             );
             return None;
         };
         let mut this = Self {
+            id: node.attribute("id"),
+            offset,
+            position,
+            kind,
             uri,
+            name: node.attribute("name"),
+            active: !node.attribute("active").is_some_and(is_false),
+            owner: Owner::find(node),
             symbols: HashMap::new(),
+            ambiguous: HashSet::new(),
             parameters: Vec::new(),
         };
         let mut next = node.first_child();
         while let Some(descendant) = next {
             next = None;
             if descendant.has_tag_name("AutomationList") {
-                this.on_automation_list(descendant);
+                this.on_automation_list(descendant, lines);
             } else if descendant.has_tag_name("Controllable") {
-                this.on_controllable(descendant);
+                this.on_controllable(descendant, lines);
+            } else if kind == PluginKind::Ladspa
+                && descendant.has_tag_name("Port")
+                && descendant
+                    .parent()
+                    .is_some_and(|p| p.has_tag_name("ladspa"))
+            {
+                this.on_port(descendant, lines);
             } else {
                 next = descendant.first_child();
             }
@@ -139,3 +394,61 @@ impl<'a> Processor<'a> {
         Some(this)
     }
 }
+
+/// Returns the URIs of the plugins used by the session `doc`, without
+/// reading their parameters or warning about malformed processors.
+pub fn plugin_uris<'a>(
+    doc: &'a roxmltree::Document<'_>,
+) -> impl Iterator<Item = &'a str> {
+    doc.descendants()
+        .filter(|n| n.has_tag_name("Processor"))
+        .filter(|n| n.attribute("type") == Some("lv2"))
+        .filter_map(|n| n.attribute("unique-id"))
+}
+
+/// Returns the byte offset of every parameter index in the session `doc`
+/// (in controllables, automation lists, and LADSPA port values), without
+/// reading the processors or warning about malformed ones.
+pub fn index_offsets(doc: &roxmltree::Document<'_>) -> HashSet<usize> {
+    let mut offsets = HashSet::new();
+    for node in doc.descendants().filter(Node::is_element) {
+        let (attr, prefix) = match node.tag_name().name() {
+            "Controllable" => ("parameter", ""),
+            "AutomationList" => ("automation-id", AUTOMATION_PREFIX),
+            "Port" => ("number", ""),
+            _ => continue,
+        };
+        if let Some(attr) = node.attribute_node(attr) {
+            if attr.value().starts_with(prefix) {
+                offsets.insert(attr.range_value().start + prefix.len());
+            }
+        }
+    }
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn active() {
+        let active = |value: Option<&str>| {
+            let attr = value.map(|v| format!(" active=\"{v}\""));
+            let xml = format!(
+                "<Processor type=\"lv2\" unique-id=\"urn:a\"{}/>",
+                attr.unwrap_or_default(),
+            );
+            let doc = roxmltree::Document::parse(&xml).unwrap();
+            let lines = LineIndex::new(&xml);
+            Processor::parse(doc.root_element(), &lines).unwrap().active()
+        };
+        assert!(active(None));
+        for value in ["1", "yes", "y", "true", "TRUE"] {
+            assert!(active(Some(value)), "{value}");
+        }
+        for value in ["0", "no", "n", "false", "No", "FALSE"] {
+            assert!(!active(Some(value)), "{value}");
+        }
+    }
+}
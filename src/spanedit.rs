@@ -0,0 +1,249 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Editing of a document by replacing byte ranges of its original text.
+//!
+//! Edits are recorded as splices over ranges of the unmodified text, such as
+//! those returned by [`roxmltree::Node::range`] and
+//! [`roxmltree::Attribute::range_value`], so they can be collected in any
+//! order while the parsed document is still borrowed. [`Splices::apply`]
+//! then orders them, and the result is rendered by copying the text between
+//! splices, so the rest of the document is preserved byte for byte.
+
+use std::fmt::{self, Display};
+use std::io::{self, Write};
+use std::ops::Range;
+
+macro_rules! debug_eprintln {
+    ($($tt:tt)*) => {
+        if cfg!(debug_assertions) {
+            eprintln!($($tt)*);
+        }
+    };
+}
+
+/// The text a range is replaced with.
+#[derive(Debug)]
+pub enum Value {
+    /// An integer, which is formatted when rendered.
+    Integer(u32),
+    Text(String),
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Self {
+        Self::Integer(n)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Self::Text(s)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(n) => write!(f, "{n}"),
+            Self::Text(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Splice {
+    location: Range<usize>,
+    value: Value,
+}
+
+/// A set of replacements to make in a document.
+#[derive(Debug, Default)]
+pub struct Splices(Vec<Splice>);
+
+impl Splices {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces `location` in the original text with `value`. The range
+    /// must not overlap any other replacement; empty ranges insert text.
+    pub fn replace(
+        &mut self,
+        location: Range<usize>,
+        value: impl Into<Value>,
+    ) {
+        self.0.push(Splice {
+            location,
+            value: value.into(),
+        });
+    }
+
+    /// Applies the replacements to `text`, the original document.
+    ///
+    /// A replacement that overlaps an earlier one is dropped, as is one that
+    /// doesn't lie within `text` on character boundaries; this indicates a
+    /// bug in whatever recorded it, so it's reported in debug builds.
+    /// Insertions at the same position keep the order they were made in.
+    pub fn apply(self, text: &str) -> Spliced<'_> {
+        let mut splices = self.0;
+        splices.sort_by_key(|s| s.location.start);
+        let mut pos = 0;
+        splices.retain(|s| {
+            let Range {
+                start,
+                end,
+            } = s.location;
+            let valid = start >= pos
+                && start <= end
+                && text.is_char_boundary(start)
+                && text.is_char_boundary(end);
+            if !valid {
+                debug_eprintln!(
+                    "warning: overlapping/out-of-order replacement: \
+                     {start}..{end} -> {} (currently at {pos})",
+                    s.value,
+                );
+                return false;
+            }
+            pos = end;
+            true
+        });
+        Spliced {
+            text,
+            splices,
+        }
+    }
+}
+
+/// A document with [`Splices`] applied.
+#[derive(Debug)]
+pub struct Spliced<'a> {
+    text: &'a str,
+    /// Ordered and non-overlapping.
+    splices: Vec<Splice>,
+}
+
+impl Spliced<'_> {
+    /// Returns the position in the rendered document of `offset` in the
+    /// original text, which must not lie inside a replaced range. If a
+    /// range starts at `offset`, this is where its replacement starts.
+    pub fn map_offset(&self, offset: usize) -> usize {
+        self.splices
+            .iter()
+            .take_while(|s| s.location.start < offset)
+            .fold(offset, |pos, s| {
+                pos + s.value.to_string().len() - s.location.len()
+            })
+    }
+
+    /// Renders the document piece by piece, passing each piece to `write`.
+    fn render<E, F>(&self, mut write: F) -> Result<(), E>
+    where
+        F: FnMut(fmt::Arguments<'_>) -> Result<(), E>,
+    {
+        let mut pos = 0;
+        for s in &self.splices {
+            let before = &self.text[pos..s.location.start];
+            write(format_args!("{before}{}", s.value))?;
+            pos = s.location.end;
+        }
+        write(format_args!("{}", &self.text[pos..]))
+    }
+
+    /// Writes the document to `writer` without rendering it in memory
+    /// first.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        self.render(|args| writer.write_fmt(args))?;
+        writer.flush()
+    }
+}
+
+impl Display for Spliced<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(|args| f.write_fmt(args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(text: &str, splices: Splices) -> String {
+        splices.apply(text).to_string()
+    }
+
+    #[test]
+    fn order() {
+        let mut splices = Splices::new();
+        splices.replace(11..12, 30);
+        splices.replace(0..1, "b".to_owned());
+        splices.replace(4..5, 20);
+        assert_eq!(apply("a = 1, c = 3", splices), "b = 20, c = 30");
+    }
+
+    #[test]
+    fn overlap() {
+        let mut splices = Splices::new();
+        splices.replace(2..6, "X".to_owned());
+        splices.replace(4..8, "Y".to_owned());
+        splices.replace(0..3, "Z".to_owned());
+        // Sorted by start, 0..3 comes first, so 2..6 overlaps it and is
+        // dropped, and then 4..8 no longer overlaps anything.
+        assert_eq!(apply("0123456789", splices), "Z3Y89");
+    }
+
+    #[test]
+    fn same_position_inserts() {
+        let mut splices = Splices::new();
+        splices.replace(1..1, "x".to_owned());
+        splices.replace(1..1, "y".to_owned());
+        splices.replace(1..2, "B".to_owned());
+        splices.replace(1..1, "z".to_owned());
+        // The insertion made after the replacement at the same position
+        // overlaps it.
+        assert_eq!(apply("abc", splices), "axyBc");
+    }
+
+    #[test]
+    fn char_boundaries() {
+        let text = "é=1";
+        let mut splices = Splices::new();
+        splices.replace(1..2, "e".to_owned());
+        splices.replace(3..4, 2);
+        splices.replace(4..6, 3);
+        assert_eq!(apply(text, splices), "é=2");
+        let mut splices = Splices::new();
+        splices.replace(0..2, "e".to_owned());
+        assert_eq!(apply(text, splices), "e=1");
+    }
+
+    #[test]
+    fn map_offset() {
+        let mut splices = Splices::new();
+        splices.replace(0..1, 100);
+        splices.replace(4..5, "".to_owned());
+        let spliced = splices.apply("1 = 2 + 3");
+        assert_eq!(spliced.to_string(), "100 =  + 3");
+        assert_eq!(spliced.map_offset(0), 0);
+        assert_eq!(spliced.map_offset(2), 4);
+        assert_eq!(spliced.map_offset(4), 6);
+        assert_eq!(spliced.map_offset(8), 9);
+    }
+}
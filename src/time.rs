@@ -0,0 +1,103 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt::{self, Display};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A UTC time, displayed in ISO 8601 format (e.g., `2024-05-01T12:34:56Z`).
+#[derive(Clone, Copy, Debug)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch.
+    secs: i64,
+}
+
+impl Timestamp {
+    /// Returns the current time, or the time given in seconds since the
+    /// epoch by `SOURCE_DATE_EPOCH` if it is set, so that output containing
+    /// timestamps can be reproduced.
+    pub fn now() -> Self {
+        let epoch = std::env::var("SOURCE_DATE_EPOCH").ok();
+        if let Some(secs) = epoch.and_then(|s| s.trim().parse().ok()) {
+            return Self {
+                secs,
+            };
+        }
+        Self::from(SystemTime::now())
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        Self {
+            secs,
+        }
+    }
+}
+
+impl Timestamp {
+    /// Returns the year, month, day, hour, minute, and second.
+    fn fields(self) -> [i64; 6] {
+        let days = self.secs.div_euclid(86400);
+        let secs = self.secs.rem_euclid(86400);
+        // Converts days since the epoch to a civil date; see
+        // <https://howardhinnant.github.io/date_algorithms.html>.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 {
+            mp + 3
+        } else {
+            mp - 9
+        };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        [year, month, day, secs / 3600, secs / 60 % 60, secs % 60]
+    }
+
+    /// Displays the time in ISO 8601 basic format (e.g.,
+    /// `20240501T123456Z`), which can be used in file names.
+    pub fn basic(self) -> Basic {
+        Basic(self)
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [year, month, day, hour, min, sec] = self.fields();
+        write!(f, "{year:04}-{month:02}-{day:02}")?;
+        write!(f, "T{hour:02}:{min:02}:{sec:02}Z")
+    }
+}
+
+/// Returned by [`Timestamp::basic`].
+pub struct Basic(Timestamp);
+
+impl Display for Basic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [year, month, day, hour, min, sec] = self.0.fields();
+        write!(f, "{year:04}{month:02}{day:02}T{hour:02}{min:02}{sec:02}Z")
+    }
+}
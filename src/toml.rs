@@ -0,0 +1,487 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A parser for the subset of TOML used by the configuration file.
+//!
+//! Tables, dotted keys, inline tables, arrays, strings, integers, floats,
+//! and booleans are supported. Arrays of tables, multi-line strings, and
+//! dates are not. Documents are parsed into a [`Value`], with tables as
+//! objects.
+
+use super::json::Value;
+use std::fmt::{self, Display};
+
+#[derive(Debug)]
+pub struct Error {
+    pub line: usize,
+    pub msg: &'static str,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}", self.msg, self.line)
+    }
+}
+
+type Table = Vec<(String, Value)>;
+
+/// Returns the table at `path` in `table`, creating it if needed.
+fn subtable<'a>(
+    mut table: &'a mut Table,
+    path: &[String],
+) -> Result<&'a mut Table, &'static str> {
+    for key in path {
+        let i = match table.iter().position(|(k, _)| k == key) {
+            Some(i) => i,
+            None => {
+                table.push((key.clone(), Value::Object(Vec::new())));
+                table.len() - 1
+            }
+        };
+        table = match &mut table[i].1 {
+            Value::Object(members) => members,
+            _ => return Err("key is already defined"),
+        };
+    }
+    Ok(table)
+}
+
+/// Adds `value` to `table` under the dotted key `key`.
+fn insert(
+    table: &mut Table,
+    key: &[String],
+    value: Value,
+) -> Result<(), &'static str> {
+    let (last, path) = key.split_last().expect("empty key");
+    let table = subtable(table, path)?;
+    if table.iter().any(|(k, _)| k == last) {
+        return Err("duplicate key");
+    }
+    table.push((last.clone(), value));
+    Ok(())
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, msg: &'static str) -> Result<T, Error> {
+        Err(Error {
+            line: self.input[..self.pos].matches('\n').count() + 1,
+            msg,
+        })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while let Some(b' ' | b'\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some(b'#') {
+            let rest = &self.input[self.pos..];
+            self.pos += rest.find('\n').unwrap_or(rest.len());
+        }
+    }
+
+    /// Skips whitespace, newlines, and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.peek() {
+                Some(b'\n' | b'\r') => self.pos += 1,
+                _ => return,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), Error> {
+        self.skip_spaces();
+        self.skip_comment();
+        if self.input[self.pos..].starts_with("\r\n") {
+            self.pos += 1;
+        }
+        match self.peek() {
+            None => Ok(()),
+            Some(b'\n') => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(_) => self.error("expected end of line"),
+        }
+    }
+
+    fn expect(&mut self, byte: u8, msg: &'static str) -> Result<(), Error> {
+        self.skip_spaces();
+        if self.peek() != Some(byte) {
+            return self.error(msg);
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, lit: &str, value: Value) -> Result<Value, Error> {
+        if !self.input[self.pos..].starts_with(lit) {
+            return self.error("invalid literal");
+        }
+        self.pos += lit.len();
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let start = self.pos;
+        while let Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E' | b'_') =
+            self.peek()
+        {
+            self.pos += 1;
+        }
+        let s = &self.input[start..self.pos];
+        // Underscores may only separate digits.
+        let bytes = s.as_bytes();
+        let separated = bytes.iter().enumerate().all(|(i, &b)| {
+            b != b'_'
+                || (i > 0
+                    && bytes[i - 1].is_ascii_digit()
+                    && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        });
+        match s.replace('_', "").parse() {
+            Ok(n) if separated => Ok(Value::Number(n)),
+            _ => {
+                self.pos = start;
+                self.error("invalid number")
+            }
+        }
+    }
+
+    fn hex(&mut self, len: usize) -> Result<char, Error> {
+        let Some(digits) = self.input.get(self.pos..self.pos + len) else {
+            return self.error("truncated escape");
+        };
+        let c = u32::from_str_radix(digits, 16).ok().and_then(char::from_u32);
+        let Some(c) = c else {
+            return self.error("invalid escape");
+        };
+        self.pos += len;
+        Ok(c)
+    }
+
+    fn basic_string(&mut self) -> Result<String, Error> {
+        if self.input[self.pos..].starts_with("\"\"\"") {
+            return self.error("multi-line strings are not supported");
+        }
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let rest = &self.input[self.pos..];
+            let Some(i) = rest.find(['"', '\\', '\n']) else {
+                return self.error("unterminated string");
+            };
+            s.push_str(&rest[..i]);
+            self.pos += i;
+            match rest.as_bytes()[i] {
+                b'"' => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                b'\n' => return self.error("unterminated string"),
+                _ => self.pos += 1,
+            }
+            let Some(escape) = self.peek() else {
+                return self.error("unterminated string");
+            };
+            self.pos += 1;
+            s.push(match escape {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'b' => '\x08',
+                b'f' => '\x0c',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => self.hex(4)?,
+                b'U' => self.hex(8)?,
+                _ => return self.error("invalid escape"),
+            });
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, Error> {
+        if self.input[self.pos..].starts_with("'''") {
+            return self.error("multi-line strings are not supported");
+        }
+        self.pos += 1;
+        let rest = &self.input[self.pos..];
+        match rest.find(['\'', '\n']) {
+            Some(i) if rest.as_bytes()[i] == b'\'' => {
+                self.pos += i + 1;
+                Ok(rest[..i].to_owned())
+            }
+            _ => self.error("unterminated string"),
+        }
+    }
+
+    fn simple_key(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Some(b'"') => self.basic_string(),
+            Some(b'\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while let Some(
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-',
+                ) = self.peek()
+                {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    return self.error("expected key");
+                }
+                Ok(self.input[start..self.pos].to_owned())
+            }
+        }
+    }
+
+    /// Parses a key, which may be dotted.
+    fn key(&mut self) -> Result<Vec<String>, Error> {
+        let mut key = Vec::new();
+        loop {
+            self.skip_spaces();
+            key.push(self.simple_key()?);
+            self.skip_spaces();
+            if self.peek() != Some(b'.') {
+                return Ok(key);
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Parses `key = value`, adding it to `table`.
+    fn key_value(&mut self, table: &mut Table) -> Result<(), Error> {
+        let key = self.key()?;
+        self.expect(b'=', "expected `=`")?;
+        let value = self.value()?;
+        insert(table, &key, value).or_else(|msg| self.error(msg))
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        self.skip_spaces();
+        match self.peek() {
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => self.basic_string().map(Value::String),
+            Some(b'\'') => self.literal_string().map(Value::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_blank();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(items));
+                        }
+                        _ => return self.error("expected `,` or `]`"),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_spaces();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                loop {
+                    self.key_value(&mut members)?;
+                    self.skip_spaces();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(members));
+                        }
+                        _ => return self.error("expected `,` or `}`"),
+                    }
+                }
+            }
+            Some(b'+' | b'-' | b'0'..=b'9') => self.number(),
+            Some(b'\n' | b'\r') | None => self.error("expected value"),
+            Some(_) => self.error("unexpected character"),
+        }
+    }
+
+    fn document(&mut self) -> Result<Value, Error> {
+        let mut root = Vec::new();
+        // The table that key/value pairs are added to.
+        let mut current = Vec::new();
+        // The tables defined with `[table]` headers.
+        let mut defined = Vec::new();
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => return Ok(Value::Object(root)),
+                Some(b'[') => {
+                    self.pos += 1;
+                    if self.peek() == Some(b'[') {
+                        return self
+                            .error("arrays of tables are not supported");
+                    }
+                    let path = self.key()?;
+                    self.expect(b']', "expected `]`")?;
+                    if defined.contains(&path) {
+                        return self.error("table defined twice");
+                    }
+                    subtable(&mut root, &path)
+                        .or_else(|msg| self.error(msg))?;
+                    defined.push(path.clone());
+                    current = path;
+                }
+                Some(_) => {
+                    let table = subtable(&mut root, &current)
+                        .or_else(|msg| self.error(msg))?;
+                    self.key_value(table)?;
+                }
+            }
+            self.end_of_line()?;
+        }
+    }
+}
+
+/// Parses the TOML document `input`, returning its root table as an object.
+pub fn parse(input: &str) -> Result<Value, Error> {
+    Parser {
+        input,
+        pos: 0,
+    }
+    .document()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(members: &[(&str, Value)]) -> Value {
+        Value::Object(
+            members
+                .iter()
+                .map(|(k, v)| ((*k).to_owned(), v.clone()))
+                .collect(),
+        )
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_owned())
+    }
+
+    #[test]
+    fn tables() {
+        let doc = "\
+# comment
+top = 1
+
+[pins]
+\"http://example.org/plug\" = { backend = \"manifest\", file = 'a.json' }
+
+[defaults] # trailing comment
+lv2-path = \"/opt/lv2\"
+deny = [
+  \"missing-plugin\", # comment
+  \"missing-port\",
+]
+verify = true
+ratio = 0.5
+a.b = -1_000
+";
+        assert_eq!(
+            parse(doc).unwrap(),
+            object(&[
+                ("top", Value::Number(1.0)),
+                (
+                    "pins",
+                    object(&[(
+                        "http://example.org/plug",
+                        object(&[
+                            ("backend", string("manifest")),
+                            ("file", string("a.json")),
+                        ]),
+                    )]),
+                ),
+                (
+                    "defaults",
+                    object(&[
+                        ("lv2-path", string("/opt/lv2")),
+                        (
+                            "deny",
+                            Value::Array(vec![
+                                string("missing-plugin"),
+                                string("missing-port"),
+                            ]),
+                        ),
+                        ("verify", Value::Bool(true)),
+                        ("ratio", Value::Number(0.5)),
+                        ("a", object(&[("b", Value::Number(-1000.0))])),
+                    ]),
+                ),
+            ]),
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let doc = r#"a = "\"\\\t\u00fc\U0001F39B"
+b = 'C:\path'
+"#;
+        assert_eq!(
+            parse(doc).unwrap(),
+            object(&[
+                ("a", string("\"\\\t\u{fc}\u{1f39b}")),
+                ("b", string("C:\\path")),
+            ]),
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let line = |doc| parse(doc).unwrap_err().line;
+        assert_eq!(line("a = 1\na = 2\n"), 2);
+        assert_eq!(line("[t]\n[t]\n"), 2);
+        assert_eq!(line("a = 1\n\nb = \"x\n"), 3);
+        assert_eq!(line("a = 1 b = 2"), 1);
+        assert_eq!(line("a = 1__0"), 1);
+        assert_eq!(line("a = \"\"\"x\"\"\""), 1);
+        assert_eq!(line("[[t]]"), 1);
+        assert_eq!(line("a = 1\n[a]\n"), 2);
+        assert_eq!(line("a =\n"), 1);
+    }
+}
@@ -0,0 +1,793 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Reading and repacking zip archives of sessions.
+//!
+//! Only the entries being replaced are decompressed; every other entry
+//! (such as the session's audio files) is copied to the new archive as-is.
+//! Zip64 archives are supported, but split archives aren't.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const END: u32 = 0x06054b50;
+const ZIP64_END: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+/// The ID of the extra field holding 64-bit sizes and offsets.
+const ZIP64_EXTRA: u16 = 0x0001;
+/// The value of a 32-bit field whose actual value is in a zip64 field.
+const MAX32: u64 = 0xffff_ffff;
+
+const FLAG_ENCRYPTED: u16 = 1 << 0;
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+const FLAG_UTF8: u16 = 1 << 11;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+/// The version of the zip specification needed for deflate.
+const VERSION_DEFLATE: u16 = 20;
+/// The version of the zip specification needed for zip64.
+const VERSION_ZIP64: u16 = 45;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Format(&'static str),
+    Unsupported(&'static str),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Format(s) => write!(f, "invalid zip archive: {s}"),
+            Self::Unsupported(s) => write!(f, "unsupported zip archive: {s}"),
+        }
+    }
+}
+
+/// Reads little-endian fields from a byte slice.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < n {
+            return Err(Error::Format("truncated record"));
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// Writes little-endian fields to a byte vector.
+trait PutFields {
+    fn put16(&mut self, value: u16);
+    fn put32(&mut self, value: u32);
+    fn put64(&mut self, value: u64);
+
+    /// Writes a 32-bit field, or [`MAX32`] if `value` needs a zip64 field.
+    fn put32_or_max(&mut self, value: u64) {
+        self.put32(value.min(MAX32) as u32);
+    }
+}
+
+impl PutFields for Vec<u8> {
+    fn put16(&mut self, value: u16) {
+        self.extend(value.to_le_bytes());
+    }
+
+    fn put32(&mut self, value: u32) {
+        self.extend(value.to_le_bytes());
+    }
+
+    fn put64(&mut self, value: u64) {
+        self.extend(value.to_le_bytes());
+    }
+}
+
+/// Returns the extra fields in `extra` other than the zip64 field, which is
+/// regenerated when an archive is written.
+fn without_zip64(extra: &[u8]) -> Vec<u8> {
+    let mut kept = Vec::new();
+    let mut fields = Fields(extra);
+    while let (Ok(id), Ok(len)) = (fields.u16(), fields.u16()) {
+        let Ok(data) = fields.take(len.into()) else {
+            break;
+        };
+        if id != ZIP64_EXTRA {
+            kept.put16(id);
+            kept.put16(len);
+            kept.extend(data);
+        }
+    }
+    kept
+}
+
+/// A file in an archive, as described by the central directory.
+#[derive(Debug)]
+pub struct Entry {
+    name: Vec<u8>,
+    made_by: u16,
+    needed: u16,
+    flags: u16,
+    method: u16,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    internal_attributes: u16,
+    external_attributes: u32,
+    offset: u64,
+    extra: Vec<u8>,
+    comment: Vec<u8>,
+}
+
+impl Entry {
+    /// The entry's path within the archive.
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.name).into_owned()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with(b"/")
+    }
+
+    fn parse(fields: &mut Fields<'_>) -> Result<Self, Error> {
+        if fields.u32()? != CENTRAL_HEADER {
+            return Err(Error::Format("bad central directory entry"));
+        }
+        let made_by = fields.u16()?;
+        let needed = fields.u16()?;
+        let flags = fields.u16()?;
+        let method = fields.u16()?;
+        let time = fields.u16()?;
+        let date = fields.u16()?;
+        let crc = fields.u32()?;
+        let mut compressed_size = fields.u32()?.into();
+        let mut size = fields.u32()?.into();
+        let name_len = fields.u16()?;
+        let extra_len = fields.u16()?;
+        let comment_len = fields.u16()?;
+        let _disk = fields.u16()?;
+        let internal_attributes = fields.u16()?;
+        let external_attributes = fields.u32()?;
+        let mut offset = fields.u32()?.into();
+        let name = fields.take(name_len.into())?.to_vec();
+        let extra = fields.take(extra_len.into())?;
+        let comment = fields.take(comment_len.into())?.to_vec();
+        let mut extra_fields = Fields(extra);
+        while let (Ok(id), Ok(len)) = (extra_fields.u16(), extra_fields.u16())
+        {
+            let mut data = Fields(extra_fields.take(len.into())?);
+            if id != ZIP64_EXTRA {
+                continue;
+            }
+            for value in [&mut size, &mut compressed_size, &mut offset] {
+                if *value == MAX32 {
+                    *value = data.u64()?;
+                }
+            }
+        }
+        Ok(Self {
+            name,
+            made_by,
+            needed,
+            flags,
+            method,
+            time,
+            date,
+            crc,
+            compressed_size,
+            size,
+            internal_attributes,
+            external_attributes,
+            offset,
+            extra: without_zip64(extra),
+            comment,
+        })
+    }
+}
+
+/// Counts the bytes written to a writer, to find the offsets of records.
+struct Counted<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The contents of a new entry, and its details for the headers.
+struct Replacement {
+    data: Vec<u8>,
+    crc: u32,
+    size: u64,
+}
+
+impl Replacement {
+    fn new(contents: &[u8]) -> io::Result<Self> {
+        let mut crc = flate2::Crc::new();
+        crc.update(contents);
+        let mut encoder = flate2::write::DeflateEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(contents)?;
+        Ok(Self {
+            data: encoder.finish()?,
+            crc: crc.sum(),
+            size: contents.len() as u64,
+        })
+    }
+}
+
+/// A zip archive opened for reading.
+pub struct Archive {
+    file: File,
+    entries: Vec<Entry>,
+    comment: Vec<u8>,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut file = File::open(path)?;
+        let len = file.seek(SeekFrom::End(0))?;
+        // The end record is 22 bytes, followed by a comment of up to 65535.
+        let tail_len = len.min(22 + 0xffff);
+        let mut tail = vec![0; tail_len as usize];
+        file.seek(SeekFrom::Start(len - tail_len))?;
+        file.read_exact(&mut tail)?;
+        let end = (0..tail.len().saturating_sub(21))
+            .rev()
+            .find(|&i| {
+                tail[i..].starts_with(&END.to_le_bytes())
+                    && 22
+                        + usize::from(u16::from_le_bytes([
+                            tail[i + 20],
+                            tail[i + 21],
+                        ]))
+                        == tail.len() - i
+            })
+            .ok_or(Error::Format("no end of central directory record"))?;
+        let mut fields = Fields(&tail[end + 4..]);
+        let disk = fields.u16()?;
+        let _cd_disk = fields.u16()?;
+        let _disk_entries = fields.u16()?;
+        let mut count = u64::from(fields.u16()?);
+        let mut cd_size = u64::from(fields.u32()?);
+        let mut cd_offset = u64::from(fields.u32()?);
+        let comment_len = fields.u16()?;
+        let comment = fields.take(comment_len.into())?.to_vec();
+        if disk != 0 {
+            return Err(Error::Unsupported("split archive"));
+        }
+        let end_offset = len - tail_len + end as u64;
+        if count == 0xffff || cd_size == MAX32 || cd_offset == MAX32 {
+            let mut locator = [0; 20];
+            let locator_offset = end_offset
+                .checked_sub(20)
+                .ok_or(Error::Format("missing zip64 locator"))?;
+            file.seek(SeekFrom::Start(locator_offset))?;
+            file.read_exact(&mut locator)?;
+            let mut fields = Fields(&locator);
+            if fields.u32()? == ZIP64_LOCATOR {
+                let _disk = fields.u32()?;
+                let zip64_end = fields.u64()?;
+                let mut record = [0; 56];
+                file.seek(SeekFrom::Start(zip64_end))?;
+                file.read_exact(&mut record)?;
+                let mut fields = Fields(&record);
+                if fields.u32()? != ZIP64_END {
+                    return Err(Error::Format("bad zip64 end record"));
+                }
+                // Skip to the total number of entries.
+                fields.take(28)?;
+                count = fields.u64()?;
+                cd_size = fields.u64()?;
+                cd_offset = fields.u64()?;
+            }
+        }
+        let mut directory = Vec::new();
+        file.seek(SeekFrom::Start(cd_offset))?;
+        (&mut file).take(cd_size).read_to_end(&mut directory)?;
+        let mut fields = Fields(&directory);
+        let entries = (0..count)
+            .map(|_| Entry::parse(&mut fields))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            file,
+            entries,
+            comment,
+        })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Returns the offset of the data of entry `i`, and the extra fields of
+    /// its local header (without the zip64 field).
+    fn local(&mut self, i: usize) -> Result<(u64, Vec<u8>), Error> {
+        let offset = self.entries[i].offset;
+        let mut header = [0; 30];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut header)?;
+        let mut fields = Fields(&header);
+        if fields.u32()? != LOCAL_HEADER {
+            return Err(Error::Format("bad local header"));
+        }
+        fields.take(22)?;
+        let name_len = u64::from(fields.u16()?);
+        let extra_len = fields.u16()?;
+        self.file.seek(SeekFrom::Current(name_len as i64))?;
+        let mut extra = vec![0; extra_len.into()];
+        self.file.read_exact(&mut extra)?;
+        let data = offset + 30 + name_len + u64::from(extra_len);
+        Ok((data, without_zip64(&extra)))
+    }
+
+    /// Reads and decompresses entry `i`.
+    pub fn read(&mut self, i: usize) -> Result<Vec<u8>, Error> {
+        let (start, _) = self.local(i)?;
+        let entry = &self.entries[i];
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(Error::Unsupported("encrypted entry"));
+        }
+        let mut raw = Vec::new();
+        self.file.seek(SeekFrom::Start(start))?;
+        (&mut self.file).take(entry.compressed_size).read_to_end(&mut raw)?;
+        let data = match entry.method {
+            METHOD_STORED => raw,
+            METHOD_DEFLATE => {
+                let mut data = Vec::new();
+                flate2::read::DeflateDecoder::new(&raw[..])
+                    .read_to_end(&mut data)?;
+                data
+            }
+            _ => return Err(Error::Unsupported("compression method")),
+        };
+        let mut crc = flate2::Crc::new();
+        crc.update(&data);
+        if crc.sum() != entry.crc {
+            return Err(Error::Format("checksum mismatch"));
+        }
+        Ok(data)
+    }
+
+    /// Writes a copy of the archive to `writer`, with the contents of each
+    /// entry `i` in `replaced` replaced by `replaced[i]`.
+    pub fn write_to<W: Write>(
+        &mut self,
+        writer: W,
+        replaced: &HashMap<usize, Vec<u8>>,
+    ) -> Result<(), Error> {
+        self.write_at(writer, replaced, 0)
+    }
+
+    /// Like [`Self::write_to`], but for a copy that starts `start` bytes
+    /// into the file, as if appended to other data.
+    fn write_at<W: Write>(
+        &mut self,
+        writer: W,
+        replaced: &HashMap<usize, Vec<u8>>,
+        start: u64,
+    ) -> Result<(), Error> {
+        let mut out = Counted {
+            inner: writer,
+            count: start,
+        };
+        let mut directory = Vec::new();
+        for i in 0..self.entries.len() {
+            let offset = out.count;
+            let (start, local_extra) = self.local(i)?;
+            let replacement = match replaced.get(&i) {
+                Some(contents) => Some(Replacement::new(contents)?),
+                None => None,
+            };
+            let entry = &self.entries[i];
+            let (flags, method, needed, crc, compressed_size, size) =
+                match &replacement {
+                    Some(r) => (
+                        entry.flags & FLAG_UTF8,
+                        METHOD_DEFLATE,
+                        VERSION_DEFLATE,
+                        r.crc,
+                        r.data.len() as u64,
+                        r.size,
+                    ),
+                    None => (
+                        entry.flags,
+                        entry.method,
+                        entry.needed,
+                        entry.crc,
+                        entry.compressed_size,
+                        entry.size,
+                    ),
+                };
+            let zip64 = size >= MAX32 || compressed_size >= MAX32;
+            let descriptor = flags & FLAG_DATA_DESCRIPTOR != 0;
+            let needed = if zip64 || offset >= MAX32 {
+                needed.max(VERSION_ZIP64)
+            } else {
+                needed
+            };
+
+            let mut header = Vec::new();
+            header.put32(LOCAL_HEADER);
+            header.put16(needed);
+            header.put16(flags);
+            header.put16(method);
+            header.put16(entry.time);
+            header.put16(entry.date);
+            let mut extra = Vec::new();
+            if descriptor {
+                // The checksum and sizes follow the data instead. The zip64
+                // field is still needed to mark them as 64-bit.
+                header.put32(0);
+                let sizes = if zip64 {
+                    MAX32
+                } else {
+                    0
+                };
+                header.put32_or_max(sizes);
+                header.put32_or_max(sizes);
+                if zip64 {
+                    extra.put16(ZIP64_EXTRA);
+                    extra.put16(16);
+                    extra.put64(0);
+                    extra.put64(0);
+                }
+            } else {
+                header.put32(crc);
+                header.put32_or_max(compressed_size);
+                header.put32_or_max(size);
+                if zip64 {
+                    extra.put16(ZIP64_EXTRA);
+                    extra.put16(16);
+                    extra.put64(size);
+                    extra.put64(compressed_size);
+                }
+            }
+            extra.extend(&local_extra);
+            header.put16(entry.name.len() as u16);
+            header.put16(extra.len() as u16);
+            header.extend(&entry.name);
+            header.extend(&extra);
+            out.write_all(&header)?;
+
+            match &replacement {
+                Some(r) => out.write_all(&r.data)?,
+                None => {
+                    self.file.seek(SeekFrom::Start(start))?;
+                    let mut data = (&mut self.file).take(compressed_size);
+                    if io::copy(&mut data, &mut out)? != compressed_size {
+                        return Err(Error::Format("truncated entry"));
+                    }
+                }
+            }
+            if descriptor {
+                let mut record = Vec::new();
+                record.put32(DATA_DESCRIPTOR);
+                record.put32(crc);
+                if zip64 {
+                    record.put64(compressed_size);
+                    record.put64(size);
+                } else {
+                    record.put32(compressed_size as u32);
+                    record.put32(size as u32);
+                }
+                out.write_all(&record)?;
+            }
+
+            let mut zip64_extra = Vec::new();
+            for value in [size, compressed_size, offset] {
+                if value >= MAX32 {
+                    zip64_extra.put64(value);
+                }
+            }
+            let mut extra = Vec::new();
+            if !zip64_extra.is_empty() {
+                extra.put16(ZIP64_EXTRA);
+                extra.put16(zip64_extra.len() as u16);
+                extra.extend(zip64_extra);
+            }
+            extra.extend(&entry.extra);
+            directory.put32(CENTRAL_HEADER);
+            directory.put16(entry.made_by);
+            directory.put16(needed);
+            directory.put16(flags);
+            directory.put16(method);
+            directory.put16(entry.time);
+            directory.put16(entry.date);
+            directory.put32(crc);
+            directory.put32_or_max(compressed_size);
+            directory.put32_or_max(size);
+            directory.put16(entry.name.len() as u16);
+            directory.put16(extra.len() as u16);
+            directory.put16(entry.comment.len() as u16);
+            directory.put16(0);
+            directory.put16(entry.internal_attributes);
+            directory.put32(entry.external_attributes);
+            directory.put32_or_max(offset);
+            directory.extend(&entry.name);
+            directory.extend(&extra);
+            directory.extend(&entry.comment);
+        }
+
+        let count = self.entries.len() as u64;
+        let cd_offset = out.count;
+        let cd_size = directory.len() as u64;
+        out.write_all(&directory)?;
+        let mut end = Vec::new();
+        if count >= 0xffff || cd_offset >= MAX32 || cd_size >= MAX32 {
+            let zip64_end = out.count;
+            end.put32(ZIP64_END);
+            end.put64(44);
+            end.put16(VERSION_ZIP64);
+            end.put16(VERSION_ZIP64);
+            end.put32(0);
+            end.put32(0);
+            end.put64(count);
+            end.put64(count);
+            end.put64(cd_size);
+            end.put64(cd_offset);
+            end.put32(ZIP64_LOCATOR);
+            end.put32(0);
+            end.put64(zip64_end);
+            end.put32(1);
+        }
+        end.put32(END);
+        end.put16(0);
+        end.put16(0);
+        end.put16(count.min(0xffff) as u16);
+        end.put16(count.min(0xffff) as u16);
+        end.put32_or_max(cd_size);
+        end.put32_or_max(cd_offset);
+        end.put16(self.comment.len() as u16);
+        end.extend(&self.comment);
+        out.write_all(&end)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// An entry of a test archive.
+    struct Source<'a> {
+        name: &'a str,
+        contents: &'a [u8],
+        deflate: bool,
+        /// Whether the checksum and sizes follow the data.
+        descriptor: bool,
+    }
+
+    const SOURCES: [Source<'static>; 3] = [
+        Source {
+            name: "S/S.ardour",
+            contents: b"<Session/>",
+            deflate: false,
+            descriptor: false,
+        },
+        Source {
+            name: "S/interchange/a.wav",
+            contents: b"RIFF audio audio audio audio audio",
+            deflate: true,
+            descriptor: false,
+        },
+        Source {
+            name: "S/S.history",
+            contents: b"<UndoHistory/>",
+            deflate: true,
+            descriptor: true,
+        },
+    ];
+
+    /// Builds an archive of `sources`.
+    fn build(sources: &[Source<'_>]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for source in sources {
+            let offset = out.len() as u32;
+            let (method, data) = if source.deflate {
+                let data = Replacement::new(source.contents).unwrap().data;
+                (METHOD_DEFLATE, data)
+            } else {
+                (METHOD_STORED, source.contents.to_vec())
+            };
+            let flags = if source.descriptor {
+                FLAG_DATA_DESCRIPTOR
+            } else {
+                0
+            };
+            let mut crc = flate2::Crc::new();
+            crc.update(source.contents);
+            let sizes = [data.len() as u32, source.contents.len() as u32];
+            let name = source.name.as_bytes();
+            out.put32(LOCAL_HEADER);
+            out.put16(VERSION_DEFLATE);
+            out.put16(flags);
+            out.put16(method);
+            out.put32(0);
+            for field in [crc.sum(), sizes[0], sizes[1]] {
+                out.put32(if source.descriptor {
+                    0
+                } else {
+                    field
+                });
+            }
+            out.put16(name.len() as u16);
+            out.put16(0);
+            out.extend(name);
+            out.extend(&data);
+            if source.descriptor {
+                out.put32(DATA_DESCRIPTOR);
+                for field in [crc.sum(), sizes[0], sizes[1]] {
+                    out.put32(field);
+                }
+            }
+            directory.put32(CENTRAL_HEADER);
+            directory.put16(VERSION_DEFLATE);
+            directory.put16(VERSION_DEFLATE);
+            directory.put16(flags);
+            directory.put16(method);
+            directory.put32(0);
+            for field in [crc.sum(), sizes[0], sizes[1]] {
+                directory.put32(field);
+            }
+            directory.put16(name.len() as u16);
+            directory.extend([0; 12]);
+            directory.put32(offset);
+            directory.extend(name);
+        }
+        let cd_offset = out.len() as u32;
+        out.extend(&directory);
+        out.put32(END);
+        out.put32(0);
+        out.put16(sources.len() as u16);
+        out.put16(sources.len() as u16);
+        out.put32(directory.len() as u32);
+        out.put32(cd_offset);
+        out.put16(0);
+        out
+    }
+
+    /// A file that is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!(
+                "fix-ardour-lv2-index-zip-{}-{name}",
+                std::process::id(),
+            )))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Returns the data of entry `i` as stored in the archive.
+    fn raw(archive: &mut Archive, i: usize) -> Vec<u8> {
+        let (start, _) = archive.local(i).unwrap();
+        let mut raw = Vec::new();
+        archive.file.seek(SeekFrom::Start(start)).unwrap();
+        let size = archive.entries[i].compressed_size;
+        (&mut archive.file).take(size).read_to_end(&mut raw).unwrap();
+        raw
+    }
+
+    /// Replaces the session in the archive in `original`, writing the copy
+    /// to `copy` `start` bytes into the file, and checks the copy.
+    fn round_trip(original: &TempFile, copy: &TempFile, start: u64) {
+        std::fs::write(&original.0, build(&SOURCES)).unwrap();
+        let mut archive = Archive::open(&original.0).unwrap();
+        let names: Vec<_> =
+            archive.entries().iter().map(Entry::name).collect();
+        assert_eq!(names, SOURCES.map(|s| s.name));
+        for (i, source) in SOURCES.iter().enumerate() {
+            assert_eq!(archive.read(i).unwrap(), source.contents);
+        }
+        let contents = b"<Session fixed=\"1\"/>".to_vec();
+        let replaced = HashMap::from([(0, contents.clone())]);
+        let mut file = File::create(&copy.0).unwrap();
+        file.set_len(start).unwrap();
+        file.seek(SeekFrom::Start(start)).unwrap();
+        archive.write_at(&mut file, &replaced, start).unwrap();
+        drop(file);
+
+        let mut repacked = Archive::open(&copy.0).unwrap();
+        assert_eq!(repacked.entries().len(), SOURCES.len());
+        assert_eq!(repacked.entries()[0].method, METHOD_DEFLATE);
+        assert_eq!(repacked.read(0).unwrap(), contents);
+        for (i, source) in SOURCES.iter().enumerate().skip(1) {
+            assert_eq!(repacked.entries()[i].name(), source.name);
+            assert_eq!(
+                repacked.entries()[i].flags,
+                archive.entries()[i].flags
+            );
+            assert_eq!(repacked.read(i).unwrap(), source.contents);
+            assert_eq!(raw(&mut repacked, i), raw(&mut archive, i));
+        }
+    }
+
+    #[test]
+    fn repack() {
+        let original = TempFile::new("repack.zip");
+        let copy = TempFile::new("repack-copy.zip");
+        round_trip(&original, &copy, 0);
+        let copy = std::fs::read(&copy.0).unwrap();
+        // No zip64 records are needed.
+        assert!(!copy.windows(4).any(|w| w == ZIP64_END.to_le_bytes()));
+    }
+
+    #[test]
+    fn repack_zip64() {
+        let original = TempFile::new("repack-zip64.zip");
+        let copy = TempFile::new("repack-zip64-copy.zip");
+        // The copy starts 4 GiB into a sparse file, so the entries' offsets
+        // need zip64 fields without 4 GiB being written.
+        round_trip(&original, &copy, MAX32);
+        let repacked = Archive::open(&copy.0).unwrap();
+        assert!(repacked.entries().iter().all(|e| e.offset >= MAX32));
+        assert!(repacked.entries().iter().all(|e| e.needed == VERSION_ZIP64));
+    }
+}
@@ -0,0 +1,623 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tests of the command-line program, run on copies of the sessions in
+//! `fixtures/` with port metadata from `fixtures/ports.json`.
+
+use fix_ardour_lv2_index::json::{self, Value};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixture(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name].iter().collect()
+}
+
+/// A directory that is removed when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "fix-ardour-lv2-index-test-{}-{name}",
+            std::process::id(),
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Copies the fixture `fixture` into the directory as `name`.
+    fn add(&self, fixture_name: &str, name: &str) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::copy(fixture(fixture_name), &path).unwrap();
+        path
+    }
+
+    /// The names of the files in the directory, sorted.
+    fn files(&self) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(&self.0)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name != "home")
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs the program in `dir` with `args`, isolated from the user's
+/// configuration, cache, and plugins.
+fn run<I>(dir: &TempDir, args: I) -> Output
+where
+    I: IntoIterator,
+    I::Item: AsRef<OsStr>,
+{
+    let home = dir.path().join("home");
+    Command::new(env!("CARGO_BIN_EXE_fix-ardour-lv2-index"))
+        .args(args)
+        .current_dir(dir.path())
+        .env("HOME", &home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_CACHE_HOME", home.join("cache"))
+        .env("LV2_PATH", home.join("lv2"))
+        .env("NO_COLOR", "1")
+        .env_remove("FIX_ARDOUR_LV2_INDEX_OPTS")
+        .output()
+        .unwrap()
+}
+
+/// Runs the program with `args`, fixing sessions with `fixtures/ports.json`,
+/// and checks that it succeeded (with exit status 0, or 1 if indices were
+/// changed).
+fn fix<I>(dir: &TempDir, args: I) -> Output
+where
+    I: IntoIterator,
+    I::Item: AsRef<OsStr>,
+{
+    let ports = fixture("ports.json");
+    let mut all = vec![
+        "--port-map".as_ref(),
+        ports.as_os_str(),
+        "--settle-time=0".as_ref(),
+    ];
+    let args: Vec<_> = args.into_iter().collect();
+    all.extend(args.iter().map(AsRef::as_ref));
+    let output = run(dir, all);
+    assert!(
+        matches!(output.status.code(), Some(0 | 1)),
+        "{}",
+        String::from_utf8_lossy(&output.stderr),
+    );
+    output
+}
+
+/// Whether the session at `path` has been fixed.
+fn is_fixed(path: &Path) -> bool {
+    let xml = std::fs::read_to_string(path).unwrap();
+    match (xml.contains("parameter=\"2\""), xml.contains("parameter=\"3\"")) {
+        (true, false) => true,
+        (false, true) => false,
+        _ => panic!("unexpected contents in {}", path.display()),
+    }
+}
+
+#[test]
+fn directory_skips_backups() {
+    let dir = TempDir::new("directory-skips-backups");
+    let session = dir.add("session.ardour", "session.ardour");
+    dir.add("session.ardour", "session.ardour.orig");
+    dir.add("session.ardour", "session.ardour.orig1");
+    fix(&dir, ["."]);
+    assert!(is_fixed(&session));
+    assert!(!is_fixed(&dir.path().join("session.ardour.orig")));
+    assert!(!is_fixed(&dir.path().join("session.ardour.orig1")));
+    assert_eq!(dir.files(), [
+        "session.ardour",
+        "session.ardour.orig",
+        "session.ardour.orig1",
+        "session.ardour.orig2",
+    ]);
+}
+
+#[test]
+fn directory_skips_backups_with_suffix() {
+    let dir = TempDir::new("directory-skips-backups-with-suffix");
+    let session = dir.add("session.ardour", "session.ardour");
+    // Named like a snapshot, but a backup given the suffix.
+    let backup = dir.add("session.ardour", "session.ardour-old.ardour");
+    fix(&dir, [".", "--backup-suffix=-old.ardour"]);
+    assert!(is_fixed(&session));
+    assert!(!is_fixed(&backup));
+    assert_eq!(dir.files(), [
+        "session.ardour",
+        "session.ardour-old.ardour",
+        "session.ardour-old.ardour1",
+    ]);
+}
+
+#[test]
+fn directory_includes_backups() {
+    let dir = TempDir::new("directory-includes-backups");
+    let session = dir.add("session.ardour", "session.ardour");
+    let backup = dir.add("session.ardour", "session.ardour.orig");
+    fix(&dir, [".", "--include-backups"]);
+    assert!(is_fixed(&session));
+    assert!(is_fixed(&backup));
+}
+
+#[test]
+fn backup_refused() {
+    let dir = TempDir::new("backup-refused");
+    let backup = dir.add("session.ardour", "session.ardour.orig1");
+    let output = run(&dir, ["session.ardour.orig1"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("looks like a backup"), "{stderr}");
+    assert!(!is_fixed(&backup));
+    fix(&dir, ["session.ardour.orig1", "--include-backups"]);
+    assert!(is_fixed(&backup));
+}
+
+/// The keys of each message printed by `patch-string`, in order.
+const MESSAGE_KEYS: [&str; 12] = [
+    "severity",
+    "code",
+    "message",
+    "uri",
+    "symbol",
+    "processor_name",
+    "processor_id",
+    "route",
+    "hint",
+    "line",
+    "column",
+    "offset",
+];
+
+/// Runs `patch-string` on `xml`, returning its output and the messages it
+/// printed to standard error, which must be only a JSON array.
+fn patch_string(dir: &TempDir, xml: &str) -> (Output, Vec<Value>) {
+    let output = run(dir, ["patch-string", xml]);
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    let value = json::parse(stderr)
+        .unwrap_or_else(|e| panic!("invalid json ({e}): {stderr}"));
+    let Value::Array(messages) = value else {
+        panic!("not an array: {stderr}");
+    };
+    for message in &messages {
+        let keys: Vec<_> =
+            message.as_object().unwrap().iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, MESSAGE_KEYS);
+    }
+    (output, messages)
+}
+
+#[test]
+fn patch_string_warnings() {
+    let dir = TempDir::new("patch-string-warnings");
+    let xml = std::fs::read_to_string(fixture("session.ardour")).unwrap();
+    let (output, messages) = patch_string(&dir, &xml);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, xml.as_bytes());
+    let [warning] = &messages[..] else {
+        panic!("expected one message: {messages:?}");
+    };
+    let field = |key| warning.get(key).unwrap();
+    assert_eq!(field("severity").as_str(), Some("warning"));
+    assert_eq!(field("code").as_str(), Some("missing-plugin"));
+    assert_eq!(field("uri").as_str(), Some("http://example.org/amp"));
+    assert_eq!(field("processor_id").as_str(), Some("30"));
+    assert_eq!(field("route").as_str(), Some("Audio 1"));
+    assert_eq!(field("line").as_u32(), Some(5));
+    assert_eq!(field("column").as_u32(), Some(7));
+    let offset = field("offset").as_u32().unwrap() as usize;
+    assert!(xml[offset..].starts_with("<Processor"));
+}
+
+#[test]
+fn patch_string_errors() {
+    let dir = TempDir::new("patch-string-errors");
+    let (output, messages) = patch_string(&dir, "<Session");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let [error] = &messages[..] else {
+        panic!("expected one message: {messages:?}");
+    };
+    assert_eq!(error.get("severity").unwrap().as_str(), Some("error"));
+    assert_eq!(error.get("code"), Some(&Value::Null));
+    let message = error.get("message").unwrap().as_str().unwrap();
+    assert!(message.starts_with("could not parse session"), "{message}");
+}
+
+/// Creates `dir/real/session.ardour` and a symlink to it, `dir/link.ardour`,
+/// returning the paths of both.
+#[cfg(unix)]
+fn linked_session(dir: &TempDir) -> (PathBuf, PathBuf) {
+    std::fs::create_dir(dir.path().join("real")).unwrap();
+    let target = dir.add("session.ardour", "real/session.ardour");
+    let link = dir.path().join("link.ardour");
+    std::os::unix::fs::symlink("real/session.ardour", &link).unwrap();
+    (target, link)
+}
+
+#[cfg(unix)]
+fn is_symlink(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).unwrap().file_type().is_symlink()
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_fixes_target() {
+    let dir = TempDir::new("symlink-fixes-target");
+    let (target, link) = linked_session(&dir);
+    fix(&dir, ["link.ardour"]);
+    assert!(is_symlink(&link));
+    assert!(is_fixed(&target));
+    // The backup is of the target, next to it, not of the link.
+    assert_eq!(dir.files(), ["link.ardour", "real"]);
+    let backup = dir.path().join("real/session.ardour.orig");
+    assert!(!is_fixed(&backup));
+    assert!(!is_symlink(&backup));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_restores_target() {
+    let dir = TempDir::new("symlink-restores-target");
+    let (target, link) = linked_session(&dir);
+    fix(&dir, ["link.ardour"]);
+    restore(&dir, ["link.ardour"]);
+    assert!(is_symlink(&link));
+    assert!(!is_fixed(&target));
+    assert!(!dir.path().join("real/session.ardour.orig").exists());
+}
+
+#[test]
+fn numbered_backups_pruned() {
+    let dir = TempDir::new("numbered-backups-pruned");
+    let session = dir.add("session.ardour", "session.ardour");
+    for _ in 0..3 {
+        fix(&dir, ["session.ardour", "--keep-backups=2"]);
+    }
+    assert!(is_fixed(&session));
+    assert_eq!(dir.files(), [
+        "session.ardour",
+        "session.ardour.orig1",
+        "session.ardour.orig2",
+    ]);
+    assert!(is_fixed(&dir.path().join("session.ardour.orig1")));
+}
+
+#[test]
+fn timestamped_backups_pruned() {
+    let dir = TempDir::new("timestamped-backups-pruned");
+    let session = dir.add("session.ardour", "session.ardour");
+    for _ in 0..2 {
+        fix(&dir, [
+            "session.ardour",
+            "--backup=timestamp",
+            "--keep-backups=1",
+        ]);
+    }
+    assert!(is_fixed(&session));
+    let files = dir.files();
+    let [_, backup] = &files[..] else {
+        panic!("expected one backup: {files:?}");
+    };
+    assert!(backup.starts_with("session.ardour.orig-"), "{backup}");
+    assert!(is_fixed(&dir.path().join(backup)));
+}
+
+/// Runs `restore` with `args`, checking that it succeeded.
+fn restore<const N: usize>(dir: &TempDir, args: [&str; N]) {
+    let output = run(dir, ["restore"].into_iter().chain(args));
+    assert_eq!(
+        output.status.code(),
+        Some(0),
+        "{}",
+        String::from_utf8_lossy(&output.stderr),
+    );
+}
+
+#[test]
+fn restore_newest() {
+    let dir = TempDir::new("restore-newest");
+    let session = dir.add("session.ardour", "session.ardour");
+    fix(&dir, ["session.ardour"]);
+    fix(&dir, ["session.ardour"]);
+    restore(&dir, ["session.ardour"]);
+    // The newest backup was made of the already fixed session.
+    assert!(is_fixed(&session));
+    assert_eq!(dir.files(), ["session.ardour", "session.ardour.orig"]);
+    restore(&dir, ["session.ardour"]);
+    assert!(!is_fixed(&session));
+    assert_eq!(dir.files(), ["session.ardour"]);
+}
+
+#[test]
+fn restore_keep_patched() {
+    let dir = TempDir::new("restore-keep-patched");
+    let session = dir.add("session.ardour", "session.ardour");
+    fix(&dir, ["session.ardour"]);
+    restore(&dir, ["session.ardour", "--keep-patched", "patched.ardour"]);
+    assert!(!is_fixed(&session));
+    assert!(is_fixed(&dir.path().join("patched.ardour")));
+    assert_eq!(dir.files(), ["patched.ardour", "session.ardour"]);
+}
+
+#[test]
+fn open_session_refused() {
+    use std::time::{Duration, SystemTime};
+    let dir = TempDir::new("open-session-refused");
+    let session = dir.add("session.ardour", "session.ardour");
+    let lock = dir.path().join("session.lock");
+    let set_modified = |path: &Path, time| {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(time).unwrap();
+    };
+    let ports = fixture("ports.json");
+    let args =
+        ["--port-map".as_ref(), ports.as_os_str(), "session.ardour".as_ref()];
+    let now = SystemTime::now();
+    std::fs::write(&lock, "").unwrap();
+    for (lock_age, message) in
+        [(60, "Ardour has saved it"), (0, "may have been left behind")]
+    {
+        set_modified(&lock, now - Duration::from_secs(lock_age));
+        set_modified(&session, now - Duration::from_secs(30));
+        let output = run(&dir, args);
+        assert_eq!(output.status.code(), Some(2));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("is open in Ardour"), "{stderr}");
+        assert!(stderr.contains(message), "{stderr}");
+        assert!(!is_fixed(&session));
+    }
+    let output = run(&dir, [&args[..], &["--force".as_ref()]].concat());
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!is_fixed(&session));
+    fix(&dir, ["session.ardour", "--ignore-lock"]);
+    assert!(is_fixed(&session));
+}
+
+/// Runs `preflight` on `session` in `dir`, returning its exit status and
+/// the line of JSON it printed.
+fn preflight(dir: &TempDir, session: &str) -> (Option<i32>, Value) {
+    let output = run(dir, ["preflight", session]);
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1, "{stdout}");
+    let value = json::parse(stdout)
+        .unwrap_or_else(|e| panic!("invalid json ({e}): {stdout}"));
+    (output.status.code(), value)
+}
+
+#[test]
+fn preflight_outcomes() {
+    use std::time::{Duration, SystemTime};
+    let dir = TempDir::new("preflight-outcomes");
+    let session = dir.add("session.ardour", "session.ardour");
+    let age = |path: &Path| {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(30))
+            .unwrap();
+    };
+    let ports = fixture("ports.json");
+    write_config(
+        &dir,
+        &format!(
+            "[defaults]\nport-map = \"{}\"\nbackup-suffix = \".bak\"\n",
+            ports.display(),
+        ),
+    );
+    let field = |value: &Value, key| value.get(key).unwrap().clone();
+
+    age(&session);
+    let (status, value) = preflight(&dir, "session.ardour");
+    assert_eq!(status, Some(0), "{value:?}");
+    assert_eq!(field(&value, "result").as_str(), Some("fixed"));
+    assert_eq!(field(&value, "changed").as_u32(), Some(1));
+    assert_eq!(field(&value, "missing_plugins").as_u32(), Some(0));
+    assert_eq!(field(&value, "message"), Value::Null);
+    assert!(is_fixed(&session));
+    assert_eq!(dir.files(), ["session.ardour", "session.ardour.bak"]);
+
+    let (status, value) = preflight(&dir, "session.ardour");
+    assert_eq!(status, Some(2));
+    assert_eq!(field(&value, "result").as_str(), Some("busy"));
+    let message = field(&value, "message");
+    assert!(message.as_str().unwrap().contains("was just modified"));
+
+    age(&session);
+    let (status, value) = preflight(&dir, "session.ardour");
+    assert_eq!(status, Some(0), "{value:?}");
+    assert_eq!(field(&value, "result").as_str(), Some("clean"));
+    assert_eq!(field(&value, "changed").as_u32(), Some(0));
+    assert_eq!(dir.files(), ["session.ardour", "session.ardour.bak"]);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn compressed_session_stays_compressed() {
+    use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+    use std::io::{Read, Write};
+    let dir = TempDir::new("compressed-session-stays-compressed");
+    let session = dir.path().join("session.ardour");
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&std::fs::read(fixture("session.ardour")).unwrap())
+        .unwrap();
+    std::fs::write(&session, encoder.finish().unwrap()).unwrap();
+    assert_eq!(fix(&dir, ["session.ardour"]).status.code(), Some(1));
+    let data = std::fs::read(&session).unwrap();
+    assert!(data.starts_with(&[0x1f, 0x8b]));
+    let mut xml = String::new();
+    GzDecoder::new(&data[..]).read_to_string(&mut xml).unwrap();
+    assert!(xml.contains("parameter=\"2\""));
+    assert!(!xml.contains("parameter=\"3\""));
+}
+
+/// Writes a zip archive of `entries`, each a name and its contents, stored
+/// uncompressed.
+#[cfg(feature = "zip")]
+fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+    let mut out = Vec::<u8>::new();
+    let mut directory = Vec::<u8>::new();
+    for (name, contents) in entries {
+        let mut crc = flate2::Crc::new();
+        crc.update(contents);
+        let offset = out.len() as u32;
+        let len = contents.len() as u32;
+        // The fields shared by the local and central headers: the version
+        // needed, flags, method, time, date, checksum, sizes, and name length.
+        let mut fields = vec![10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        for field in [crc.sum(), len, len] {
+            fields.extend(field.to_le_bytes());
+        }
+        fields.extend((name.len() as u16).to_le_bytes());
+        out.extend(0x04034b50_u32.to_le_bytes());
+        out.extend(&fields);
+        out.extend([0, 0]);
+        out.extend(name.as_bytes());
+        out.extend(*contents);
+        directory.extend(0x02014b50_u32.to_le_bytes());
+        directory.extend([10, 0]);
+        directory.extend(&fields);
+        directory.extend([0; 12]);
+        directory.extend(offset.to_le_bytes());
+        directory.extend(name.as_bytes());
+    }
+    let count = (entries.len() as u16).to_le_bytes();
+    let cd_offset = out.len() as u32;
+    out.extend(&directory);
+    out.extend(0x06054b50_u32.to_le_bytes());
+    out.extend([0, 0, 0, 0]);
+    out.extend(count);
+    out.extend(count);
+    out.extend((directory.len() as u32).to_le_bytes());
+    out.extend(cd_offset.to_le_bytes());
+    out.extend([0, 0]);
+    std::fs::write(path, out).unwrap();
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn archive_fixed() {
+    let dir = TempDir::new("archive-fixed");
+    let session = std::fs::read(fixture("session.ardour")).unwrap();
+    let audio = b"RIFF\0\0\0\0WAVEfmt audio that isn't compressed";
+    write_zip(&dir.path().join("session.zip"), &[
+        ("session/session.ardour", &session),
+        ("session/interchange/session/audiofiles/a.wav", audio),
+    ]);
+    assert_eq!(fix(&dir, ["session.zip"]).status.code(), Some(1));
+    assert_eq!(dir.files(), ["session.zip", "session.zip.orig"]);
+    let check = fix(&dir, ["--check", "session.zip"]);
+    assert_eq!(check.status.code(), Some(0));
+    // Entries that weren't replaced are copied as they were.
+    let archive = std::fs::read(dir.path().join("session.zip")).unwrap();
+    assert!(archive.windows(audio.len()).any(|w| w == audio));
+    // The backup is the original archive, which still needs fixing.
+    let args = ["--check", "--include-backups", "session.zip.orig"];
+    assert_eq!(fix(&dir, args).status.code(), Some(1));
+}
+
+/// Writes `contents` to the configuration file used by [`run`] in `dir`.
+fn write_config(dir: &TempDir, contents: &str) {
+    let config = dir.path().join("home/config/fix-ardour-lv2-index");
+    std::fs::create_dir_all(&config).unwrap();
+    std::fs::write(config.join("config.toml"), contents).unwrap();
+}
+
+#[test]
+fn config_defaults_overridden() {
+    let dir = TempDir::new("config-defaults-overridden");
+    let session = dir.add("session.ardour", "session.ardour");
+    write_config(
+        &dir,
+        "[defaults]\nindex-map = \"/nonexistent.json\"\ndry-run = true\n",
+    );
+    // `fix` gives `--port-map`, which replaces the default `--index-map`.
+    fix(&dir, ["session.ardour"]);
+    assert!(!is_fixed(&session));
+    fix(&dir, ["session.ardour", "--no-dry-run"]);
+    assert!(is_fixed(&session));
+}
+
+#[test]
+fn config_defaults_modes_refused() {
+    let dir = TempDir::new("config-defaults-modes-refused");
+    dir.add("session.ardour", "session.ardour");
+    write_config(&dir, "[defaults]\nversion = true\n");
+    let output = run(&dir, ["session.ardour"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--version cannot be used as a default option"),
+        "{stderr}",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn copy_keeps_inode() {
+    use std::os::unix::fs::MetadataExt;
+    let dir = TempDir::new("copy-keeps-inode");
+    let session = dir.add("session.ardour", "session.ardour");
+    let link = dir.path().join("hard-link");
+    std::fs::hard_link(&session, &link).unwrap();
+    let ino = std::fs::metadata(&session).unwrap().ino();
+    fix(&dir, ["session.ardour"]);
+    assert_eq!(std::fs::metadata(&session).unwrap().ino(), ino);
+    assert!(is_fixed(&session));
+    assert!(is_fixed(&link));
+    assert!(!is_fixed(&dir.path().join("session.ardour.orig")));
+}
+
+#[test]
+fn rename_uses_new_temporary_file() {
+    let dir = TempDir::new("rename-uses-new-temporary-file");
+    let session = dir.add("session.ardour", "session.ardour");
+    let tmp = dir.path().join("session.ardour.tmp");
+    std::fs::write(&tmp, "not ours").unwrap();
+    let ports = fixture("ports.json");
+    let args = [
+        "--port-map".as_ref(),
+        ports.as_os_str(),
+        "--backup-strategy=rename".as_ref(),
+        "session.ardour".as_ref(),
+    ];
+    let output = run(&dir, args);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(std::fs::read_to_string(&tmp).unwrap(), "not ours");
+    assert!(!is_fixed(&session));
+    std::fs::remove_file(&tmp).unwrap();
+    fix(&dir, ["session.ardour", "--backup-strategy=rename"]);
+    assert!(is_fixed(&session));
+    assert_eq!(dir.files(), ["session.ardour", "session.ardour.orig"]);
+}
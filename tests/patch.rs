@@ -0,0 +1,89 @@
+/*
+ * Copyright (C) 2024 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of fix-ardour-lv2-index.
+ *
+ * fix-ardour-lv2-index is free software: you can redistribute it and/or
+ * modify it under the terms of the GNU General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * fix-ardour-lv2-index is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with fix-ardour-lv2-index. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tests of fixing sessions with port metadata from the inventory
+//! `fixtures/ports.json`.
+
+use fix_ardour_lv2_index::inventory::Inventory;
+use fix_ardour_lv2_index::patch::{self, Owner, Resolver, Source};
+use fix_ardour_lv2_index::report::{self, FileReport, Report};
+use fix_ardour_lv2_index::session::OwnerKind;
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name].iter().collect()
+}
+
+fn read_fixture(name: &str) -> String {
+    std::fs::read_to_string(fixture(name)).unwrap()
+}
+
+fn resolver() -> Resolver {
+    let inventory = Inventory::load(fixture("ports.json")).unwrap();
+    Resolver::new(Source::PortMap(inventory))
+}
+
+#[test]
+fn region_effects() {
+    let xml = read_fixture("region-effects.ardour");
+    let mut resolver = resolver();
+    let patched =
+        patch::patch(&xml, &mut resolver, patch::Options::default()).unwrap();
+    let output = patched.to_string();
+    assert!(!output.contains("parameter=\"3\""));
+    assert!(!output.contains("parameter-3"));
+    assert_eq!(output.matches("parameter=\"2\"").count(), 2);
+    assert!(output.contains("automation-id=\"parameter-2\""));
+
+    let summary = patched.into_summary();
+    let owners: Vec<_> = summary
+        .changes
+        .iter()
+        .map(|c| (c.instance.id.as_deref(), c.owner.clone()))
+        .collect();
+    let route = Owner {
+        kind: OwnerKind::Route,
+        name: "Audio 1".to_owned(),
+        index: Some(1),
+    };
+    let region = Owner {
+        kind: OwnerKind::Region,
+        name: "Audio 1.1".to_owned(),
+        index: None,
+    };
+    assert_eq!(owners, [
+        (Some("30"), Some(route)),
+        (Some("60"), Some(region.clone())),
+        (Some("60"), Some(region)),
+    ]);
+
+    let mut report = Report::new();
+    report.files.push(FileReport {
+        path: "region-effects.ardour".to_owned(),
+        changes: summary.changes,
+        skipped: summary.skipped,
+        bypassed: summary.bypassed,
+        verified: summary.verified,
+        unchecked: summary.unchecked,
+        plugins: summary.plugins,
+        missing_plugins: summary.missing_plugins,
+    });
+    let text = report.render(report::Format::Text).to_string();
+    assert!(text.contains("(region effect) on region \"Audio 1.1\""));
+}